@@ -0,0 +1,90 @@
+use crate::record_writer::AlignmentRecord;
+use std::io::Write;
+
+/// The coordinates of an alignment on its read before and after decompression.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AlignmentMapping {
+    pub edge_index: u64,
+    pub alignment_index: u64,
+    pub read_id: Vec<u8>,
+    /// True for forwards (+), false for backwards (-).
+    pub direction: bool,
+    pub original_offset: usize,
+    pub original_length: usize,
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// The header line of the alignment map.
+pub const HEADER: &str = "contig\tedge_index\talignment_index\tread_id\tstrand\t\
+                          original_offset\toriginal_length\toffset\tlength";
+
+impl AlignmentRecord for AlignmentMapping {
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        contig_name: &str,
+        _contig_length: u64,
+    ) -> std::io::Result<()> {
+        write!(
+            writer,
+            "{contig_name}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.edge_index,
+            self.alignment_index,
+            String::from_utf8_lossy(&self.read_id),
+            if self.direction { '+' } else { '-' },
+            self.original_offset,
+            self.original_length,
+            self.offset,
+            self.length,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alignment_map::{AlignmentMapping, HEADER};
+    use crate::record_writer::AlignmentRecordWriter;
+
+    fn mapping(alignment_index: u64, read_id: &str, direction: bool) -> AlignmentMapping {
+        AlignmentMapping {
+            edge_index: 1,
+            alignment_index,
+            read_id: read_id.as_bytes().to_vec(),
+            direction,
+            original_offset: 10,
+            original_length: 20,
+            offset: 15,
+            length: 30,
+        }
+    }
+
+    #[test]
+    fn test_alignment_map() {
+        let path =
+            std::env::temp_dir().join(format!("alignment_map_test_{}.tsv", std::process::id()));
+        let mut writer = AlignmentRecordWriter::create(&path, 1024, Some(HEADER)).unwrap();
+        for (alignment_index, read_id) in ["read1", "read2", "read3"].iter().enumerate() {
+            writer.add_record(mapping(
+                alignment_index as u64,
+                read_id,
+                alignment_index != 1,
+            ));
+        }
+        // The second alignment is dropped.
+        writer.finish_contig("ctg1", 100, &[1]).unwrap();
+        writer.add_record(mapping(0, "read4", false));
+        writer.finish_contig("ctg2", 100, &[]).unwrap();
+        assert_eq!(writer.finish().unwrap(), 3);
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            output,
+            "contig\tedge_index\talignment_index\tread_id\tstrand\toriginal_offset\toriginal_length\toffset\tlength\n\
+             ctg1\t1\t0\tread1\t+\t10\t20\t15\t30\n\
+             ctg1\t1\t2\tread3\t+\t10\t20\t15\t30\n\
+             ctg2\t1\t0\tread4\t-\t10\t20\t15\t30\n"
+        );
+    }
+}
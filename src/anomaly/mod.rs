@@ -0,0 +1,282 @@
+use crate::error::{IoContext, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Contigs with fewer alignments are not checked, since their typical expansion ratio cannot be estimated robustly.
+const MIN_ALIGNMENT_COUNT: usize = 5;
+
+/// Scales the median absolute deviation such that it estimates the standard deviation of a normal distribution.
+const MAD_SCALE: f64 = 0.6745;
+
+/// Scales the mean absolute deviation such that it estimates the standard deviation of a normal distribution.
+/// It is used instead of the median absolute deviation if more than half of the ratios are equal.
+const MEAN_AD_SCALE: f64 = 0.7979;
+
+/// An alignment of a contig, as seen by the anomaly detector.
+#[derive(Clone, Debug)]
+pub struct AlignmentRatio {
+    pub edge_index: u64,
+    pub alignment_index: u64,
+    pub read_id: Vec<u8>,
+    pub direction: bool,
+    /// The length of the alignment in the input.
+    pub compressed_length: usize,
+    /// The length of the decompressed alignment.
+    pub decompressed_length: usize,
+}
+
+impl AlignmentRatio {
+    fn ratio(&self) -> f64 {
+        self.decompressed_length as f64 / self.compressed_length.max(1) as f64
+    }
+}
+
+/// An alignment whose expansion ratio is far from the typical expansion ratio of its contig.
+#[derive(Clone, Debug)]
+pub struct Anomaly {
+    /// The index of the alignment among all alignments of its contig.
+    pub ordinal: usize,
+    pub robust_z_score: f64,
+    /// Whether the alignment is removed from the output.
+    /// False if anomalies are only reported, and for the least anomalous alignment of an edge
+    /// whose alignments are all anomalous, which is kept such that the edge keeps an alignment.
+    pub dropped: bool,
+}
+
+/// Collects the expansion ratios of the alignments of a contig and flags outliers by their robust z-score.
+pub struct AnomalyDetector {
+    /// Alignments with an absolute robust z-score above this threshold are anomalies.
+    threshold: f64,
+    drop: bool,
+    contig_name: String,
+    alignments: Vec<AlignmentRatio>,
+    report: Option<(PathBuf, BufWriter<File>)>,
+    anomaly_count: u64,
+    dropped_count: u64,
+}
+
+impl AnomalyDetector {
+    pub fn new<P: AsRef<Path>>(threshold: f64, drop: bool, report_path: Option<P>) -> Result<Self> {
+        let report = if let Some(report_path) = report_path {
+            let report_path = report_path.as_ref();
+            let mut writer = BufWriter::new(
+                File::create(report_path)
+                    .io_context(|| format!("Could not create {report_path:?}"))?,
+            );
+            writeln!(
+                writer,
+                "contig\tedge\talignment\tread\tdirection\tcompressed_length\tdecompressed_length\t\
+                 ratio\tcontig_median_ratio\trobust_z_score\tdropped"
+            )
+            .io_context(|| format!("Could not write to {report_path:?}"))?;
+            Some((report_path.to_owned(), writer))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            threshold,
+            drop,
+            contig_name: String::new(),
+            alignments: Vec::new(),
+            report,
+            anomaly_count: 0,
+            dropped_count: 0,
+        })
+    }
+
+    pub fn start_contig(&mut self, name: &str) {
+        self.contig_name = name.to_owned();
+        self.alignments.clear();
+    }
+
+    pub fn add_alignment(&mut self, alignment: AlignmentRatio) {
+        self.alignments.push(alignment);
+    }
+
+    /// Detect the anomalies of the current contig and write them to the report.
+    /// Returns the anomalies in order of their ordinals.
+    pub fn finish_contig(&mut self) -> Result<Vec<Anomaly>> {
+        let ratios: Vec<_> = self.alignments.iter().map(AlignmentRatio::ratio).collect();
+        let (median, scores) = match robust_z_scores(&ratios) {
+            Some(scores) => scores,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut anomalies: Vec<_> = scores
+            .iter()
+            .enumerate()
+            .filter(|(_, score)| score.abs() > self.threshold)
+            .map(|(ordinal, &robust_z_score)| Anomaly {
+                ordinal,
+                robust_z_score,
+                dropped: self.drop,
+            })
+            .collect();
+
+        // Every edge keeps at least one alignment, since consensus needs reads for each edge.
+        if self.drop {
+            let mut anomalies = anomalies.iter_mut().peekable();
+            let mut first_alignment_of_edge = 0;
+            while first_alignment_of_edge < self.alignments.len() {
+                let edge_index = self.alignments[first_alignment_of_edge].edge_index;
+                let mut end = first_alignment_of_edge;
+                while end < self.alignments.len() && self.alignments[end].edge_index == edge_index {
+                    end += 1;
+                }
+
+                let mut edge_anomalies = Vec::new();
+                while let Some(anomaly) = anomalies.next_if(|anomaly| anomaly.ordinal < end) {
+                    edge_anomalies.push(anomaly);
+                }
+                if edge_anomalies.len() == end - first_alignment_of_edge {
+                    // Keep the least anomalous alignment.
+                    if let Some(kept) = edge_anomalies.into_iter().min_by(|a, b| {
+                        a.robust_z_score
+                            .abs()
+                            .partial_cmp(&b.robust_z_score.abs())
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    }) {
+                        kept.dropped = false;
+                    }
+                }
+                first_alignment_of_edge = end;
+            }
+        }
+
+        self.anomaly_count += anomalies.len() as u64;
+        self.dropped_count += anomalies.iter().filter(|anomaly| anomaly.dropped).count() as u64;
+        if let Some((report_path, writer)) = &mut self.report {
+            for anomaly in &anomalies {
+                let alignment = &self.alignments[anomaly.ordinal];
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.2}\t{}",
+                    self.contig_name,
+                    alignment.edge_index,
+                    alignment.alignment_index,
+                    String::from_utf8_lossy(&alignment.read_id),
+                    if alignment.direction { '+' } else { '-' },
+                    alignment.compressed_length,
+                    alignment.decompressed_length,
+                    alignment.ratio(),
+                    median,
+                    anomaly.robust_z_score,
+                    anomaly.dropped,
+                )
+                .io_context(|| format!("Could not write to {report_path:?}"))?;
+            }
+        }
+        Ok(anomalies)
+    }
+
+    /// Flush the report and return the total number of anomalies and dropped alignments.
+    pub fn finish(&mut self) -> Result<(u64, u64)> {
+        if let Some((report_path, writer)) = &mut self.report {
+            writer
+                .flush()
+                .io_context(|| format!("Could not write to {report_path:?}"))?;
+        }
+        Ok((self.anomaly_count, self.dropped_count))
+    }
+}
+
+/// Compute the median and the robust z-scores of the given values.
+/// Returns `None` if there are too few values or if they do not vary.
+pub fn robust_z_scores(values: &[f64]) -> Option<(f64, Vec<f64>)> {
+    if values.len() < MIN_ALIGNMENT_COUNT {
+        return None;
+    }
+
+    let median_value = median(values.to_vec());
+    let deviations: Vec<_> = values
+        .iter()
+        .map(|value| (value - median_value).abs())
+        .collect();
+    let median_absolute_deviation = median(deviations.clone());
+    let scale = if median_absolute_deviation > 0.0 {
+        MAD_SCALE / median_absolute_deviation
+    } else {
+        let mean_absolute_deviation = deviations.iter().sum::<f64>() / deviations.len() as f64;
+        if mean_absolute_deviation > 0.0 {
+            MEAN_AD_SCALE / mean_absolute_deviation
+        } else {
+            return None;
+        }
+    };
+
+    Some((
+        median_value,
+        values
+            .iter()
+            .map(|value| (value - median_value) * scale)
+            .collect(),
+    ))
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::anomaly::{robust_z_scores, AlignmentRatio, AnomalyDetector};
+    use std::path::Path;
+
+    #[test]
+    fn test_robust_z_scores() {
+        assert!(robust_z_scores(&[1.0, 2.0, 3.0]).is_none());
+        assert!(robust_z_scores(&[2.0; 6]).is_none());
+
+        let (median, scores) = robust_z_scores(&[2.0, 2.1, 1.9, 2.0, 2.2, 1.8, 6.0]).unwrap();
+        assert!((median - 2.0).abs() < 1e-9);
+        assert!(scores[..6].iter().all(|score| score.abs() < 3.5));
+        assert!(scores[6] > 3.5);
+
+        // More than half of the ratios are equal, so the mean absolute deviation is used.
+        let (_, scores) = robust_z_scores(&[2.0, 2.0, 2.0, 2.0, 2.0, 2.1, 6.0]).unwrap();
+        assert!(scores[5].abs() < 3.5);
+        assert!(scores[6] > 3.5);
+    }
+
+    #[test]
+    fn test_drop() {
+        let mut anomaly_detector = AnomalyDetector::new::<&Path>(3.5, true, None).unwrap();
+        anomaly_detector.start_contig("ctg1");
+        let edges: [&[usize]; 3] = [
+            // The last alignment is anomalous, but the others are not.
+            &[200, 210, 600],
+            &[190, 200, 220],
+            // All alignments are anomalous.
+            &[700, 650],
+        ];
+        for (edge_index, decompressed_lengths) in edges.iter().enumerate() {
+            for (alignment_index, &decompressed_length) in decompressed_lengths.iter().enumerate() {
+                anomaly_detector.add_alignment(AlignmentRatio {
+                    edge_index: edge_index as u64,
+                    alignment_index: alignment_index as u64,
+                    read_id: b"read".to_vec(),
+                    direction: true,
+                    compressed_length: 100,
+                    decompressed_length,
+                });
+            }
+        }
+
+        let anomalies = anomaly_detector.finish_contig().unwrap();
+        let anomalies: Vec<_> = anomalies
+            .iter()
+            .map(|anomaly| (anomaly.ordinal, anomaly.dropped))
+            .collect();
+        // Only the least anomalous alignment of the last edge is kept.
+        assert_eq!(anomalies, vec![(2, true), (6, true), (7, false)]);
+        assert_eq!(anomaly_detector.finish().unwrap(), (3, 2));
+    }
+}
@@ -0,0 +1,313 @@
+use crate::error::{Error, IoContext, Result};
+use crate::fingerprint::file_fingerprint;
+use crate::output::ShardState;
+use crate::wtdbg2_ctg_lay::Wtdbg2CtgLayLine;
+use std::fs;
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The state of the output after the last completely written contig.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Checkpoint {
+    /// The number of contigs that were completely written to the output.
+    pub completed_contigs: u64,
+    /// The name of the last contig that was completely written to the output.
+    pub last_contig_name: String,
+    /// The state of each output shard up to and including the last complete contig.
+    pub shards: Vec<ShardState>,
+    /// The byte offset of the contig line of the last contig that was completely written to the output.
+    pub last_contig_offset: u64,
+    /// The byte offset of the first contig line in the input that was not completely written yet.
+    pub input_offset: u64,
+    /// The location of the normal reads index.
+    pub index_path: PathBuf,
+    /// The fingerprint of the input file, see [file_fingerprint](crate::fingerprint::file_fingerprint).
+    pub input_fingerprint: String,
+    /// The fingerprint of the normal reads file.
+    pub reads_fingerprint: String,
+    /// The options of the run that affect the output.
+    pub options: String,
+}
+
+impl Checkpoint {
+    /// Load a checkpoint, or return `None` if there is none.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>> {
+        let path = path.as_ref();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error).io_context(|| format!("Could not open {path:?}")),
+        };
+
+        Self::read(BufReader::new(file), path).map(Some)
+    }
+
+    /// Parse a checkpoint. The path is only used in error messages.
+    fn read<R: BufRead>(reader: R, path: &Path) -> Result<Self> {
+        let mut completed_contigs = None;
+        let mut last_contig_name = None;
+        let mut shards = Vec::new();
+        let mut input_offset = None;
+        let mut index_path = None;
+        let mut last_contig_offset = None;
+        let mut input_fingerprint = None;
+        let mut reads_fingerprint = None;
+        let mut options = None;
+        for line in reader.lines() {
+            let line = line.io_context(|| format!("Could not read {path:?}"))?;
+            let (key, value) = line.split_once('=').ok_or_else(|| malformed_error(path))?;
+            match key {
+                "completed_contigs" => {
+                    completed_contigs = Some(value.parse().map_err(|_| malformed_error(path))?)
+                }
+                "last_contig_name" => last_contig_name = Some(value.to_owned()),
+                "shard" => {
+                    let mut columns = value
+                        .split(',')
+                        .map(|column| column.parse().map_err(|_| malformed_error(path)));
+                    let mut next_column =
+                        || columns.next().unwrap_or_else(|| Err(malformed_error(path)));
+                    shards.push(ShardState {
+                        offset: next_column()?,
+                        contig_count: next_column()?,
+                        base_count: next_column()?,
+                    });
+                }
+                "input_offset" => {
+                    input_offset = Some(value.parse().map_err(|_| malformed_error(path))?)
+                }
+                "last_contig_offset" => {
+                    last_contig_offset = Some(value.parse().map_err(|_| malformed_error(path))?)
+                }
+                "index_path" => index_path = Some(PathBuf::from(value)),
+                "input_fingerprint" => input_fingerprint = Some(value.to_owned()),
+                "reads_fingerprint" => reads_fingerprint = Some(value.to_owned()),
+                "options" => options = Some(value.to_owned()),
+                _ => return Err(malformed_error(path)),
+            }
+        }
+
+        Ok(Self {
+            completed_contigs: completed_contigs.ok_or_else(|| malformed_error(path))?,
+            last_contig_name: last_contig_name.ok_or_else(|| malformed_error(path))?,
+            shards,
+            last_contig_offset: last_contig_offset.ok_or_else(|| malformed_error(path))?,
+            input_offset: input_offset.ok_or_else(|| malformed_error(path))?,
+            index_path: index_path.ok_or_else(|| malformed_error(path))?,
+            input_fingerprint: input_fingerprint.ok_or_else(|| malformed_error(path))?,
+            reads_fingerprint: reads_fingerprint.ok_or_else(|| malformed_error(path))?,
+            options: options.ok_or_else(|| malformed_error(path))?,
+        })
+    }
+
+    /// Store the checkpoint atomically, such that an interruption never leaves a partial checkpoint behind.
+    pub fn store<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut file =
+            File::create(&tmp_path).io_context(|| format!("Could not create {tmp_path:?}"))?;
+        self.write(&mut file)
+            .and_then(|()| file.sync_all())
+            .io_context(|| format!("Could not write {tmp_path:?}"))?;
+        fs::rename(&tmp_path, path)
+            .io_context(|| format!("Could not rename {tmp_path:?} to {path:?}"))
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writeln!(writer, "completed_contigs={}", self.completed_contigs)?;
+        writeln!(writer, "last_contig_name={}", self.last_contig_name)?;
+        writeln!(writer, "last_contig_offset={}", self.last_contig_offset)?;
+        writeln!(writer, "input_offset={}", self.input_offset)?;
+        writeln!(writer, "index_path={}", self.index_path.to_string_lossy())?;
+        writeln!(writer, "input_fingerprint={}", self.input_fingerprint)?;
+        writeln!(writer, "reads_fingerprint={}", self.reads_fingerprint)?;
+        writeln!(writer, "options={}", self.options)?;
+        for shard in &self.shards {
+            writeln!(
+                writer,
+                "shard={},{},{}",
+                shard.offset, shard.contig_count, shard.base_count
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Check that the checkpoint belongs to a run on the same input and reads with the same options,
+    /// and that its input offsets still point to the expected contig lines.
+    pub fn verify(
+        &self,
+        input: &Path,
+        normal_reads: &Path,
+        options: &str,
+        io_buffer_size: usize,
+    ) -> Result<()> {
+        if self.input_fingerprint != file_fingerprint(input)? {
+            return Err(Error::Input(format!(
+                "The input {input:?} changed since the checkpoint was stored"
+            )));
+        }
+        if self.reads_fingerprint != file_fingerprint(normal_reads)? {
+            return Err(Error::Input(format!(
+                "The normal reads {normal_reads:?} changed since the checkpoint was stored"
+            )));
+        }
+        if self.options != options {
+            return Err(Error::Input(format!(
+                "The options changed since the checkpoint was stored, from {} to {options}",
+                self.options
+            )));
+        }
+
+        let mut reader = BufReader::with_capacity(
+            io_buffer_size,
+            File::open(input).io_context(|| format!("Could not open {input:?}"))?,
+        );
+        let last_contig_line = read_line_at(&mut reader, input, self.last_contig_offset)?;
+        if contig_name(&last_contig_line).as_deref() != Some(self.last_contig_name.as_str()) {
+            return Err(Error::Input(format!(
+                "The input {input:?} has no contig {} at byte {} where the checkpoint expects it",
+                self.last_contig_name, self.last_contig_offset
+            )));
+        }
+        // The input may end after the last completed contig, but otherwise the run resumes at a contig line.
+        let resume_line = read_line_at(&mut reader, input, self.input_offset)?;
+        if !resume_line.is_empty() && contig_name(&resume_line).is_none() {
+            return Err(Error::Input(format!(
+                "The input {input:?} has no contig line at byte {} where the checkpoint resumes",
+                self.input_offset
+            )));
+        }
+        Ok(())
+    }
+
+    /// Remove the checkpoint after a successful run.
+    pub fn remove<P: AsRef<Path>>(path: P) -> Result<()> {
+        let path = path.as_ref();
+        match fs::remove_file(path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => {
+                Err(error).io_context(|| format!("Could not remove {path:?}"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Read the line starting at the given offset without its line break, or an empty line at the end of the input.
+fn read_line_at<R: BufRead + Seek>(reader: &mut R, path: &Path, offset: u64) -> Result<String> {
+    let mut line = String::new();
+    reader
+        .seek(SeekFrom::Start(offset))
+        .and_then(|_| reader.read_line(&mut line))
+        .io_context(|| format!("Could not read {path:?}"))?;
+    Ok(line.trim_end_matches(&['\n', '\r'][..]).to_owned())
+}
+
+/// The name of the contig if the line is a contig line.
+fn contig_name(line: &str) -> Option<String> {
+    match Wtdbg2CtgLayLine::from_str(line) {
+        Ok(Wtdbg2CtgLayLine::Contig { name, .. }) => Some(name),
+        _ => None,
+    }
+}
+
+fn malformed_error(path: &Path) -> Error {
+    Error::Input(format!("Malformed checkpoint file {path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::Checkpoint;
+    use crate::fingerprint::file_fingerprint;
+    use crate::output::ShardState;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_round_trip() {
+        let checkpoint = Checkpoint {
+            completed_contigs: 2,
+            last_contig_name: "ctg2".to_string(),
+            shards: vec![
+                ShardState {
+                    offset: 120,
+                    contig_count: 1,
+                    base_count: 3000,
+                },
+                ShardState {
+                    offset: 80,
+                    contig_count: 1,
+                    base_count: 2000,
+                },
+            ],
+            last_contig_offset: 57,
+            input_offset: 98,
+            index_path: PathBuf::from("output.ctg.lay.normal_index"),
+            input_fingerprint: "# source=input.ctg.lay size=140 modified=1.000000000".to_string(),
+            reads_fingerprint: "# source=reads.fa size=9000 modified=2.000000000".to_string(),
+            options: "shards=2".to_string(),
+        };
+        let mut stored = Vec::new();
+        checkpoint.write(&mut stored).unwrap();
+        let loaded = Checkpoint::read(&stored[..], Path::new("checkpoint")).unwrap();
+        assert_eq!(loaded, checkpoint);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let path = Path::new("checkpoint");
+        // A line without a key.
+        assert!(Checkpoint::read(&b"completed_contigs\n"[..], path).is_err());
+        // A value that is not a number.
+        assert!(Checkpoint::read(&b"completed_contigs=two\n"[..], path).is_err());
+        // An unknown key.
+        assert!(Checkpoint::read(&b"contigs=2\n"[..], path).is_err());
+        // A missing key, as in a checkpoint of an older version.
+        assert!(Checkpoint::read(
+            &b"completed_contigs=2\nlast_contig_name=ctg2\ninput_offset=98\nindex_path=index\n"[..],
+            path
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let directory =
+            std::env::temp_dir().join(format!("checkpoint_test_{}_verify", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("input.ctg.lay");
+        let reads = directory.join("reads.fa");
+        fs::write(
+            &input,
+            ">ctg1 nodes=2 len=4\nE\t0\tN1\t+\tN2\t+\n>ctg2 nodes=2 len=4\n",
+        )
+        .unwrap();
+        fs::write(&reads, ">read1\nACGT\n").unwrap();
+        let checkpoint = Checkpoint {
+            completed_contigs: 1,
+            last_contig_name: "ctg1".to_string(),
+            shards: Vec::new(),
+            last_contig_offset: 0,
+            input_offset: 33,
+            index_path: directory.join("index"),
+            input_fingerprint: file_fingerprint(&input).unwrap(),
+            reads_fingerprint: file_fingerprint(&reads).unwrap(),
+            options: "shards=1".to_string(),
+        };
+        // A different spelling of the same paths identifies the same files.
+        let respelled_input = directory.join(".").join("input.ctg.lay");
+        checkpoint
+            .verify(&respelled_input, &reads, "shards=1", 16)
+            .unwrap();
+        assert!(checkpoint.verify(&input, &reads, "shards=2", 16).is_err());
+
+        // Changing the size of the reads changes their fingerprint.
+        fs::write(&reads, ">read1\nACGTA\n").unwrap();
+        assert!(checkpoint.verify(&input, &reads, "shards=1", 16).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
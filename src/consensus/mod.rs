@@ -0,0 +1,463 @@
+use crate::error::{Cancellation, Error, Result};
+use crossbeam::channel::Sender;
+
+/// The alignment score of two equal bases.
+const MATCH_SCORE: i32 = 2;
+/// The alignment score of two different bases.
+const MISMATCH_SCORE: i32 = -4;
+/// The alignment score of a base aligned to nothing.
+const GAP_SCORE: i32 = -4;
+
+/// A unit of work of the consensus stages, numbered in the order of the layout.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConsensusTask {
+    Contig {
+        name: String,
+    },
+    /// The read segments of an edge in the direction of the contig, or only their consensus once it was computed.
+    Edge {
+        offset: u64,
+        segments: Vec<Vec<u8>>,
+    },
+}
+
+impl ConsensusTask {
+    /// Replace the read segments of an edge with their consensus, which is empty if the edge has no segments.
+    /// Contigs are returned unchanged.
+    pub fn compute_consensus(self) -> Self {
+        match self {
+            ConsensusTask::Edge { offset, segments } => ConsensusTask::Edge {
+                offset,
+                segments: vec![poa_consensus(&segments)],
+            },
+            contig => contig,
+        }
+    }
+}
+
+/// Groups the read segments of the layout into numbered consensus tasks and sends them to the consensus stages.
+pub struct ConsensusDispatcher<'a> {
+    sender: Sender<(u64, ConsensusTask)>,
+    cancellation: &'a Cancellation,
+    sequence_number: u64,
+    edge: Option<(u64, Vec<Vec<u8>>)>,
+    finished: bool,
+}
+
+impl<'a> ConsensusDispatcher<'a> {
+    pub fn new(sender: Sender<(u64, ConsensusTask)>, cancellation: &'a Cancellation) -> Self {
+        Self {
+            sender,
+            cancellation,
+            sequence_number: 0,
+            edge: None,
+            finished: false,
+        }
+    }
+
+    pub fn start_contig(&mut self, name: &str) -> Result<()> {
+        self.send_edge()?;
+        self.send(ConsensusTask::Contig {
+            name: name.to_owned(),
+        })
+    }
+
+    /// Start an edge of the current contig at its offset in the output.
+    pub fn start_edge(&mut self, offset: u64) -> Result<()> {
+        self.send_edge()?;
+        self.edge = Some((offset, Vec::new()));
+        Ok(())
+    }
+
+    /// Add a read segment of the current edge, in the direction of the contig.
+    pub fn add_segment(&mut self, segment: Vec<u8>) -> Result<()> {
+        let (_, segments) = self.edge.as_mut().ok_or_else(|| {
+            Error::Consistency("Received a read segment outside of an edge".to_string())
+        })?;
+        segments.push(segment);
+        Ok(())
+    }
+
+    /// Send the last edge. The consensus stages terminate once the dispatcher is dropped.
+    pub fn finish(mut self) -> Result<()> {
+        self.send_edge()?;
+        self.finished = true;
+        Ok(())
+    }
+
+    fn send_edge(&mut self) -> Result<()> {
+        if let Some((offset, segments)) = self.edge.take() {
+            self.send(ConsensusTask::Edge { offset, segments })?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, task: ConsensusTask) -> Result<()> {
+        self.sender
+            .send((self.sequence_number, task))
+            .map_err(|_| Error::Cancelled)?;
+        self.sequence_number += 1;
+        Ok(())
+    }
+}
+
+impl Drop for ConsensusDispatcher<'_> {
+    /// A dispatcher that is dropped before it is finished belongs to a failed output writer.
+    /// Cancel the pipeline before the channel closes, such that the consensus stages
+    /// do not take the closed channel for the end of the layout and write a truncated consensus.
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cancellation.cancel();
+        }
+    }
+}
+
+/// Compute the consensus of the given sequences with a partial-order alignment.
+/// The sequences are added from the longest to the shortest, each aligned to the graph with free end gaps in the graph,
+/// and the consensus is the heaviest path through the graph, weighted by the number of sequences using each edge.
+pub fn poa_consensus<Sequence: AsRef<[u8]>>(sequences: &[Sequence]) -> Vec<u8> {
+    let mut sequences: Vec<_> = sequences.iter().map(AsRef::as_ref).collect();
+    sequences.sort_by_key(|sequence| std::cmp::Reverse(sequence.len()));
+    let mut graph = PoaGraph::default();
+    for sequence in sequences {
+        graph.add_sequence(&sequence.to_ascii_uppercase());
+    }
+    graph.consensus()
+}
+
+#[derive(Debug, Default)]
+struct PoaNode {
+    base: u8,
+    /// The predecessors of the node and the number of sequences using the edge from them.
+    predecessors: Vec<(usize, u32)>,
+    /// Nodes with different bases in the same alignment column.
+    aligned_nodes: Vec<usize>,
+}
+
+#[derive(Debug, Default)]
+struct PoaGraph {
+    nodes: Vec<PoaNode>,
+    /// The nodes in topological order.
+    order: Vec<usize>,
+}
+
+impl PoaGraph {
+    fn add_sequence(&mut self, sequence: &[u8]) {
+        let alignment = self.align(sequence);
+        let mut previous_node = None;
+        for (node, query_index) in alignment {
+            let query_index = if let Some(query_index) = query_index {
+                query_index
+            } else {
+                continue;
+            };
+            let base = sequence[query_index];
+            let node = match node {
+                Some(node) if self.nodes[node].base == base => node,
+                Some(node) => {
+                    let aligned_node = self.nodes[node]
+                        .aligned_nodes
+                        .iter()
+                        .copied()
+                        .find(|&aligned_node| self.nodes[aligned_node].base == base);
+                    if let Some(aligned_node) = aligned_node {
+                        aligned_node
+                    } else {
+                        let new_node = self.add_node(base);
+                        let mut column = self.nodes[node].aligned_nodes.clone();
+                        column.push(node);
+                        for &column_node in &column {
+                            self.nodes[column_node].aligned_nodes.push(new_node);
+                        }
+                        self.nodes[new_node].aligned_nodes = column;
+                        new_node
+                    }
+                }
+                None => self.add_node(base),
+            };
+            if let Some(previous_node) = previous_node {
+                self.add_edge(previous_node, node);
+            }
+            previous_node = Some(node);
+        }
+        self.sort_topologically();
+    }
+
+    fn add_node(&mut self, base: u8) -> usize {
+        self.nodes.push(PoaNode {
+            base,
+            ..Default::default()
+        });
+        self.nodes.len() - 1
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize) {
+        let predecessors = &mut self.nodes[to].predecessors;
+        if let Some((_, weight)) = predecessors
+            .iter_mut()
+            .find(|(predecessor, _)| *predecessor == from)
+        {
+            *weight += 1;
+        } else {
+            predecessors.push((from, 1));
+        }
+    }
+
+    fn sort_topologically(&mut self) {
+        let mut successors = vec![Vec::new(); self.nodes.len()];
+        let mut in_degrees = vec![0; self.nodes.len()];
+        for (node, poa_node) in self.nodes.iter().enumerate() {
+            for &(predecessor, _) in &poa_node.predecessors {
+                successors[predecessor].push(node);
+                in_degrees[node] += 1;
+            }
+        }
+
+        self.order.clear();
+        let mut stack: Vec<_> = (0..self.nodes.len())
+            .rev()
+            .filter(|&node| in_degrees[node] == 0)
+            .collect();
+        while let Some(node) = stack.pop() {
+            self.order.push(node);
+            for &successor in successors[node].iter().rev() {
+                in_degrees[successor] -= 1;
+                if in_degrees[successor] == 0 {
+                    stack.push(successor);
+                }
+            }
+        }
+        debug_assert_eq!(self.order.len(), self.nodes.len());
+    }
+
+    /// Align the sequence to the graph globally in the sequence, but with free end gaps in the graph.
+    /// Returns the alignment columns as pairs of a node and a sequence index, either of which may be missing.
+    fn align(&self, sequence: &[u8]) -> Vec<(Option<usize>, Option<usize>)> {
+        let width = sequence.len() + 1;
+        // Row 0 is a virtual start node preceding all nodes, row `rank + 1` belongs to the node `order[rank]`.
+        let mut ranks = vec![0; self.nodes.len()];
+        for (rank, &node) in self.order.iter().enumerate() {
+            ranks[node] = rank + 1;
+        }
+        let predecessor_rows = |node: usize| {
+            std::iter::once(0).chain(
+                self.nodes[node]
+                    .predecessors
+                    .iter()
+                    .map(|&(predecessor, _)| ranks[predecessor]),
+            )
+        };
+        let substitution_score = |node: usize, query_index: usize| {
+            if self.nodes[node].base == sequence[query_index] {
+                MATCH_SCORE
+            } else {
+                MISMATCH_SCORE
+            }
+        };
+
+        let mut scores = vec![0; (self.order.len() + 1) * width];
+        for (column, score) in scores[..width].iter_mut().enumerate() {
+            *score = column as i32 * GAP_SCORE;
+        }
+        for (rank, &node) in self.order.iter().enumerate() {
+            let row = rank + 1;
+            for column in 0..width {
+                let mut score = i32::MIN;
+                for predecessor_row in predecessor_rows(node) {
+                    score = score.max(scores[predecessor_row * width + column] + GAP_SCORE);
+                    if column > 0 {
+                        score = score.max(
+                            scores[predecessor_row * width + column - 1]
+                                + substitution_score(node, column - 1),
+                        );
+                    }
+                }
+                if column > 0 {
+                    score = score.max(scores[row * width + column - 1] + GAP_SCORE);
+                }
+                scores[row * width + column] = score;
+            }
+        }
+
+        // The alignment may end at any node, or at the virtual start node if the graph is empty.
+        let mut row = (0..=self.order.len())
+            .max_by_key(|&row| (scores[row * width + width - 1], std::cmp::Reverse(row)))
+            .unwrap_or(0);
+        let mut column = width - 1;
+        let mut alignment = Vec::new();
+        while column > 0 || row > 0 {
+            if row == 0 {
+                column -= 1;
+                alignment.push((None, Some(column)));
+                continue;
+            }
+            let node = self.order[row - 1];
+            let score = scores[row * width + column];
+            if column > 0 && score == scores[row * width + column - 1] + GAP_SCORE {
+                column -= 1;
+                alignment.push((None, Some(column)));
+                continue;
+            }
+            // Leading nodes of the graph are skipped for free once the sequence is aligned.
+            if column == 0 {
+                break;
+            }
+            let mut next_row = None;
+            for predecessor_row in predecessor_rows(node) {
+                if score
+                    == scores[predecessor_row * width + column - 1]
+                        + substitution_score(node, column - 1)
+                {
+                    next_row = Some((predecessor_row, column - 1));
+                    alignment.push((Some(node), Some(column - 1)));
+                    break;
+                } else if score == scores[predecessor_row * width + column] + GAP_SCORE {
+                    next_row = Some((predecessor_row, column));
+                    alignment.push((Some(node), None));
+                    break;
+                }
+            }
+            let (next_row, next_column) =
+                next_row.expect("every score is derived from one of its neighbours");
+            row = next_row;
+            column = next_column;
+        }
+        alignment.reverse();
+        alignment
+    }
+
+    /// The heaviest path through the graph.
+    fn consensus(&self) -> Vec<u8> {
+        let mut scores = vec![0u64; self.nodes.len()];
+        let mut best_predecessors = vec![None; self.nodes.len()];
+        for &node in &self.order {
+            for &(predecessor, weight) in &self.nodes[node].predecessors {
+                let score = scores[predecessor] + u64::from(weight);
+                if best_predecessors[node].is_none() || score > scores[node] {
+                    scores[node] = score;
+                    best_predecessors[node] = Some(predecessor);
+                }
+            }
+        }
+
+        let mut node = self
+            .order
+            .iter()
+            .copied()
+            .rev()
+            .max_by_key(|&node| scores[node]);
+        let mut consensus = Vec::new();
+        while let Some(current_node) = node {
+            consensus.push(self.nodes[current_node].base);
+            node = best_predecessors[current_node];
+        }
+        consensus.reverse();
+        consensus
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::consensus::{poa_consensus, ConsensusDispatcher, ConsensusTask};
+    use crate::error::Cancellation;
+    use crate::reorder_buffer::ReorderBuffer;
+    use crossbeam::channel;
+
+    fn edge(offset: u64, segments: &[&str]) -> ConsensusTask {
+        ConsensusTask::Edge {
+            offset,
+            segments: segments
+                .iter()
+                .map(|segment| segment.as_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    fn contig(name: &str) -> ConsensusTask {
+        ConsensusTask::Contig {
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_consensus_dispatcher() {
+        let (sender, receiver) = channel::unbounded();
+        let cancellation = Cancellation::default();
+        let mut dispatcher = ConsensusDispatcher::new(sender, &cancellation);
+        dispatcher.start_contig("ctg1").unwrap();
+        assert!(dispatcher.add_segment(b"ACGT".to_vec()).is_err());
+        dispatcher.start_edge(0).unwrap();
+        dispatcher.add_segment(b"ACGT".to_vec()).unwrap();
+        dispatcher.add_segment(b"ACGGT".to_vec()).unwrap();
+        dispatcher.add_segment(b"ACGGT".to_vec()).unwrap();
+        // An edge whose alignments were all dropped.
+        dispatcher.start_edge(5).unwrap();
+        dispatcher.start_edge(8).unwrap();
+        dispatcher.add_segment(b"TTGA".to_vec()).unwrap();
+        dispatcher.start_contig("ctg2").unwrap();
+        dispatcher.start_edge(0).unwrap();
+        dispatcher.finish().unwrap();
+        assert!(!cancellation.is_cancelled());
+
+        let tasks: Vec<_> = receiver.iter().collect();
+        let sequence_numbers: Vec<_> = tasks.iter().map(|(number, _)| *number).collect();
+        assert_eq!(sequence_numbers, (0..6).collect::<Vec<_>>());
+
+        // The consensus stages finish the tasks out of order, and the consensus writer restores the order.
+        let mut reorder_buffer = ReorderBuffer::new();
+        let mut ordered_tasks = Vec::new();
+        for (sequence_number, task) in tasks.into_iter().rev() {
+            reorder_buffer
+                .insert(sequence_number, task.compute_consensus())
+                .unwrap();
+            while let Some(task) = reorder_buffer.pop() {
+                ordered_tasks.push(task);
+            }
+        }
+        assert!(reorder_buffer.is_empty());
+        assert_eq!(
+            ordered_tasks,
+            vec![
+                contig("ctg1"),
+                edge(0, &["ACGGT"]),
+                edge(5, &[""]),
+                edge(8, &["TTGA"]),
+                contig("ctg2"),
+                edge(0, &[""]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unfinished_consensus_dispatcher_cancels() {
+        let (sender, receiver) = channel::unbounded();
+        let cancellation = Cancellation::default();
+        let mut dispatcher = ConsensusDispatcher::new(sender, &cancellation);
+        dispatcher.start_contig("ctg1").unwrap();
+        drop(dispatcher);
+        assert!(cancellation.is_cancelled());
+        assert_eq!(receiver.iter().count(), 1);
+    }
+
+    #[test]
+    fn test_poa_consensus() {
+        assert_eq!(poa_consensus(&["ACGTTGCA"]), b"ACGTTGCA");
+        // Each read has a different error.
+        assert_eq!(
+            poa_consensus(&[
+                "ACGTTGCAAGT",
+                "ACGATGCAAGT",
+                "ACGTTGCAGT",
+                "ACGTTGGCAAGT",
+                "acgttgcaagt",
+            ]),
+            b"ACGTTGCAAGT"
+        );
+        // Overhangs are aligned with free end gaps.
+        assert_eq!(
+            poa_consensus(&["TTACGTTGCAAGT", "ACGTTGCAAGTCC", "ACGTTGCAAGT"]),
+            b"TTACGTTGCAAGTCC"
+        );
+        assert!(poa_consensus::<&str>(&[]).is_empty());
+    }
+}
@@ -0,0 +1,112 @@
+use crate::error::{Error, IoContext, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Selects contigs by name, name pattern and length.
+/// A contig is selected if it matches any of the given names or the pattern (or if neither is given),
+/// and if its length lies within the given bounds.
+#[derive(Debug, Default)]
+pub struct ContigFilter {
+    names: Option<HashSet<String>>,
+    regex: Option<Regex>,
+    min_length: Option<u64>,
+    max_length: Option<u64>,
+}
+
+impl ContigFilter {
+    pub fn new<P: AsRef<Path>>(
+        names: &[String],
+        names_file: Option<P>,
+        regex: Option<&str>,
+        min_length: Option<u64>,
+        max_length: Option<u64>,
+    ) -> Result<Self> {
+        let names = if names.is_empty() && names_file.is_none() {
+            None
+        } else {
+            let mut names: HashSet<_> = names.iter().cloned().collect();
+            if let Some(names_file) = names_file {
+                let names_file = names_file.as_ref();
+                let file = File::open(names_file)
+                    .io_context(|| format!("Could not open {names_file:?}"))?;
+                for line in BufReader::new(file).lines() {
+                    let line = line.io_context(|| format!("Could not read {names_file:?}"))?;
+                    let name = line.trim();
+                    if !name.is_empty() {
+                        names.insert(name.to_owned());
+                    }
+                }
+            }
+            Some(names)
+        };
+        let regex = regex
+            .map(|regex| {
+                Regex::new(regex)
+                    .map_err(|error| Error::Input(format!("Invalid contig regex: {error}")))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            names,
+            regex,
+            min_length,
+            max_length,
+        })
+    }
+
+    /// The names of the selected contigs, if the filter selects by exact names only.
+    pub fn exact_names(&self) -> Option<&HashSet<String>> {
+        if self.regex.is_none() && self.min_length.is_none() && self.max_length.is_none() {
+            self.names.as_ref()
+        } else {
+            None
+        }
+    }
+
+    pub fn is_selected(&self, name: &str, length: u64) -> bool {
+        let name_selected = match (&self.names, &self.regex) {
+            (None, None) => true,
+            (names, regex) => {
+                names.as_ref().map_or(false, |names| names.contains(name))
+                    || regex.as_ref().map_or(false, |regex| regex.is_match(name))
+            }
+        };
+
+        name_selected
+            && self
+                .min_length
+                .map_or(true, |min_length| length >= min_length)
+            && self
+                .max_length
+                .map_or(true, |max_length| length <= max_length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::contig_filter::ContigFilter;
+
+    #[test]
+    fn test_contig_filter() {
+        let filter = ContigFilter::default();
+        assert!(filter.is_selected("ctg1", 0));
+
+        let names = vec!["ctg1".to_string(), "ctg3".to_string()];
+        let filter =
+            ContigFilter::new(&names, None::<&str>, Some("^ctg1[0-9]$"), None, Some(100)).unwrap();
+        assert!(filter.is_selected("ctg1", 100));
+        assert!(!filter.is_selected("ctg1", 101));
+        assert!(!filter.is_selected("ctg2", 50));
+        assert!(filter.is_selected("ctg3", 50));
+        assert!(filter.is_selected("ctg12", 50));
+        assert!(!filter.is_selected("ctg123", 50));
+        assert!(filter.exact_names().is_none());
+
+        let filter = ContigFilter::new(&[], None::<&str>, None, Some(10), None).unwrap();
+        assert!(!filter.is_selected("ctg1", 9));
+        assert!(filter.is_selected("ctg1", 10));
+    }
+}
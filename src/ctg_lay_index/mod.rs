@@ -0,0 +1,342 @@
+use crate::error::{Error, IoContext, Result};
+use crate::fingerprint::file_fingerprint;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// The location and size of a contig in a .ctg.lay file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CtgLayIndexEntry {
+    pub name: String,
+    /// The byte offset of the contig line.
+    pub offset: u64,
+    pub edge_count: u64,
+    pub alignment_count: u64,
+}
+
+/// Maps each contig of a .ctg.lay file to its position in the file.
+pub struct CtgLayIndex {
+    /// Sorted by offset.
+    entries: Vec<CtgLayIndexEntry>,
+    by_name: HashMap<String, usize>,
+    file_length: u64,
+}
+
+impl CtgLayIndex {
+    /// Load the side index of the given .ctg.lay file, or build and persist it if it is missing or outdated.
+    pub fn load_or_build<P: AsRef<Path>>(input_file: P, io_buffer_size: usize) -> Result<Self> {
+        let input_file = input_file.as_ref();
+        let index_file = index_path(input_file);
+        match Self::load(input_file, &index_file, io_buffer_size) {
+            Ok(Some(index)) => return Ok(index),
+            Ok(None) => {}
+            // The index is rebuilt, so a corrupted index is not fatal either.
+            Err(error) => warn!("Ignoring the persisted contig index: {error}"),
+        }
+
+        info!("Building contig index of {input_file:?}...");
+        let index = Self::build(input_file, io_buffer_size)?;
+        // The index is only an optimisation, so failing to persist it is not fatal.
+        if let Err(error) = index.persist(input_file, &index_file, io_buffer_size) {
+            warn!("Could not persist contig index: {error}");
+        }
+        info!("Built contig index with {} contigs", index.entries.len());
+        Ok(index)
+    }
+
+    /// Build the index by scanning the line types, without parsing the lines.
+    pub fn build<P: AsRef<Path>>(input_file: P, io_buffer_size: usize) -> Result<Self> {
+        let input_file = input_file.as_ref();
+        let mut reader = BufReader::with_capacity(
+            io_buffer_size,
+            File::open(input_file).io_context(|| format!("Could not open {input_file:?}"))?,
+        );
+
+        let mut entries: Vec<CtgLayIndexEntry> = Vec::new();
+        let mut line = Vec::new();
+        let mut offset = 0;
+        loop {
+            line.clear();
+            let length = reader
+                .read_until(b'\n', &mut line)
+                .io_context(|| format!("Could not read {input_file:?}"))?;
+            if length == 0 {
+                break;
+            }
+
+            match line.first() {
+                Some(b'>') => {
+                    let name = line[1..]
+                        .split(|&c| c == b' ' || c == b'\n')
+                        .next()
+                        .unwrap_or_default();
+                    entries.push(CtgLayIndexEntry {
+                        name: String::from_utf8_lossy(name).into_owned(),
+                        offset,
+                        edge_count: 0,
+                        alignment_count: 0,
+                    });
+                }
+                Some(b'E') | Some(b'S') => {
+                    let entry = entries.last_mut().ok_or_else(|| {
+                        Error::Input(format!(
+                            "{input_file:?} has a line before the first contig line"
+                        ))
+                    })?;
+                    if line[0] == b'E' {
+                        entry.edge_count += 1;
+                    } else {
+                        entry.alignment_count += 1;
+                    }
+                }
+                _ => {}
+            }
+            offset += length as u64;
+        }
+
+        Self::new(entries, offset)
+    }
+
+    fn new(entries: Vec<CtgLayIndexEntry>, file_length: u64) -> Result<Self> {
+        let mut by_name = HashMap::new();
+        for (index, entry) in entries.iter().enumerate() {
+            if by_name.insert(entry.name.clone(), index).is_some() {
+                return Err(Error::Input(format!(
+                    "Found duplicate contig name: {}",
+                    entry.name
+                )));
+            }
+        }
+
+        Ok(Self {
+            entries,
+            by_name,
+            file_length,
+        })
+    }
+
+    pub fn persist<P1: AsRef<Path>, P2: AsRef<Path>>(
+        &self,
+        input_file: P1,
+        index_file: P2,
+        io_buffer_size: usize,
+    ) -> Result<()> {
+        let index_file = index_file.as_ref();
+        let mut writer = BufWriter::with_capacity(
+            io_buffer_size,
+            File::create(index_file).io_context(|| format!("Could not create {index_file:?}"))?,
+        );
+        writeln!(writer, "{}", file_fingerprint(input_file.as_ref())?)
+            .and_then(|()| writeln!(writer, "# contigs={}", self.entries.len()))
+            .io_context(|| format!("Could not write to {index_file:?}"))?;
+        for entry in &self.entries {
+            writeln!(
+                writer,
+                "{}\t{}\t{}\t{}",
+                entry.name, entry.offset, entry.edge_count, entry.alignment_count
+            )
+            .io_context(|| format!("Could not write to {index_file:?}"))?;
+        }
+        writer
+            .flush()
+            .io_context(|| format!("Could not write to {index_file:?}"))
+    }
+
+    /// Load a persisted index, or return `None` if it does not exist or the .ctg.lay file was modified.
+    /// Fails if the index is malformed or truncated.
+    pub fn load<P1: AsRef<Path>, P2: AsRef<Path>>(
+        input_file: P1,
+        index_file: P2,
+        io_buffer_size: usize,
+    ) -> Result<Option<Self>> {
+        let input_file = input_file.as_ref();
+        let index_file = index_file.as_ref();
+        let file = match File::open(index_file) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).io_context(|| format!("Could not open {index_file:?}"))
+            }
+        };
+
+        let mut lines = BufReader::with_capacity(io_buffer_size, file).lines();
+        match lines.next() {
+            Some(fingerprint) => {
+                if fingerprint.io_context(|| format!("Could not read {index_file:?}"))?
+                    != file_fingerprint(input_file)?
+                {
+                    return Ok(None);
+                }
+            }
+            None => return Ok(None),
+        }
+        // The number of contigs detects an index that was truncated at a line break.
+        let contig_count: usize = lines
+            .next()
+            .transpose()
+            .io_context(|| format!("Could not read {index_file:?}"))?
+            .as_deref()
+            .and_then(|line| line.strip_prefix("# contigs="))
+            .and_then(|contig_count| contig_count.parse().ok())
+            .ok_or_else(|| malformed_error(index_file))?;
+
+        let mut entries = Vec::new();
+        for line in lines {
+            let line = line.io_context(|| format!("Could not read {index_file:?}"))?;
+            let mut columns = line.split('\t');
+            let name = columns.next().ok_or_else(|| malformed_error(index_file))?;
+            let mut next_number = || -> Result<u64> {
+                columns
+                    .next()
+                    .and_then(|column| column.parse().ok())
+                    .ok_or_else(|| malformed_error(index_file))
+            };
+            entries.push(CtgLayIndexEntry {
+                name: name.to_owned(),
+                offset: next_number()?,
+                edge_count: next_number()?,
+                alignment_count: next_number()?,
+            });
+        }
+
+        if entries.len() != contig_count {
+            return Err(malformed_error(index_file));
+        }
+
+        let file_length = input_file
+            .metadata()
+            .io_context(|| format!("Could not read metadata of {input_file:?}"))?
+            .len();
+        Self::new(entries, file_length).map(Some)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CtgLayIndexEntry> {
+        self.by_name.get(name).map(|&index| &self.entries[index])
+    }
+
+    /// The byte range of the given contig, including all of its edge and alignment lines.
+    pub fn byte_range(&self, name: &str) -> Option<Range<u64>> {
+        self.by_name.get(name).map(|&index| {
+            let end = self
+                .entries
+                .get(index + 1)
+                .map(|entry| entry.offset)
+                .unwrap_or(self.file_length);
+            self.entries[index].offset..end
+        })
+    }
+}
+
+fn index_path(input_file: &Path) -> PathBuf {
+    let mut index_file = OsString::from(input_file.as_os_str());
+    index_file.push(".ctg_index");
+    index_file.into()
+}
+
+fn malformed_error(index_file: &Path) -> Error {
+    Error::Input(format!("Malformed contig index file {index_file:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ctg_lay_index::{index_path, CtgLayIndex};
+    use std::fs;
+    use std::path::PathBuf;
+
+    const LAYOUT: &str = ">ctg1 nodes=2 len=20
+E\t0\tN1\t+\tN2\t+
+S\tread1\t+\t0\t4\tACGT
+S\tread2\t-\t2\t4\tACGT
+>ctg2 nodes=2 len=10
+E\t0\tN3\t+\tN4\t-
+S\tread3\t+\t0\t2\tAC
+";
+
+    fn write_layout(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ctg_lay_index_test_{}_{name}.ctg.lay",
+            std::process::id()
+        ));
+        fs::write(&path, LAYOUT).unwrap();
+        path
+    }
+
+    fn remove_layout(path: &PathBuf) {
+        fs::remove_file(path).unwrap();
+        fs::remove_file(index_path(path)).ok();
+    }
+
+    #[test]
+    fn test_build() {
+        let path = write_layout("build");
+        let index = CtgLayIndex::build(&path, 16).unwrap();
+        let ctg1 = index.get("ctg1").unwrap();
+        assert_eq!(
+            (ctg1.offset, ctg1.edge_count, ctg1.alignment_count),
+            (0, 1, 2)
+        );
+        let ctg2 = index.get("ctg2").unwrap();
+        assert_eq!(
+            (ctg2.offset, ctg2.edge_count, ctg2.alignment_count),
+            (73, 1, 1)
+        );
+        assert!(index.get("ctg3").is_none());
+
+        assert_eq!(index.byte_range("ctg1"), Some(0..73));
+        assert_eq!(index.byte_range("ctg2"), Some(73..LAYOUT.len() as u64));
+        assert!(LAYOUT[73..].starts_with(">ctg2"));
+        remove_layout(&path);
+    }
+
+    #[test]
+    fn test_load() {
+        let path = write_layout("load");
+        let built = CtgLayIndex::load_or_build(&path, 16).unwrap();
+        let loaded = CtgLayIndex::load(&path, index_path(&path), 16)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.entries, built.entries);
+        assert_eq!(loaded.byte_range("ctg2"), built.byte_range("ctg2"));
+        remove_layout(&path);
+    }
+
+    #[test]
+    fn test_load_stale() {
+        let path = write_layout("stale");
+        CtgLayIndex::load_or_build(&path, 16).unwrap();
+        // Changing the size of the layout changes its fingerprint.
+        fs::write(&path, &LAYOUT[..73]).unwrap();
+        assert!(CtgLayIndex::load(&path, index_path(&path), 16)
+            .unwrap()
+            .is_none());
+        // The stale index is rebuilt.
+        let index = CtgLayIndex::load_or_build(&path, 16).unwrap();
+        assert_eq!(index.byte_range("ctg1"), Some(0..73));
+        assert!(index.get("ctg2").is_none());
+        remove_layout(&path);
+    }
+
+    #[test]
+    fn test_load_truncated() {
+        let path = write_layout("truncated");
+        CtgLayIndex::load_or_build(&path, 16).unwrap();
+        let persisted = fs::read_to_string(index_path(&path)).unwrap();
+
+        // Truncated at a line break, such that the last contig is missing.
+        let last_line_start = persisted.trim_end().rfind('\n').unwrap() + 1;
+        fs::write(index_path(&path), &persisted[..last_line_start]).unwrap();
+        assert!(CtgLayIndex::load(&path, index_path(&path), 16).is_err());
+        // Truncated within a line.
+        fs::write(index_path(&path), &persisted[..persisted.len() - 4]).unwrap();
+        assert!(CtgLayIndex::load(&path, index_path(&path), 16).is_err());
+
+        // The truncated index is rebuilt.
+        let index = CtgLayIndex::load_or_build(&path, 16).unwrap();
+        assert_eq!(index.byte_range("ctg2"), Some(73..LAYOUT.len() as u64));
+        remove_layout(&path);
+    }
+}
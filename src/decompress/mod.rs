@@ -1,4 +1,18 @@
-pub fn decompress(offset: usize, limit: usize, sequence: &[u8]) -> (usize, usize) {
+use crate::error::{Error, Result};
+use crate::reverse_complement;
+
+/// Translate the homopolymer compressed interval `offset..limit` of `sequence` into an uncompressed interval.
+/// Fails if the interval is reversed or does not lie within the compressed sequence.
+pub fn decompress(offset: usize, limit: usize, sequence: &[u8]) -> Result<(usize, usize)> {
+    if offset > limit {
+        return Err(Error::Input(format!(
+            "Alignment offset {offset} is greater than its limit {limit}"
+        )));
+    }
+    if sequence.is_empty() && limit > 0 {
+        return Err(out_of_bounds_error(offset, limit, sequence));
+    }
+
     // Find offset.
     // Use a block to ensure the next block does not accidentally reuse any variable.
     let shifted_offset = {
@@ -18,6 +32,9 @@ pub fn decompress(offset: usize, limit: usize, sequence: &[u8]) -> (usize, usize
         }
         // The windowed iteration cannot recognise an offset after the end of the sequence.
         if current_offset != offset {
+            if current_offset + 1 != offset {
+                return Err(out_of_bounds_error(offset, limit, sequence));
+            }
             shifted_offset += 1;
         }
         shifted_offset
@@ -40,14 +57,193 @@ pub fn decompress(offset: usize, limit: usize, sequence: &[u8]) -> (usize, usize
     }
     // The windowed iteration cannot recognise a limit after the end of the sequence.
     if current_limit != limit {
+        // If the iteration started at the end of the sequence, then the limit cannot be after it.
+        if current_limit + 1 != limit || shifted_limit >= sequence.len() {
+            return Err(out_of_bounds_error(offset, limit, sequence));
+        }
         shifted_limit += 1;
     }
-    (shifted_offset, shifted_limit)
+    Ok((shifted_offset, shifted_limit))
+}
+
+/// Translate the uncompressed interval `offset..limit` of `sequence` into a homopolymer compressed interval.
+/// This is the inverse of [decompress] for intervals that start and end at the boundaries of runs,
+/// while other intervals are extended to the runs they overlap.
+/// Fails if the interval is reversed or does not lie within the sequence.
+pub fn compress(offset: usize, limit: usize, sequence: &[u8]) -> Result<(usize, usize)> {
+    if offset > limit {
+        return Err(Error::Input(format!(
+            "Alignment offset {offset} is greater than its limit {limit}"
+        )));
+    }
+    if limit > sequence.len() {
+        return Err(out_of_bounds_error(offset, limit, sequence));
+    }
+
+    // The index of the run containing a position is the number of run boundaries up to it.
+    let run_index = |position: usize| {
+        sequence[..=position]
+            .windows(2)
+            .filter(|character_window| character_window[0] != character_window[1])
+            .count()
+    };
+    let compressed_offset = if offset < sequence.len() {
+        run_index(offset)
+    } else if sequence.is_empty() {
+        0
+    } else {
+        run_index(sequence.len() - 1) + 1
+    };
+    let compressed_limit = if limit > offset {
+        run_index(limit - 1) + 1
+    } else {
+        compressed_offset
+    };
+    Ok((compressed_offset, compressed_limit))
+}
+
+/// The minimum fraction of matching characters for a segment to be considered as compressing to an embedded segment.
+pub const MIN_SEGMENT_IDENTITY: f64 = 0.8;
+
+/// Compress each run of equal characters into a single character.
+pub fn homopolymer_compress(sequence: &[u8]) -> Vec<u8> {
+    let mut compressed = sequence.to_vec();
+    compressed.dedup();
+    compressed
+}
+
+/// The number of runs of equal characters, i.e. the length of the homopolymer compressed sequence.
+pub fn run_count(sequence: &[u8]) -> usize {
+    if sequence.is_empty() {
+        0
+    } else {
+        sequence
+            .windows(2)
+            .filter(|character_window| character_window[0] != character_window[1])
+            .count()
+            + 1
+    }
+}
+
+/// Compress each run of equal characters into a single character, and return the length of each run alongside.
+pub fn run_length_encode(sequence: &[u8]) -> (Vec<u8>, Vec<usize>) {
+    let mut compressed = Vec::new();
+    let mut run_lengths = Vec::new();
+    for &character in sequence {
+        if compressed.last() == Some(&character) {
+            *run_lengths
+                .last_mut()
+                .expect("runs and characters are pushed together") += 1;
+        } else {
+            compressed.push(character);
+            run_lengths.push(1);
+        }
+    }
+    (compressed, run_lengths)
+}
+
+/// Detect in which orientation the uncompressed `segment` of a read compresses to `compressed_segment`.
+/// Returns `Some(true)` if it matches better forwards, `Some(false)` if it matches better reverse complemented,
+/// the given `direction` if it matches equally well in both orientations, e.g. for reverse complement palindromes,
+/// and `None` if it matches in neither orientation.
+pub fn detect_strand(segment: &[u8], compressed_segment: &[u8], direction: bool) -> Option<bool> {
+    let forward_identity = compressed_identity(segment, compressed_segment, true);
+    let reverse_identity = compressed_identity(segment, compressed_segment, false);
+    if forward_identity.max(reverse_identity) < MIN_SEGMENT_IDENTITY {
+        None
+    } else if forward_identity == reverse_identity {
+        Some(direction)
+    } else {
+        Some(forward_identity > reverse_identity)
+    }
+}
+
+/// Check if the uncompressed `segment` of a read compresses to `compressed_segment` in the given direction.
+pub fn matches_compressed(segment: &[u8], compressed_segment: &[u8], direction: bool) -> bool {
+    compressed_identity(segment, compressed_segment, direction) >= MIN_SEGMENT_IDENTITY
+}
+
+/// The identity of the compressed `segment` with `compressed_segment`,
+/// after reverse complementing the compressed segment if `direction` is false.
+fn compressed_identity(segment: &[u8], compressed_segment: &[u8], direction: bool) -> f64 {
+    let compressed = homopolymer_compress(segment);
+    if direction {
+        identity(compressed.iter().cloned(), compressed_segment)
+    } else {
+        identity(
+            compressed.iter().rev().map(|&c| complement(c)),
+            compressed_segment,
+        )
+    }
+}
+
+/// The fraction of equal characters at equal positions, relative to the longer sequence.
+fn identity<Sequence: ExactSizeIterator<Item = u8>>(sequence: Sequence, other: &[u8]) -> f64 {
+    let length = sequence.len().max(other.len());
+    if length == 0 {
+        return 1.0;
+    }
+    let matches = sequence
+        .zip(other)
+        .filter(|(a, b)| a.eq_ignore_ascii_case(b))
+        .count();
+    matches as f64 / length as f64
+}
+
+pub fn complement(c: u8) -> u8 {
+    match c.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        c => c,
+    }
+}
+
+/// The end of the alignment interval `offset..offset + length`.
+/// Fails if the end overflows, which only happens for malformed coordinates.
+pub fn alignment_limit(offset: usize, length: usize) -> Result<usize> {
+    offset.checked_add(length).ok_or_else(|| {
+        Error::Input(format!(
+            "Alignment offset {offset} plus length {length} overflows"
+        ))
+    })
+}
+
+/// The segment `offset..limit` of a read in the direction of its contig,
+/// i.e. reverse complemented if `direction` is false.
+pub fn oriented_segment(
+    read: &[u8],
+    offset: usize,
+    limit: usize,
+    direction: bool,
+) -> Result<Vec<u8>> {
+    let segment = read.get(offset..limit).ok_or_else(|| {
+        Error::Input(format!(
+            "Alignment {offset}..{limit} is out of bounds of a read with length {}",
+            read.len()
+        ))
+    })?;
+    if direction {
+        Ok(segment.to_vec())
+    } else {
+        reverse_complement(segment.iter().copied())
+    }
+}
+
+fn out_of_bounds_error(offset: usize, limit: usize, sequence: &[u8]) -> Error {
+    Error::Input(format!(
+        "Alignment {offset}..{limit} is out of bounds of a read with uncompressed length {}",
+        sequence.len()
+    ))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::decompress;
+    use crate::decompress::{
+        alignment_limit, compress, decompress, detect_strand, homopolymer_compress, run_count,
+        run_length_encode,
+    };
 
     #[test]
     fn test_decompress() {
@@ -71,8 +267,74 @@ mod tests {
             (6, 6, 10, 10),
         ];
         for (offset, limit, shifted_offset, shifted_limit) in tests {
-            let (decompressed_offset, decompressed_limit) = decompress(offset, limit, &sequence);
+            let (decompressed_offset, decompressed_limit) =
+                decompress(offset, limit, &sequence).unwrap();
             assert_eq!((decompressed_offset, decompressed_limit), (shifted_offset, shifted_limit), "({offset}, {limit}): expected ({shifted_offset}, {shifted_limit}) but got ({decompressed_offset}, {decompressed_limit})");
+            assert_eq!(
+                compress(shifted_offset, shifted_limit, &sequence).unwrap(),
+                (offset, limit)
+            );
+        }
+    }
+
+    #[test]
+    fn test_compress() {
+        let sequence = vec![0, 0, 1, 1, 2, 3, 3, 3, 4, 5];
+        // Intervals that end within runs are extended to the whole runs.
+        assert_eq!(compress(1, 6, &sequence).unwrap(), (0, 4));
+        assert_eq!(compress(3, 3, &sequence).unwrap(), (1, 1));
+        assert!(compress(5, 11, &sequence).is_err());
+        assert!(compress(3, 2, &sequence).is_err());
+        assert_eq!(compress(0, 0, &[]).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_detect_strand() {
+        let segment = b"AACGGGTTA";
+        assert_eq!(homopolymer_compress(segment), b"ACGTA");
+        assert_eq!(detect_strand(segment, b"ACGTA", false), Some(true));
+        assert_eq!(detect_strand(segment, b"TACGT", true), Some(false));
+        assert_eq!(detect_strand(segment, b"GGCCA", true), None);
+
+        // A reverse complement palindrome matches in both orientations, so the given direction is kept.
+        let segment = b"AACGGTT";
+        assert_eq!(homopolymer_compress(segment), b"ACGT");
+        assert_eq!(detect_strand(segment, b"ACGT", false), Some(false));
+        assert_eq!(detect_strand(segment, b"ACGT", true), Some(true));
+    }
+
+    #[test]
+    fn test_alignment_limit() {
+        assert_eq!(alignment_limit(3, 4).unwrap(), 7);
+        assert!(alignment_limit(usize::MAX, 1).is_err());
+    }
+
+    #[test]
+    fn test_run_count() {
+        assert_eq!(run_count(b"AACGGGTTA"), 5);
+        assert_eq!(run_count(b"A"), 1);
+        assert_eq!(run_count(b""), 0);
+    }
+
+    #[test]
+    fn test_run_length_encode() {
+        assert_eq!(
+            run_length_encode(b"AACGGGTTA"),
+            (b"ACGTA".to_vec(), vec![2, 1, 3, 2, 1])
+        );
+        assert_eq!(run_length_encode(b""), (Vec::new(), Vec::new()));
+    }
+
+    #[test]
+    fn test_decompress_out_of_bounds() {
+        let sequence = vec![0, 0, 1, 1, 2, 3, 3, 3, 4, 5];
+        for (offset, limit) in [(0, 7), (6, 7), (7, 7), (3, 2)] {
+            assert!(
+                decompress(offset, limit, &sequence).is_err(),
+                "({offset}, {limit}) should be out of bounds"
+            );
         }
+        assert!(decompress(0, 0, &[]).is_ok());
+        assert!(decompress(0, 1, &[]).is_err());
     }
 }
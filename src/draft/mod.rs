@@ -0,0 +1,129 @@
+use bio::io::fasta;
+use clap::ArgEnum;
+use std::io::Write;
+
+/// The read segment of an edge that represents the edge in the draft contig.
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SegmentChoice {
+    Longest,
+    /// The shorter of the two middle segments if the edge has an even number of segments.
+    Median,
+}
+
+impl SegmentChoice {
+    /// Return the index of the chosen segment, or `None` if there are no segments.
+    pub fn choose<Segment: AsRef<[u8]>>(&self, segments: &[Segment]) -> Option<usize> {
+        let length = |index: usize| segments[index].as_ref().len();
+        match self {
+            // Reversed, such that ties are broken by the order of the segments.
+            SegmentChoice::Longest => (0..segments.len()).rev().max_by_key(|&index| length(index)),
+            SegmentChoice::Median => {
+                let mut indices: Vec<_> = (0..segments.len()).collect();
+                indices.sort_by_key(|&index| length(index));
+                indices.get(indices.len().saturating_sub(1) / 2).copied()
+            }
+        }
+    }
+}
+
+/// Writes a draft sequence of each contig as FASTA, without computing a consensus.
+/// The chosen segment of each edge is placed at the offset of the edge, and overwritten from the offset of the next edge on.
+/// Positions not covered by any segment are filled with `N`.
+pub struct DraftWriter<W: Write> {
+    writer: fasta::Writer<W>,
+    segment_choice: SegmentChoice,
+    contig_name: String,
+    sequence: Vec<u8>,
+    edge_offset: usize,
+    edge_segments: Vec<Vec<u8>>,
+    contig_count: u64,
+}
+
+impl<W: Write> DraftWriter<W> {
+    pub fn new(writer: W, segment_choice: SegmentChoice) -> Self {
+        Self {
+            writer: fasta::Writer::new(writer),
+            segment_choice,
+            contig_name: String::new(),
+            sequence: Vec::new(),
+            edge_offset: 0,
+            edge_segments: Vec::new(),
+            contig_count: 0,
+        }
+    }
+
+    pub fn start_contig(&mut self, contig_name: &str) {
+        self.contig_name = contig_name.to_owned();
+        self.sequence.clear();
+        self.edge_offset = 0;
+        self.edge_segments.clear();
+    }
+
+    /// Start an edge of the current contig at its offset in the output.
+    pub fn start_edge(&mut self, offset: u64) {
+        self.place_edge();
+        self.edge_offset = offset as usize;
+    }
+
+    /// Add a read segment of the current edge, in the direction of the contig.
+    pub fn add_segment(&mut self, segment: &[u8]) {
+        self.edge_segments.push(segment.to_owned());
+    }
+
+    /// Write the draft sequence of the current contig.
+    pub fn finish_contig(&mut self) -> std::io::Result<()> {
+        self.place_edge();
+        self.writer.write(&self.contig_name, None, &self.sequence)?;
+        self.contig_count += 1;
+        Ok(())
+    }
+
+    /// Flush the output and return the number of written contigs.
+    pub fn finish(mut self) -> std::io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.contig_count)
+    }
+
+    /// Place the chosen segment of the current edge, if it has any segments.
+    fn place_edge(&mut self) {
+        if let Some(index) = self.segment_choice.choose(&self.edge_segments) {
+            self.sequence.resize(self.edge_offset, b'N');
+            self.sequence.extend_from_slice(&self.edge_segments[index]);
+        }
+        self.edge_segments.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::draft::{DraftWriter, SegmentChoice};
+
+    #[test]
+    fn test_segment_choice() {
+        let segments = ["ACG", "A", "ACGTA", "AC"];
+        assert_eq!(SegmentChoice::Longest.choose(&segments), Some(2));
+        assert_eq!(SegmentChoice::Median.choose(&segments), Some(3));
+        assert_eq!(SegmentChoice::Median.choose(&segments[..3]), Some(0));
+        assert_eq!(SegmentChoice::Longest.choose::<&str>(&[]), None);
+    }
+
+    #[test]
+    fn test_tiling() {
+        let mut output = Vec::new();
+        let mut draft_writer = DraftWriter::new(&mut output, SegmentChoice::Longest);
+        draft_writer.start_contig("ctg1");
+        draft_writer.start_edge(0);
+        draft_writer.add_segment(b"AAAAAA");
+        draft_writer.add_segment(b"AA");
+        draft_writer.start_edge(4);
+        draft_writer.add_segment(b"CCC");
+        // An edge without segments does not change the draft.
+        draft_writer.start_edge(6);
+        draft_writer.start_edge(9);
+        draft_writer.add_segment(b"GG");
+        draft_writer.finish_contig().unwrap();
+        assert_eq!(draft_writer.finish().unwrap(), 1);
+
+        assert_eq!(String::from_utf8(output).unwrap(), ">ctg1\nAAAACCCNNGG\n");
+    }
+}
@@ -0,0 +1,182 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The input files are malformed or do not match each other.
+    Input(String),
+
+    /// An I/O operation failed.
+    Io { context: String, source: io::Error },
+
+    /// An internal invariant of the pipeline was violated.
+    Consistency(String),
+
+    /// The operation was aborted because a different pipeline stage failed.
+    Cancelled,
+}
+
+impl Error {
+    /// The exit code of the process if it terminates with this error.
+    /// The codes are taken from `sysexits.h`.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Input(_) => 65,
+            Error::Io { .. } => 74,
+            Error::Consistency(_) | Error::Cancelled => 70,
+        }
+    }
+
+    /// Prepend the location of the problem to the message of input errors.
+    pub fn at<Location: Display>(self, location: Location) -> Self {
+        match self {
+            Error::Input(message) => Error::Input(format!("{location}: {message}")),
+            error => error,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Error::Cancelled)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Input(message) => write!(f, "Input error: {message}"),
+            Error::Io { context, source } => write!(f, "I/O error: {context}: {source}"),
+            Error::Consistency(message) => write!(f, "Consistency error: {message}"),
+            Error::Cancelled => write!(f, "Cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Attach a description of the failed operation to I/O errors.
+pub trait IoContext<T> {
+    fn io_context<Context: FnOnce() -> String>(self, context: Context) -> Result<T>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context<Context: FnOnce() -> String>(self, context: Context) -> Result<T> {
+        self.map_err(|source| Error::Io {
+            context: context(),
+            source,
+        })
+    }
+}
+
+/// Shared flag that tells all pipeline stages to stop once one of them has failed.
+#[derive(Default, Debug)]
+pub struct Cancellation {
+    cancelled: AtomicBool,
+}
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns `Err(Error::Cancelled)` if any stage has failed.
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run a pipeline stage on its channel endpoints, and cancel all other stages if it fails.
+    /// The endpoints are dropped only after the cancellation flag is set,
+    /// such that the stages at their other end observe the cancellation instead of a regular end of input.
+    pub fn run<Endpoints, T, Stage: FnOnce(&Endpoints) -> Result<T>>(
+        &self,
+        endpoints: Endpoints,
+        stage: Stage,
+    ) -> Result<T> {
+        let result = stage(&endpoints);
+        if result.is_err() {
+            self.cancel();
+        }
+        drop(endpoints);
+        result
+    }
+}
+
+/// Combine the results of all threads of a pipeline into one.
+/// The first error that is not a cancellation wins, since cancellations are only consequences of other errors.
+pub fn merge_results<Results: IntoIterator<Item = Result<()>>>(results: Results) -> Result<()> {
+    let mut cancelled = false;
+    for result in results {
+        match result {
+            Ok(()) => {}
+            Err(Error::Cancelled) => cancelled = true,
+            Err(error) => return Err(error),
+        }
+    }
+
+    if cancelled {
+        Err(Error::Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Convert the result of joining a thread into a pipeline result.
+pub fn join_result<T>(result: std::thread::Result<Result<T>>, thread_name: &str) -> Result<T> {
+    result.unwrap_or_else(|_| Err(Error::Consistency(format!("thread {thread_name} panicked"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{Cancellation, Error, Result};
+    use crossbeam::channel;
+
+    #[test]
+    fn test_run_cancels_before_dropping_endpoints() {
+        let cancellation = Cancellation::default();
+        let (sender, receiver) = channel::unbounded::<()>();
+        crossbeam::scope(|scope| {
+            let cancellation = &cancellation;
+            let downstream = scope.spawn(move |_| {
+                while receiver.recv().is_ok() {}
+                // The channel is closed, so the failing stage must have set the flag already.
+                cancellation.is_cancelled()
+            });
+            let result = cancellation.run(sender, |_| -> Result<()> {
+                Err(Error::Input("failure".to_string()))
+            });
+            assert!(matches!(result, Err(Error::Input(_))));
+            assert!(downstream.join().unwrap());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_run_succeeds_without_cancelling() {
+        let cancellation = Cancellation::default();
+        let (sender, receiver) = channel::unbounded();
+        let result = cancellation.run(sender, |sender| {
+            sender.send(1).unwrap();
+            Ok(2)
+        });
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(receiver.iter().collect::<Vec<_>>(), vec![1]);
+        assert!(!cancellation.is_cancelled());
+    }
+}
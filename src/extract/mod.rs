@@ -0,0 +1,87 @@
+use crate::contig_filter::ContigFilter;
+use crate::ctg_lay_index::CtgLayIndex;
+use crate::error::{IoContext, Result};
+use crate::wtdbg2_ctg_lay::Wtdbg2CtgLayLine;
+use log::warn;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Copy the contigs selected by the filter from a .ctg.lay file to the output file, without modifying them.
+/// If contigs are selected by name only, then they are looked up in the contig index instead of scanning the whole input.
+/// Returns the number of copied contigs.
+pub fn extract_contigs<P1: AsRef<Path>, P2: AsRef<Path>>(
+    input_file: P1,
+    output_file: P2,
+    contig_filter: &ContigFilter,
+    io_buffer_size: usize,
+) -> Result<u64> {
+    let input_file = input_file.as_ref();
+    let output_file = output_file.as_ref();
+    let input_ranges = if let Some(names) = contig_filter.exact_names() {
+        let ctg_lay_index = CtgLayIndex::load_or_build(input_file, io_buffer_size)?;
+        let mut input_ranges = Vec::new();
+        for name in names {
+            if let Some(range) = ctg_lay_index.byte_range(name) {
+                input_ranges.push(range);
+            } else {
+                warn!("Contig {name} does not exist in the input");
+            }
+        }
+        input_ranges.sort_unstable_by_key(|range| range.start);
+        input_ranges
+    } else {
+        let whole_input = 0..u64::MAX;
+        vec![whole_input]
+    };
+
+    let mut reader = BufReader::with_capacity(
+        io_buffer_size,
+        File::open(input_file).io_context(|| format!("Could not open {input_file:?}"))?,
+    );
+    let mut writer = BufWriter::with_capacity(
+        io_buffer_size,
+        File::create(output_file).io_context(|| format!("Could not create {output_file:?}"))?,
+    );
+    let mut line = String::new();
+    let mut selected = false;
+    let mut contig_count = 0;
+    for input_range in input_ranges {
+        let mut input_offset = reader
+            .seek(SeekFrom::Start(input_range.start))
+            .io_context(|| format!("Could not seek in {input_file:?}"))?;
+        while input_offset < input_range.end {
+            line.clear();
+            let length = reader
+                .read_line(&mut line)
+                .io_context(|| format!("Could not read {input_file:?}"))?;
+            if length == 0 {
+                break;
+            }
+            input_offset += length as u64;
+
+            // Only contig lines are parsed, all other lines are copied as they are.
+            if line.starts_with('>') {
+                if let Wtdbg2CtgLayLine::Contig { name, length, .. } =
+                    Wtdbg2CtgLayLine::from_str(line.trim_end())?
+                {
+                    selected = contig_filter.is_selected(&name, length);
+                    if selected {
+                        contig_count += 1;
+                    }
+                }
+            }
+            if selected {
+                writer
+                    .write_all(line.as_bytes())
+                    .io_context(|| format!("Could not write to {output_file:?}"))?;
+            }
+        }
+    }
+
+    writer
+        .flush()
+        .io_context(|| format!("Could not write to {output_file:?}"))?;
+    Ok(contig_count)
+}
@@ -1,12 +1,17 @@
+use crate::error::{join_result, Error, IoContext, Result};
+use crate::fingerprint::file_fingerprint;
+use crate::progress::CountingReader;
 use bio::io::fasta;
 use crossbeam::channel;
 use crossbeam::thread::Scope;
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs::{File, OpenOptions};
-use std::io::{BufWriter, Write};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
 use std::os::unix::fs::FileExt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::slice;
+use std::sync::atomic::AtomicU64;
 
 struct FileSlice {
     offset: u64,
@@ -24,65 +29,76 @@ impl FastaSequenceIndex {
         input_file: P1,
         tmp_file: P2,
         io_buffer_size: usize,
-    ) -> Self {
-        let reader = fasta::Reader::with_capacity(io_buffer_size, File::open(input_file).unwrap());
-        let mut writer = BufWriter::with_capacity(
+    ) -> Result<Self> {
+        let input_file = input_file.as_ref();
+        let tmp_file = tmp_file.as_ref();
+        let reader = fasta::Reader::with_capacity(
             io_buffer_size,
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(tmp_file)
-                .unwrap(),
+            File::open(input_file).io_context(|| format!("Could not open {input_file:?}"))?,
         );
+        let mut writer = BufWriter::with_capacity(io_buffer_size, create_index_file(tmp_file)?);
         let mut index = HashMap::new();
 
         let mut offset = 0;
         for record in reader.records() {
-            let record = record.unwrap();
+            let record =
+                record.io_context(|| format!("Could not read fasta record from {input_file:?}"))?;
 
             let len = record.seq().len();
-            writer.write_all(record.seq()).unwrap();
+            writer
+                .write_all(record.seq())
+                .io_context(|| format!("Could not write to {tmp_file:?}"))?;
             // Write delimiter character to catch errors.
-            writer.write_all(&[b'\n']).unwrap();
+            writer
+                .write_all(b"\n")
+                .io_context(|| format!("Could not write to {tmp_file:?}"))?;
 
             let previous = index.insert(record.id().as_bytes().to_vec(), FileSlice { offset, len });
-            offset += u64::try_from(len).unwrap() + 1;
-            assert!(previous.is_none());
+            offset += len as u64 + 1;
+            if previous.is_some() {
+                return Err(duplicate_read_id_error(record.id()));
+            }
         }
 
-        Self {
-            file: writer.into_inner().unwrap(),
+        Ok(Self {
+            file: writer
+                .into_inner()
+                .map_err(|error| error.into_error())
+                .io_context(|| format!("Could not write to {tmp_file:?}"))?,
             index,
-        }
+        })
     }
 
-    #[allow(dead_code)]
-    pub fn build_parallel<P1: AsRef<Path>, P2: AsRef<Path>>(
+    /// Build the index with separate reader and writer threads.
+    /// The number of bytes read from the fasta file is counted in `read_bytes`.
+    pub fn build_parallel<'env, P1: AsRef<Path>, P2: AsRef<Path>>(
         input_file: P1,
         tmp_file: P2,
-        scope: &Scope,
+        scope: &Scope<'env>,
         channel_size: usize,
         io_buffer_size: usize,
-    ) -> Self {
-        let reader = fasta::Reader::with_capacity(io_buffer_size, File::open(input_file).unwrap());
-        let mut writer = BufWriter::with_capacity(
+        read_bytes: &'env AtomicU64,
+    ) -> Result<Self> {
+        let input_file = input_file.as_ref().to_owned();
+        let tmp_file = tmp_file.as_ref().to_owned();
+        let reader = fasta::Reader::with_capacity(
             io_buffer_size,
-            OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(tmp_file)
-                .unwrap(),
+            CountingReader::new(
+                File::open(&input_file).io_context(|| format!("Could not open {input_file:?}"))?,
+                read_bytes,
+            ),
         );
+        let mut writer = BufWriter::with_capacity(io_buffer_size, create_index_file(&tmp_file)?);
         let (sender, receiver) = channel::bounded(channel_size);
 
         // Reader thread.
-        scope.spawn(move |_| {
+        let reader_result = scope.spawn(move |_| {
             for record in reader.records() {
-                let record = record.unwrap();
-                sender.send(record).unwrap();
+                let record = record
+                    .io_context(|| format!("Could not read fasta record from {input_file:?}"))?;
+                sender.send(record).map_err(|_| Error::Cancelled)?;
             }
+            Ok(())
         });
 
         // Writer thread.
@@ -91,30 +107,133 @@ impl FastaSequenceIndex {
             let mut offset = 0;
             while let Ok(record) = receiver.recv() {
                 let len = record.seq().len();
-                writer.write_all(record.seq()).unwrap();
-                writer.write_all(&[b'\n']).unwrap();
+                writer
+                    .write_all(record.seq())
+                    .io_context(|| format!("Could not write to {tmp_file:?}"))?;
+                writer
+                    .write_all(b"\n")
+                    .io_context(|| format!("Could not write to {tmp_file:?}"))?;
 
                 let previous =
                     index.insert(record.id().as_bytes().to_vec(), FileSlice { offset, len });
-                offset += u64::try_from(len).unwrap() + 1;
-                assert!(
-                    previous.is_none(),
-                    "found duplicate read id: {}",
-                    record.id()
-                );
+                offset += len as u64 + 1;
+                if previous.is_some() {
+                    return Err(duplicate_read_id_error(record.id()));
+                }
             }
-            (writer, index)
+            let file = writer
+                .into_inner()
+                .map_err(|error| error.into_error())
+                .io_context(|| format!("Could not write to {tmp_file:?}"))?;
+            Ok((file, index))
         });
 
-        let (writer, index) = writer_result.join().unwrap();
-        Self {
-            file: writer.into_inner().unwrap(),
-            index,
+        // Join the writer first, since if it fails, then the reader fails as well.
+        let writer_result = join_result(writer_result.join(), "normal_index_writer");
+        let reader_result = join_result(reader_result.join(), "normal_index_reader");
+        let (file, index) = match (writer_result, reader_result) {
+            (_, Err(error)) if !error.is_cancelled() => return Err(error),
+            (writer_result, _) => writer_result?,
+        };
+        Ok(Self { file, index })
+    }
+
+    /// Write the read ids of the index next to the sequence file, such that [Self::load] can reuse it.
+    /// The ids file also identifies the fasta file the index was built from.
+    pub fn persist<P1: AsRef<Path>, P2: AsRef<Path>>(
+        &self,
+        input_file: P1,
+        index_file: P2,
+        io_buffer_size: usize,
+    ) -> Result<()> {
+        let ids_file = ids_path(index_file.as_ref());
+        let mut writer = BufWriter::with_capacity(
+            io_buffer_size,
+            File::create(&ids_file).io_context(|| format!("Could not create {ids_file:?}"))?,
+        );
+        writeln!(writer, "{}", file_fingerprint(input_file.as_ref())?)
+            .io_context(|| format!("Could not write to {ids_file:?}"))?;
+        for (id, FileSlice { offset, len }) in &self.index {
+            writer
+                .write_all(id)
+                .and_then(|()| writeln!(writer, "\t{offset}\t{len}"))
+                .io_context(|| format!("Could not write to {ids_file:?}"))?;
+        }
+        writer
+            .flush()
+            .io_context(|| format!("Could not write to {ids_file:?}"))
+    }
+
+    /// Load an index persisted with [Self::persist].
+    /// Returns `None` if the index does not exist or was built from a different or modified fasta file.
+    pub fn load<P1: AsRef<Path>, P2: AsRef<Path>>(
+        input_file: P1,
+        index_file: P2,
+        io_buffer_size: usize,
+    ) -> Result<Option<Self>> {
+        let index_file = index_file.as_ref();
+        let ids_file = ids_path(index_file);
+        let (file, ids) = match (File::open(index_file), File::open(&ids_file)) {
+            (Ok(file), Ok(ids)) => (file, ids),
+            (Err(error), _) | (_, Err(error)) if error.kind() == ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            (Err(error), _) | (_, Err(error)) => {
+                return Err(error).io_context(|| format!("Could not open {index_file:?}"))
+            }
+        };
+
+        let mut lines = BufReader::with_capacity(io_buffer_size, ids).lines();
+        let fingerprint = match lines.next() {
+            Some(fingerprint) => {
+                fingerprint.io_context(|| format!("Could not read {ids_file:?}"))?
+            }
+            None => return Ok(None),
+        };
+        if fingerprint != file_fingerprint(input_file.as_ref())? {
+            return Ok(None);
+        }
+
+        let mut index = HashMap::new();
+        let mut expected_file_length = 0;
+        for line in lines {
+            let line = line.io_context(|| format!("Could not read {ids_file:?}"))?;
+            let mut columns = line.rsplitn(3, '\t');
+            let (len, offset, id) = match (columns.next(), columns.next(), columns.next()) {
+                (Some(len), Some(offset), Some(id)) => (len, offset, id),
+                _ => return Err(malformed_ids_error(&ids_file)),
+            };
+            let len: usize = len.parse().map_err(|_| malformed_ids_error(&ids_file))?;
+            let offset: u64 = offset.parse().map_err(|_| malformed_ids_error(&ids_file))?;
+            expected_file_length = expected_file_length.max(offset + len as u64 + 1);
+            index.insert(id.as_bytes().to_vec(), FileSlice { offset, len });
         }
+
+        let file_length = file
+            .metadata()
+            .io_context(|| format!("Could not read metadata of {index_file:?}"))?
+            .len();
+        if file_length != expected_file_length {
+            return Ok(None);
+        }
+
+        Ok(Some(Self { file, index }))
     }
 
-    pub fn get_sequence(&mut self, id: &[u8], output: &mut Vec<u8>) {
-        let file_slice = self.index.get(id).unwrap();
+    /// The number of reads in the index.
+    pub fn read_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Read the sequence of the given read into `output`.
+    /// This uses positioned reads, so it can be called from multiple threads at once.
+    pub fn get_sequence(&self, id: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        let file_slice = self.index.get(id).ok_or_else(|| {
+            Error::Input(format!(
+                "Read {} is not contained in the normal reads",
+                String::from_utf8_lossy(id)
+            ))
+        })?;
         //output.resize(file_slice.len, 0);
         //self.file.read_exact_at(output, file_slice.offset).unwrap();
 
@@ -130,7 +249,110 @@ impl FastaSequenceIndex {
                 unsafe { slice::from_raw_parts_mut(buffer, file_slice.len) },
                 file_slice.offset,
             )
-            .unwrap();
+            .io_context(|| {
+                format!(
+                    "Could not read sequence of read {} from the normal reads index",
+                    String::from_utf8_lossy(id)
+                )
+            })?;
         unsafe { output.set_len(file_slice.len) };
+        Ok(())
+    }
+}
+
+fn create_index_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .io_context(|| format!("Could not create {path:?}"))
+}
+
+fn ids_path(index_file: &Path) -> PathBuf {
+    let mut ids_file = OsString::from(index_file.as_os_str());
+    ids_file.push(".ids");
+    ids_file.into()
+}
+
+fn malformed_ids_error(ids_file: &Path) -> Error {
+    Error::Input(format!("Malformed read index file {ids_file:?}"))
+}
+
+fn duplicate_read_id_error(id: &str) -> Error {
+    Error::Input(format!("Found duplicate read id: {id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fasta_sequence_index::{ids_path, FastaSequenceIndex};
+    use std::fs;
+    use std::path::PathBuf;
+
+    const READS: &str = ">read1\nACGT\n>read2\nGGTTA\n";
+
+    /// Write the reads and return their path and the path of their index.
+    fn write_reads(name: &str) -> (PathBuf, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "fasta_sequence_index_test_{}_{name}.fa",
+            std::process::id()
+        ));
+        fs::write(&path, READS).unwrap();
+        let mut index_path = path.clone().into_os_string();
+        index_path.push(".normal_index");
+        (path, index_path.into())
+    }
+
+    fn remove_reads(path: &PathBuf, index_path: &PathBuf) {
+        fs::remove_file(path).unwrap();
+        fs::remove_file(index_path).ok();
+        fs::remove_file(ids_path(index_path)).ok();
+    }
+
+    fn sequence(index: &FastaSequenceIndex, id: &[u8]) -> Vec<u8> {
+        let mut sequence = Vec::new();
+        index.get_sequence(id, &mut sequence).unwrap();
+        sequence
+    }
+
+    #[test]
+    fn test_persist_and_load() {
+        let (path, index_path) = write_reads("load");
+        let built = FastaSequenceIndex::build(&path, &index_path, 16).unwrap();
+        built.persist(&path, &index_path, 16).unwrap();
+
+        // A different spelling of the same path identifies the same reads.
+        let respelled_path = path
+            .parent()
+            .unwrap()
+            .join(".")
+            .join(path.file_name().unwrap());
+        let loaded = FastaSequenceIndex::load(&respelled_path, &index_path, 16)
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.read_count(), 2);
+        assert_eq!(sequence(&loaded, b"read1"), b"ACGT");
+        assert_eq!(sequence(&loaded, b"read2"), b"GGTTA");
+        assert!(loaded.get_sequence(b"read3", &mut Vec::new()).is_err());
+        remove_reads(&path, &index_path);
+    }
+
+    #[test]
+    fn test_load_stale() {
+        let (path, index_path) = write_reads("stale");
+        assert!(FastaSequenceIndex::load(&path, &index_path, 16)
+            .unwrap()
+            .is_none());
+        FastaSequenceIndex::build(&path, &index_path, 16)
+            .unwrap()
+            .persist(&path, &index_path, 16)
+            .unwrap();
+        // Changing the size of the reads changes their fingerprint.
+        fs::write(&path, &READS[..11]).unwrap();
+        assert!(FastaSequenceIndex::load(&path, &index_path, 16)
+            .unwrap()
+            .is_none());
+        remove_reads(&path, &index_path);
     }
 }
@@ -0,0 +1,27 @@
+use crate::error::{IoContext, Result};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Identify a file by its canonical path, size and modification time.
+/// Persisted indices store the fingerprint of the file they were built from, to detect when they are outdated.
+/// The path is canonicalised, such that e.g. `reads.fa` and `./reads.fa` have the same fingerprint.
+pub fn file_fingerprint(path: &Path) -> Result<String> {
+    let canonical_path = path
+        .canonicalize()
+        .io_context(|| format!("Could not resolve {path:?}"))?;
+    let metadata = path
+        .metadata()
+        .io_context(|| format!("Could not read metadata of {path:?}"))?;
+    let modified = metadata
+        .modified()
+        .io_context(|| format!("Could not read modification time of {path:?}"))?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!(
+        "# source={} size={} modified={}.{:09}",
+        canonical_path.to_string_lossy(),
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    ))
+}
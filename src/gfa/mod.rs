@@ -0,0 +1,152 @@
+use crate::layout::ContigLayout;
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Writes the layout graph as GFA 1.0, with the wtdbg2 nodes as segments, the edges as links and the contigs as paths.
+/// Segments have no sequence. Their length is the distance from their position to the position of the next node
+/// of their contig, where each node is placed at the offset of its outgoing edge,
+/// and the last node of a contig is placed at the end of the contig and has length zero.
+/// The position is stored in the rGFA tags `SN` and `SO`.
+/// Nodes that occur in multiple contigs are written only once, at their first occurrence.
+pub struct GfaWriter<W> {
+    writer: W,
+    segments: HashSet<String>,
+    link_count: u64,
+    path_count: u64,
+}
+
+impl<W: Write> GfaWriter<W> {
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writeln!(writer, "H\tVN:Z:1.0")?;
+        Ok(Self {
+            writer,
+            segments: HashSet::new(),
+            link_count: 0,
+            path_count: 0,
+        })
+    }
+
+    pub fn add_contig(&mut self, contig: &ContigLayout) -> std::io::Result<()> {
+        let (first_edge, last_edge) = match (contig.edges.first(), contig.edges.last()) {
+            (Some(first_edge), Some(last_edge)) => (first_edge, last_edge),
+            // A contig without edges does not name its nodes.
+            _ => return Ok(()),
+        };
+
+        let mut path = Vec::with_capacity(contig.edges.len() + 1);
+        path.push((first_edge.from_node.as_str(), first_edge.from_direction));
+        for (index, edge) in contig.edges.iter().enumerate() {
+            let next_offset = contig
+                .edges
+                .get(index + 1)
+                .map(|next_edge| next_edge.offset)
+                .unwrap_or(contig.length);
+            self.write_segment(
+                &edge.from_node,
+                next_offset.saturating_sub(edge.offset),
+                &contig.name,
+                edge.offset,
+            )?;
+            path.push((edge.to_node.as_str(), edge.to_direction));
+        }
+        self.write_segment(&last_edge.to_node, 0, &contig.name, contig.length)?;
+
+        for edge in &contig.edges {
+            writeln!(
+                self.writer,
+                "L\t{}\t{}\t{}\t{}\t0M",
+                edge.from_node,
+                direction_str(edge.from_direction),
+                edge.to_node,
+                direction_str(edge.to_direction)
+            )?;
+            self.link_count += 1;
+        }
+
+        write!(self.writer, "P\t{}\t", contig.name)?;
+        for (index, (node, direction)) in path.iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            write!(
+                self.writer,
+                "{separator}{node}{}",
+                direction_str(*direction)
+            )?;
+        }
+        writeln!(self.writer, "\t*")?;
+        self.path_count += 1;
+        Ok(())
+    }
+
+    /// Flush the output and return the number of segments, links and paths.
+    pub fn finish(mut self) -> std::io::Result<(usize, u64, u64)> {
+        self.writer.flush()?;
+        Ok((self.segments.len(), self.link_count, self.path_count))
+    }
+
+    fn write_segment(
+        &mut self,
+        name: &str,
+        length: u64,
+        contig_name: &str,
+        offset: u64,
+    ) -> std::io::Result<()> {
+        if self.segments.insert(name.to_owned()) {
+            writeln!(
+                self.writer,
+                "S\t{name}\t*\tLN:i:{length}\tSN:Z:{contig_name}\tSO:i:{offset}"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn direction_str(direction: bool) -> &'static str {
+    if direction {
+        "+"
+    } else {
+        "-"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gfa::GfaWriter;
+    use crate::layout::{ContigLayout, EdgeLayout};
+
+    #[test]
+    fn test_gfa_writer() {
+        let edge =
+            |offset, from_node: &str, from_direction, to_node: &str, to_direction| EdgeLayout {
+                offset,
+                from_node: from_node.to_string(),
+                from_direction,
+                to_node: to_node.to_string(),
+                to_direction,
+                alignments: Vec::new(),
+            };
+        let contig = ContigLayout {
+            name: "ctg1".to_string(),
+            node_count: 3,
+            length: 250,
+            edges: vec![
+                edge(0, "N1", true, "N2", false),
+                edge(100, "N2", false, "N3", true),
+            ],
+        };
+
+        let mut output = Vec::new();
+        let mut gfa_writer = GfaWriter::new(&mut output).unwrap();
+        gfa_writer.add_contig(&contig).unwrap();
+        assert_eq!(gfa_writer.finish().unwrap(), (3, 2, 1));
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "H\tVN:Z:1.0\n\
+             S\tN1\t*\tLN:i:100\tSN:Z:ctg1\tSO:i:0\n\
+             S\tN2\t*\tLN:i:150\tSN:Z:ctg1\tSO:i:100\n\
+             S\tN3\t*\tLN:i:0\tSN:Z:ctg1\tSO:i:250\n\
+             L\tN1\t+\tN2\t-\t0M\n\
+             L\tN2\t-\tN3\t+\t0M\n\
+             P\tctg1\tN1+,N2-,N3+\t*\n"
+        );
+    }
+}
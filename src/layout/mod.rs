@@ -0,0 +1,178 @@
+use crate::error::{Error, IoContext, Result};
+use crate::wtdbg2_ctg_lay::Wtdbg2CtgLayLine;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::path::Path;
+
+/// A contig of a .ctg.lay file with all of its edges and alignments, without the embedded sequences.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContigLayout {
+    pub name: String,
+    pub node_count: u64,
+    pub length: u64,
+    pub edges: Vec<EdgeLayout>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct EdgeLayout {
+    pub offset: u64,
+    pub from_node: String,
+    /// True for forwards (+), false for backwards (-).
+    pub from_direction: bool,
+    pub to_node: String,
+    /// True for forwards (+), false for backwards (-).
+    pub to_direction: bool,
+    pub alignments: Vec<AlignmentLayout>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AlignmentLayout {
+    pub read_id: Vec<u8>,
+    /// True for forwards (+), false for backwards (-).
+    pub direction: bool,
+    pub offset: usize,
+    pub length: usize,
+}
+
+/// Reads the contigs of a .ctg.lay file one after the other.
+pub struct ContigLayoutReader<R> {
+    lines: Lines<R>,
+    line_number: u64,
+    current_contig: Option<ContigLayout>,
+    failed: bool,
+}
+
+impl ContigLayoutReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(input_file: P, io_buffer_size: usize) -> Result<Self> {
+        let input_file = input_file.as_ref();
+        Ok(Self::new(BufReader::with_capacity(
+            io_buffer_size,
+            File::open(input_file).io_context(|| format!("Could not open {input_file:?}"))?,
+        )))
+    }
+}
+
+impl<R: BufRead> ContigLayoutReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            line_number: 0,
+            current_contig: None,
+            failed: false,
+        }
+    }
+
+    /// Read lines until the current contig is complete.
+    fn next_contig(&mut self) -> Result<Option<ContigLayout>> {
+        for line in &mut self.lines {
+            self.line_number += 1;
+            let line_number = self.line_number;
+            let line = line.io_context(|| format!("Could not read line {line_number}"))?;
+            let line = Wtdbg2CtgLayLine::parse(line.trim_end_matches('\r'), false)
+                .map_err(|error| error.at(format_args!("Line {line_number}")))?;
+
+            match line {
+                Wtdbg2CtgLayLine::Contig {
+                    name,
+                    node_count,
+                    length,
+                } => {
+                    let next_contig = ContigLayout {
+                        name,
+                        node_count,
+                        length,
+                        edges: Vec::new(),
+                    };
+                    if let Some(contig) = self.current_contig.replace(next_contig) {
+                        return Ok(Some(contig));
+                    }
+                }
+                Wtdbg2CtgLayLine::Edge {
+                    offset,
+                    from_node,
+                    from_direction,
+                    to_node,
+                    to_direction,
+                    ..
+                } => {
+                    let contig = self.current_contig.as_mut().ok_or_else(|| {
+                        Error::Input(format!("Line {line_number}: edge before the first contig"))
+                    })?;
+                    contig.edges.push(EdgeLayout {
+                        offset,
+                        from_node,
+                        from_direction,
+                        to_node,
+                        to_direction,
+                        alignments: Vec::new(),
+                    });
+                }
+                Wtdbg2CtgLayLine::Alignment {
+                    read_id,
+                    direction,
+                    offset,
+                    length,
+                    ..
+                } => {
+                    let edge = self
+                        .current_contig
+                        .as_mut()
+                        .and_then(|contig| contig.edges.last_mut())
+                        .ok_or_else(|| {
+                            Error::Input(format!(
+                                "Line {line_number}: alignment before the first edge"
+                            ))
+                        })?;
+                    edge.alignments.push(AlignmentLayout {
+                        read_id,
+                        direction,
+                        offset,
+                        length,
+                    });
+                }
+            }
+        }
+        Ok(self.current_contig.take())
+    }
+}
+
+impl<R: BufRead> Iterator for ContigLayoutReader<R> {
+    type Item = Result<ContigLayout>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Do not continue in the middle of a contig after an error.
+        if self.failed {
+            return None;
+        }
+        let result = self.next_contig();
+        self.failed = result.is_err();
+        result.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout::ContigLayoutReader;
+
+    #[test]
+    fn test_contig_layout_reader() {
+        let input = ">ctg1 nodes=2 len=100\n\
+                     E\t0\tN1\t+\tN2\t-\n\
+                     S\tread1\t+\t0\t4\tACGT\n\
+                     S\tread2\t-\t3\t2\tAC\n\
+                     >ctg2 nodes=1 len=10\n";
+        let contigs: Vec<_> = ContigLayoutReader::new(input.as_bytes())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(contigs.len(), 2);
+        assert_eq!(contigs[0].edges.len(), 1);
+        assert_eq!(contigs[0].edges[0].alignments[1].read_id, b"read2");
+        assert!(!contigs[0].edges[0].alignments[1].direction);
+        assert_eq!(contigs[1].name, "ctg2");
+        assert!(contigs[1].edges.is_empty());
+
+        let mut contigs = ContigLayoutReader::new("S\tread1\t+\t0\t4\tACGT\n".as_bytes());
+        assert!(contigs.next().unwrap().is_err());
+        assert!(contigs.next().is_none());
+    }
+}
@@ -0,0 +1,499 @@
+use crate::error::{Error, IoContext, Result};
+use crate::layout::{ContigLayout, EdgeLayout};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// The kind of a difference between two layouts.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DifferenceKind {
+    ContigRemoved,
+    ContigAdded,
+    ContigLength,
+    EdgeRemoved,
+    EdgeAdded,
+    EdgeOffset,
+    EdgeOrientation,
+    ReadRemoved,
+    ReadAdded,
+    ReadOrientation,
+}
+
+impl DifferenceKind {
+    pub const ALL: [DifferenceKind; 10] = [
+        DifferenceKind::ContigRemoved,
+        DifferenceKind::ContigAdded,
+        DifferenceKind::ContigLength,
+        DifferenceKind::EdgeRemoved,
+        DifferenceKind::EdgeAdded,
+        DifferenceKind::EdgeOffset,
+        DifferenceKind::EdgeOrientation,
+        DifferenceKind::ReadRemoved,
+        DifferenceKind::ReadAdded,
+        DifferenceKind::ReadOrientation,
+    ];
+}
+
+impl Display for DifferenceKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let key = match self {
+            DifferenceKind::ContigRemoved => "contig_removed",
+            DifferenceKind::ContigAdded => "contig_added",
+            DifferenceKind::ContigLength => "contig_length",
+            DifferenceKind::EdgeRemoved => "edge_removed",
+            DifferenceKind::EdgeAdded => "edge_added",
+            DifferenceKind::EdgeOffset => "edge_offset",
+            DifferenceKind::EdgeOrientation => "edge_orientation",
+            DifferenceKind::ReadRemoved => "read_removed",
+            DifferenceKind::ReadAdded => "read_added",
+            DifferenceKind::ReadOrientation => "read_orientation",
+        };
+        write!(f, "{key}")
+    }
+}
+
+/// A difference between the old and the new layout.
+/// Fields that do not apply to the kind of the difference are empty.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Difference {
+    pub kind: DifferenceKind,
+    pub contig: String,
+    /// The node pair of the edge, e.g. `N1+:N2-`.
+    /// Repeated occurrences of a node pair within a contig are numbered from the second occurrence on,
+    /// e.g. `N1+:N2-#2`.
+    pub edge: String,
+    pub read: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// Compare two layouts, matching contigs by name, edges by their node pair and alignments by their read.
+/// If a node pair occurs multiple times within a contig, then its occurrences are matched in order.
+/// Contigs are reported in the order of the old layout, followed by the contigs only in the new layout.
+/// Fails if a contig name occurs more than once in a layout, since such contigs cannot be matched.
+pub fn diff_layouts(old: &[ContigLayout], new: &[ContigLayout]) -> Result<Vec<Difference>> {
+    let mut differences = Vec::new();
+    let new_by_name = contigs_by_name(new, "new")?;
+    let old_by_name = contigs_by_name(old, "old")?;
+
+    for old_contig in old {
+        if let Some(new_contig) = new_by_name.get(old_contig.name.as_str()) {
+            diff_contigs(old_contig, new_contig, &mut differences);
+        } else {
+            differences.push(difference(
+                DifferenceKind::ContigRemoved,
+                &old_contig.name,
+                "",
+                "",
+                old_contig.length,
+                "",
+            ));
+        }
+    }
+    for new_contig in new {
+        if !old_by_name.contains_key(new_contig.name.as_str()) {
+            differences.push(difference(
+                DifferenceKind::ContigAdded,
+                &new_contig.name,
+                "",
+                "",
+                "",
+                new_contig.length,
+            ));
+        }
+    }
+    Ok(differences)
+}
+
+fn contigs_by_name<'layout>(
+    layout: &'layout [ContigLayout],
+    layout_name: &str,
+) -> Result<HashMap<&'layout str, &'layout ContigLayout>> {
+    let mut contigs_by_name = HashMap::new();
+    for contig in layout {
+        if contigs_by_name
+            .insert(contig.name.as_str(), contig)
+            .is_some()
+        {
+            return Err(Error::Input(format!(
+                "The {layout_name} layout contains contig {} more than once",
+                contig.name
+            )));
+        }
+    }
+    Ok(contigs_by_name)
+}
+
+fn diff_contigs(old: &ContigLayout, new: &ContigLayout, differences: &mut Vec<Difference>) {
+    let contig = &old.name;
+    if old.length != new.length {
+        differences.push(difference(
+            DifferenceKind::ContigLength,
+            contig,
+            "",
+            "",
+            old.length,
+            new.length,
+        ));
+    }
+
+    let old_keys = edge_keys(&old.edges);
+    let new_keys = edge_keys(&new.edges);
+    let new_by_key: HashMap<_, _> = new_keys.iter().cloned().zip(&new.edges).collect();
+    let old_key_set: HashSet<_> = old_keys.iter().cloned().collect();
+
+    for (old_key, old_edge) in old_keys.iter().zip(&old.edges) {
+        let edge = edge_name(old_edge, old_key.2);
+        let new_edge = if let Some(new_edge) = new_by_key.get(old_key) {
+            new_edge
+        } else {
+            differences.push(difference(
+                DifferenceKind::EdgeRemoved,
+                contig,
+                &edge,
+                "",
+                old_edge.offset,
+                "",
+            ));
+            continue;
+        };
+
+        if old_edge.offset != new_edge.offset {
+            differences.push(difference(
+                DifferenceKind::EdgeOffset,
+                contig,
+                &edge,
+                "",
+                old_edge.offset,
+                new_edge.offset,
+            ));
+        }
+        if (old_edge.from_direction, old_edge.to_direction)
+            != (new_edge.from_direction, new_edge.to_direction)
+        {
+            differences.push(difference(
+                DifferenceKind::EdgeOrientation,
+                contig,
+                &edge,
+                "",
+                &edge,
+                edge_name(new_edge, old_key.2),
+            ));
+        }
+        diff_reads(contig, &edge, old_edge, new_edge, differences);
+    }
+    for (new_key, new_edge) in new_keys.iter().zip(&new.edges) {
+        if !old_key_set.contains(new_key) {
+            differences.push(difference(
+                DifferenceKind::EdgeAdded,
+                contig,
+                &edge_name(new_edge, new_key.2),
+                "",
+                "",
+                new_edge.offset,
+            ));
+        }
+    }
+}
+
+/// Compare the reads aligned to an edge. If a read is aligned multiple times, then its first alignment is compared.
+fn diff_reads(
+    contig: &str,
+    edge: &str,
+    old: &EdgeLayout,
+    new: &EdgeLayout,
+    differences: &mut Vec<Difference>,
+) {
+    let mut new_directions = HashMap::new();
+    for alignment in &new.alignments {
+        new_directions
+            .entry(alignment.read_id.as_slice())
+            .or_insert(alignment.direction);
+    }
+    let old_reads: HashSet<_> = old
+        .alignments
+        .iter()
+        .map(|alignment| alignment.read_id.as_slice())
+        .collect();
+
+    let mut reported = HashSet::new();
+    for alignment in &old.alignments {
+        let read_id = alignment.read_id.as_slice();
+        if !reported.insert(read_id) {
+            continue;
+        }
+        let read = String::from_utf8_lossy(read_id);
+        match new_directions.get(read_id) {
+            None => differences.push(difference(
+                DifferenceKind::ReadRemoved,
+                contig,
+                edge,
+                &read,
+                direction_str(alignment.direction),
+                "",
+            )),
+            Some(&new_direction) if new_direction != alignment.direction => {
+                differences.push(difference(
+                    DifferenceKind::ReadOrientation,
+                    contig,
+                    edge,
+                    &read,
+                    direction_str(alignment.direction),
+                    direction_str(new_direction),
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    for alignment in &new.alignments {
+        let read_id = alignment.read_id.as_slice();
+        if !old_reads.contains(read_id) && reported.insert(read_id) {
+            differences.push(difference(
+                DifferenceKind::ReadAdded,
+                contig,
+                edge,
+                &String::from_utf8_lossy(read_id),
+                "",
+                direction_str(alignment.direction),
+            ));
+        }
+    }
+}
+
+fn difference<Old: ToString, New: ToString>(
+    kind: DifferenceKind,
+    contig: &str,
+    edge: &str,
+    read: &str,
+    old: Old,
+    new: New,
+) -> Difference {
+    Difference {
+        kind,
+        contig: contig.to_owned(),
+        edge: edge.to_owned(),
+        read: read.to_owned(),
+        old: old.to_string(),
+        new: new.to_string(),
+    }
+}
+
+/// Key each edge by its node pair and the number of earlier edges with the same node pair.
+fn edge_keys(edges: &[EdgeLayout]) -> Vec<(&str, &str, usize)> {
+    let mut occurrences = HashMap::new();
+    edges
+        .iter()
+        .map(|edge| {
+            let node_pair = (edge.from_node.as_str(), edge.to_node.as_str());
+            let occurrence = occurrences.entry(node_pair).or_insert(0);
+            *occurrence += 1;
+            (node_pair.0, node_pair.1, *occurrence - 1)
+        })
+        .collect()
+}
+
+fn edge_name(edge: &EdgeLayout, occurrence: usize) -> String {
+    let name = format!(
+        "{}{}:{}{}",
+        edge.from_node,
+        direction_str(edge.from_direction),
+        edge.to_node,
+        direction_str(edge.to_direction)
+    );
+    if occurrence == 0 {
+        name
+    } else {
+        format!("{name}#{}", occurrence + 1)
+    }
+}
+
+fn direction_str(direction: bool) -> &'static str {
+    if direction {
+        "+"
+    } else {
+        "-"
+    }
+}
+
+/// Write the differences as tab-separated values to the given file, or to standard output if no file is given.
+pub fn write_differences<P: AsRef<Path>>(
+    differences: &[Difference],
+    path: Option<P>,
+) -> Result<()> {
+    let (mut writer, destination): (Box<dyn Write>, _) = if let Some(path) = &path {
+        let path = path.as_ref();
+        (
+            Box::new(BufWriter::new(
+                File::create(path).io_context(|| format!("Could not create {path:?}"))?,
+            )),
+            format!("{path:?}"),
+        )
+    } else {
+        (
+            Box::new(BufWriter::new(std::io::stdout())),
+            "standard output".to_string(),
+        )
+    };
+
+    writeln!(writer, "kind\tcontig\tedge\tread\told\tnew")
+        .and_then(|()| {
+            for difference in differences {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    difference.kind,
+                    difference.contig,
+                    difference.edge,
+                    difference.read,
+                    difference.old,
+                    difference.new
+                )?;
+            }
+            writer.flush()
+        })
+        .io_context(|| format!("Could not write to {destination}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::layout::{AlignmentLayout, ContigLayout, EdgeLayout};
+    use crate::layout_diff::{diff_layouts, DifferenceKind};
+
+    fn contig(name: &str, length: u64, edges: Vec<EdgeLayout>) -> ContigLayout {
+        ContigLayout {
+            name: name.to_string(),
+            node_count: edges.len() as u64 + 1,
+            length,
+            edges,
+        }
+    }
+
+    fn edge(offset: u64, from_node: &str, to_node: &str, reads: &[(&str, bool)]) -> EdgeLayout {
+        EdgeLayout {
+            offset,
+            from_node: from_node.to_string(),
+            from_direction: true,
+            to_node: to_node.to_string(),
+            to_direction: true,
+            alignments: reads
+                .iter()
+                .map(|&(read_id, direction)| AlignmentLayout {
+                    read_id: read_id.as_bytes().to_vec(),
+                    direction,
+                    offset: 0,
+                    length: 10,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_layouts() {
+        let old = vec![
+            contig(
+                "ctg1",
+                100,
+                vec![
+                    edge(0, "N1", "N2", &[("r1", true), ("r2", true)]),
+                    edge(50, "N2", "N3", &[("r3", true)]),
+                ],
+            ),
+            contig("ctg2", 10, Vec::new()),
+        ];
+        let new = vec![
+            contig(
+                "ctg1",
+                120,
+                vec![
+                    edge(0, "N1", "N2", &[("r1", false), ("r4", true)]),
+                    edge(60, "N2", "N4", &[("r3", true)]),
+                ],
+            ),
+            contig("ctg3", 10, Vec::new()),
+        ];
+
+        let kinds: Vec<_> = diff_layouts(&old, &new)
+            .unwrap()
+            .iter()
+            .map(|difference| difference.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                DifferenceKind::ContigLength,
+                DifferenceKind::ReadOrientation,
+                DifferenceKind::ReadRemoved,
+                DifferenceKind::ReadAdded,
+                DifferenceKind::EdgeRemoved,
+                DifferenceKind::EdgeAdded,
+                DifferenceKind::ContigRemoved,
+                DifferenceKind::ContigAdded,
+            ]
+        );
+        assert!(diff_layouts(&old, &old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_repeated_node_pairs() {
+        let old = vec![contig(
+            "ctg1",
+            100,
+            vec![
+                edge(0, "N1", "N2", &[("r1", true)]),
+                edge(30, "N2", "N1", &[("r2", true)]),
+                edge(60, "N1", "N2", &[("r3", true)]),
+            ],
+        )];
+        let new = vec![contig(
+            "ctg1",
+            100,
+            vec![
+                edge(0, "N1", "N2", &[("r1", true)]),
+                edge(30, "N2", "N1", &[("r2", true)]),
+                edge(70, "N1", "N2", &[("r4", true)]),
+                edge(90, "N1", "N2", &[("r5", true)]),
+            ],
+        )];
+
+        let differences: Vec<_> = diff_layouts(&old, &new)
+            .unwrap()
+            .into_iter()
+            .map(|difference| (difference.kind, difference.edge, difference.read))
+            .collect();
+        let expected = [
+            (DifferenceKind::EdgeOffset, "N1+:N2+#2", ""),
+            (DifferenceKind::ReadRemoved, "N1+:N2+#2", "r3"),
+            (DifferenceKind::ReadAdded, "N1+:N2+#2", "r4"),
+            (DifferenceKind::EdgeAdded, "N1+:N2+#3", ""),
+        ];
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(kind, edge, read)| (kind, edge.to_string(), read.to_string()))
+            .collect();
+        assert_eq!(differences, expected);
+        assert!(diff_layouts(&new, &new).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_duplicate_contig_names() {
+        let layout = vec![
+            contig("ctg1", 10, Vec::new()),
+            contig("ctg2", 10, Vec::new()),
+        ];
+        let duplicated = vec![
+            contig("ctg1", 10, Vec::new()),
+            contig("ctg1", 20, Vec::new()),
+        ];
+        assert!(matches!(
+            diff_layouts(&layout, &duplicated),
+            Err(Error::Input(_))
+        ));
+        assert!(matches!(
+            diff_layouts(&duplicated, &layout),
+            Err(Error::Input(_))
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::error::{Error, IoContext, Result};
+use crate::summary::{format_ratio, Summary, Table};
+use crate::wtdbg2_ctg_lay::Wtdbg2CtgLayLine;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// The size of a contig as given in a .ctg.lay file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct LayoutContig {
+    pub name: String,
+    pub node_count: u64,
+    /// The length given in the contig line.
+    pub length: u64,
+    pub edge_count: u64,
+    pub alignment_count: u64,
+    /// The sum of the lengths of the alignments.
+    pub aligned_bases: u64,
+}
+
+/// Summarises the contigs of a .ctg.lay file, without looking at the reads.
+#[derive(Default)]
+pub struct LayoutStatistics {
+    contigs: Vec<LayoutContig>,
+}
+
+impl LayoutStatistics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Collect the statistics of all lines of the given .ctg.lay file.
+    pub fn from_file<P: AsRef<Path>>(input_file: P, io_buffer_size: usize) -> Result<Self> {
+        let input_file = input_file.as_ref();
+        let reader = BufReader::with_capacity(
+            io_buffer_size,
+            File::open(input_file).io_context(|| format!("Could not open {input_file:?}"))?,
+        );
+        let mut statistics = Self::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line.io_context(|| format!("Could not read {input_file:?}"))?;
+            Wtdbg2CtgLayLine::parse(line.trim_end_matches('\r'), false)
+                .and_then(|line| statistics.add_line(&line))
+                .map_err(|error| error.at(format_args!("Line {}", index + 1)))?;
+        }
+        Ok(statistics)
+    }
+
+    pub fn add_line(&mut self, line: &Wtdbg2CtgLayLine) -> Result<()> {
+        if let Wtdbg2CtgLayLine::Contig {
+            name,
+            node_count,
+            length,
+        } = line
+        {
+            self.contigs.push(LayoutContig {
+                name: name.clone(),
+                node_count: *node_count,
+                length: *length,
+                ..Default::default()
+            });
+            return Ok(());
+        }
+
+        let contig = self
+            .contigs
+            .last_mut()
+            .ok_or_else(|| Error::Input("line before the first contig".to_string()))?;
+        if let Wtdbg2CtgLayLine::Alignment { length, .. } = line {
+            contig.alignment_count += 1;
+            contig.aligned_bases += *length as u64;
+        } else {
+            contig.edge_count += 1;
+        }
+        Ok(())
+    }
+
+    /// Write the statistics as JSON if the path does not end in `.tsv`,
+    /// and as tab-separated values otherwise or to standard output if no path is given.
+    pub fn write<P: AsRef<Path>>(&self, path: Option<P>) -> Result<()> {
+        self.to_summary().write(path)
+    }
+
+    fn summary(&self) -> Vec<(&'static str, String)> {
+        let sum = |value: fn(&LayoutContig) -> u64| self.contigs.iter().map(value).sum::<u64>();
+        let total_length = sum(|contig| contig.length);
+        let aligned_bases = sum(|contig| contig.aligned_bases);
+        let lengths: Vec<_> = self.contigs.iter().map(|contig| contig.length).collect();
+        vec![
+            ("contigs", self.contigs.len().to_string()),
+            ("nodes", sum(|contig| contig.node_count).to_string()),
+            ("edges", sum(|contig| contig.edge_count).to_string()),
+            (
+                "alignments",
+                sum(|contig| contig.alignment_count).to_string(),
+            ),
+            ("total_length", total_length.to_string()),
+            (
+                "longest_contig",
+                lengths.iter().max().unwrap_or(&0).to_string(),
+            ),
+            ("n50", n50(lengths).to_string()),
+            ("aligned_bases", aligned_bases.to_string()),
+            ("coverage", format_ratio(aligned_bases, total_length)),
+        ]
+    }
+
+    fn to_summary(&self) -> Summary {
+        let contigs = Table {
+            key: "contig_statistics",
+            row_kind: "contig",
+            columns: vec![
+                "nodes",
+                "length",
+                "edges",
+                "alignments",
+                "aligned_bases",
+                "coverage",
+            ],
+            rows: self
+                .contigs
+                .iter()
+                .map(|contig| {
+                    (
+                        contig.name.clone(),
+                        vec![
+                            contig.node_count.to_string(),
+                            contig.length.to_string(),
+                            contig.edge_count.to_string(),
+                            contig.alignment_count.to_string(),
+                            contig.aligned_bases.to_string(),
+                            format_ratio(contig.aligned_bases, contig.length),
+                        ],
+                    )
+                })
+                .collect(),
+        };
+        Summary {
+            values: self.summary(),
+            tables: vec![contigs],
+        }
+    }
+}
+
+/// The largest length such that the contigs of at least this length make up half of the total length.
+pub fn n50(mut lengths: Vec<u64>) -> u64 {
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    let total_length: u64 = lengths.iter().sum();
+    let mut cumulative_length = 0;
+    for length in lengths {
+        cumulative_length += length;
+        if cumulative_length * 2 >= total_length {
+            return length;
+        }
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::layout_statistics::n50;
+
+    #[test]
+    fn test_n50() {
+        assert_eq!(n50(Vec::new()), 0);
+        assert_eq!(n50(vec![10]), 10);
+        assert_eq!(n50(vec![2, 3, 4, 5, 6, 7, 8, 9, 10]), 8);
+        assert_eq!(n50(vec![1, 1, 8]), 8);
+    }
+}
@@ -0,0 +1,474 @@
+use crate::error::{Error, IoContext, Result};
+use crate::record_writer::RecordFile;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// An ungapped block of a chain, mapping `size` positions from `from_start` on to `to_start` on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChainBlock {
+    pub from_start: u64,
+    pub to_start: u64,
+    pub size: u64,
+}
+
+/// The mapping from the coordinates of a contig in the input to the coordinates of the same contig in the output,
+/// in the terms of a UCSC chain with the input as target and the output as query, both on the forward strand.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Chain {
+    pub from_name: String,
+    pub from_length: u64,
+    pub to_name: String,
+    pub to_length: u64,
+    /// Sorted and non-overlapping in both coordinate systems.
+    pub blocks: Vec<ChainBlock>,
+}
+
+impl Chain {
+    /// Build the chain of a contig from the input and output offsets of its edges.
+    /// The interval between two edges is rescaled like the edge offsets, i.e. each position of the shorter side
+    /// is mapped to its proportional position on the longer side, and the remaining positions of the longer side
+    /// are small gaps spread evenly across the interval. So a lifted position is off by less than one position
+    /// from the linear interpolation between the edges.
+    /// Anchors that would map backwards are ignored.
+    pub fn from_anchors(
+        name: &str,
+        from_length: u64,
+        to_length: u64,
+        anchors: &[(u64, u64)],
+    ) -> Self {
+        let mut blocks: Vec<ChainBlock> = Vec::new();
+        let mut previous = (0, 0);
+        for &(from_offset, to_offset) in anchors.iter().chain(Some(&(from_length, to_length))) {
+            if from_offset < previous.0 || to_offset < previous.1 {
+                continue;
+            }
+            let from_size = from_offset - previous.0;
+            let to_size = to_offset - previous.1;
+            let mapped_size = from_size.min(to_size);
+            let longer_size = from_size.max(to_size);
+            for index in 0..mapped_size {
+                let scaled_index =
+                    (u128::from(index) * u128::from(longer_size) / u128::from(mapped_size)) as u64;
+                let (from_index, to_index) = if from_size > to_size {
+                    (scaled_index, index)
+                } else {
+                    (index, scaled_index)
+                };
+                push_position(&mut blocks, previous.0 + from_index, previous.1 + to_index);
+            }
+            previous = (from_offset, to_offset);
+        }
+
+        Self {
+            from_name: name.to_owned(),
+            from_length,
+            to_name: name.to_owned(),
+            to_length,
+            blocks,
+        }
+    }
+
+    /// Map a position of the input contig to the output contig, or return `None` if it lies in a gap.
+    pub fn lift(&self, position: u64) -> Option<u64> {
+        let index = self
+            .blocks
+            .partition_point(|block| block.from_start <= position)
+            .checked_sub(1)?;
+        let block = &self.blocks[index];
+        (position < block.from_start + block.size)
+            .then(|| block.to_start + (position - block.from_start))
+    }
+
+    /// Write the chain in UCSC chain format, scored by the number of mapped positions.
+    /// Chains without blocks are not written, since the format cannot represent them.
+    pub fn write<W: Write>(&self, writer: &mut W, id: u64) -> std::io::Result<()> {
+        let (first_block, last_block) = match (self.blocks.first(), self.blocks.last()) {
+            (Some(first_block), Some(last_block)) => (first_block, last_block),
+            _ => return Ok(()),
+        };
+        let score: u64 = self.blocks.iter().map(|block| block.size).sum();
+        writeln!(
+            writer,
+            "chain {score} {} {} + {} {} {} {} + {} {} {id}",
+            self.from_name,
+            self.from_length,
+            first_block.from_start,
+            last_block.from_start + last_block.size,
+            self.to_name,
+            self.to_length,
+            first_block.to_start,
+            last_block.to_start + last_block.size,
+        )?;
+        for (block, next_block) in self.blocks.iter().zip(&self.blocks[1..]) {
+            writeln!(
+                writer,
+                "{}\t{}\t{}",
+                block.size,
+                next_block.from_start - block.from_start - block.size,
+                next_block.to_start - block.to_start - block.size
+            )?;
+        }
+        writeln!(writer, "{}\n", last_block.size)
+    }
+}
+
+/// Map a single position, extending the last block if the position continues it.
+fn push_position(blocks: &mut Vec<ChainBlock>, from_position: u64, to_position: u64) {
+    match blocks.last_mut() {
+        Some(block)
+            if block.from_start + block.size == from_position
+                && block.to_start + block.size == to_position =>
+        {
+            block.size += 1
+        }
+        _ => blocks.push(ChainBlock {
+            from_start: from_position,
+            to_start: to_position,
+            size: 1,
+        }),
+    }
+}
+
+/// The chains of all contigs of a liftover, by the name of their input contig.
+#[derive(Clone, Debug, Default)]
+pub struct Liftover {
+    chains: HashMap<String, Chain>,
+}
+
+impl Liftover {
+    /// Load a UCSC chain file. Only chains on the forward strand are supported,
+    /// and if an input contig has multiple chains, then only its first chain is kept.
+    pub fn from_chain_file<P: AsRef<Path>>(path: P, io_buffer_size: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let reader = BufReader::with_capacity(
+            io_buffer_size,
+            File::open(path).io_context(|| format!("Could not open {path:?}"))?,
+        );
+        let mut chains = HashMap::new();
+        let mut current_chain: Option<(Chain, u64, u64)> = None;
+        for (line_index, line) in reader.lines().enumerate() {
+            let line_number = line_index + 1;
+            let line = line.io_context(|| format!("Could not read {path:?}"))?;
+            let columns: Vec<_> = line.split_whitespace().collect();
+            let parse_error = || {
+                Error::Input(format!("Could not parse chain line: {line}"))
+                    .at(format_args!("Line {line_number}"))
+            };
+            let parse = |column: &str| column.parse::<u64>().map_err(|_| parse_error());
+
+            if columns.first() == Some(&"chain") {
+                if columns.len() < 12 {
+                    return Err(parse_error());
+                }
+                if columns[4] != "+" || columns[9] != "+" {
+                    return Err(Error::Input(format!(
+                        "Chains on the reverse strand are not supported: {line}"
+                    ))
+                    .at(format_args!("Line {line_number}")));
+                }
+                if let Some((chain, ..)) = current_chain.take() {
+                    chains.entry(chain.from_name.clone()).or_insert(chain);
+                }
+                current_chain = Some((
+                    Chain {
+                        from_name: columns[2].to_owned(),
+                        from_length: parse(columns[3])?,
+                        to_name: columns[7].to_owned(),
+                        to_length: parse(columns[8])?,
+                        blocks: Vec::new(),
+                    },
+                    parse(columns[5])?,
+                    parse(columns[10])?,
+                ));
+            } else if !columns.is_empty() {
+                let (chain, from_offset, to_offset) =
+                    current_chain.as_mut().ok_or_else(parse_error)?;
+                let size = parse(columns[0])?;
+                chain.blocks.push(ChainBlock {
+                    from_start: *from_offset,
+                    to_start: *to_offset,
+                    size,
+                });
+                *from_offset += size;
+                *to_offset += size;
+                match columns[1..] {
+                    [] => {}
+                    [from_gap, to_gap] => {
+                        *from_offset += parse(from_gap)?;
+                        *to_offset += parse(to_gap)?;
+                    }
+                    _ => return Err(parse_error()),
+                }
+            }
+        }
+        if let Some((chain, ..)) = current_chain {
+            chains.entry(chain.from_name.clone()).or_insert(chain);
+        }
+        Ok(Self { chains })
+    }
+
+    /// Map a position of an input contig to the name of its output contig and the position on it,
+    /// or return `None` if the contig is unknown or the position lies in a gap.
+    pub fn lift(&self, contig_name: &str, position: u64) -> Option<(&str, u64)> {
+        let chain = self.chains.get(contig_name)?;
+        chain
+            .lift(position)
+            .map(|position| (chain.to_name.as_str(), position))
+    }
+}
+
+/// Lift the intervals of a BED file to the output contigs, keeping all columns after the interval.
+/// Intervals whose first or last position lies in a gap or on an unknown contig are left out.
+/// Header, track and comment lines are copied as they are.
+/// Returns the number of lifted and left out intervals.
+pub fn lift_bed<P1: AsRef<Path>, P2: AsRef<Path>>(
+    liftover: &Liftover,
+    input_file: P1,
+    output_file: P2,
+    io_buffer_size: usize,
+) -> Result<(u64, u64)> {
+    let input_file = input_file.as_ref();
+    let output_file = output_file.as_ref();
+    let reader = BufReader::with_capacity(
+        io_buffer_size,
+        File::open(input_file).io_context(|| format!("Could not open {input_file:?}"))?,
+    );
+    let mut writer = BufWriter::with_capacity(
+        io_buffer_size,
+        File::create(output_file).io_context(|| format!("Could not create {output_file:?}"))?,
+    );
+    let write_error = || format!("Could not write to {output_file:?}");
+    let mut lifted_count = 0;
+    let mut unmapped_count = 0;
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line.io_context(|| format!("Could not read {input_file:?}"))?;
+        if line.is_empty()
+            || line.starts_with('#')
+            || line.starts_with("track")
+            || line.starts_with("browser")
+        {
+            writeln!(writer, "{line}").io_context(write_error)?;
+            continue;
+        }
+
+        let mut columns = line.splitn(4, '\t');
+        let parse_error = || {
+            Error::Input(format!("Could not parse BED line: {line}"))
+                .at(format_args!("Line {}", line_index + 1))
+        };
+        let (contig_name, start, end) = match (columns.next(), columns.next(), columns.next()) {
+            (Some(contig_name), Some(start), Some(end)) => (
+                contig_name,
+                start.parse::<u64>().map_err(|_| parse_error())?,
+                end.parse::<u64>().map_err(|_| parse_error())?,
+            ),
+            _ => return Err(parse_error()),
+        };
+        if end <= start {
+            return Err(parse_error());
+        }
+
+        match (
+            liftover.lift(contig_name, start),
+            liftover.lift(contig_name, end - 1),
+        ) {
+            (Some((lifted_name, lifted_start)), Some((_, lifted_last))) => {
+                write!(writer, "{lifted_name}\t{lifted_start}\t{}", lifted_last + 1)
+                    .and_then(|()| {
+                        if let Some(rest) = columns.next() {
+                            write!(writer, "\t{rest}")?;
+                        }
+                        writeln!(writer)
+                    })
+                    .io_context(write_error)?;
+                lifted_count += 1;
+            }
+            _ => unmapped_count += 1,
+        }
+    }
+
+    writer.flush().io_context(write_error)?;
+    Ok((lifted_count, unmapped_count))
+}
+
+/// Writes the chain of each contig as it is decompressed.
+pub struct ChainWriter {
+    file: RecordFile,
+    contig_name: String,
+    contig_length: u64,
+    anchors: Vec<(u64, u64)>,
+}
+
+impl ChainWriter {
+    pub fn create<P: AsRef<Path>>(path: P, io_buffer_size: usize) -> Result<Self> {
+        Ok(Self {
+            file: RecordFile::create(path, io_buffer_size, None)?,
+            contig_name: String::new(),
+            contig_length: 0,
+            anchors: Vec::new(),
+        })
+    }
+
+    /// Start a contig with its name and length in the input.
+    pub fn start_contig(&mut self, contig_name: &str, contig_length: u64) {
+        self.contig_name = contig_name.to_owned();
+        self.contig_length = contig_length;
+        self.anchors.clear();
+    }
+
+    /// Add an edge of the current contig with its offsets in the input and the output.
+    pub fn add_edge(&mut self, from_offset: u64, to_offset: u64) {
+        self.anchors.push((from_offset, to_offset));
+    }
+
+    /// Write the chain of the current contig, given its length in the output.
+    pub fn finish_contig(&mut self, contig_length: u64) -> Result<()> {
+        let chain = Chain::from_anchors(
+            &self.contig_name,
+            self.contig_length,
+            contig_length,
+            &self.anchors,
+        );
+        if !chain.blocks.is_empty() {
+            let chain_id = self.file.record_count() + 1;
+            self.file
+                .write_record(|writer| chain.write(writer, chain_id))?;
+        }
+        Ok(())
+    }
+
+    /// Flush the output and return the number of written chains.
+    pub fn finish(self) -> Result<u64> {
+        self.file.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::liftover::{lift_bed, Chain, ChainBlock, ChainWriter, Liftover};
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("liftover_test_{}_{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_chain() {
+        // The first interval expands from 4 to 6 positions, the second one from 6 to 7 positions.
+        let chain = Chain::from_anchors("ctg1", 10, 13, &[(0, 0), (4, 6)]);
+        assert_eq!(
+            chain.blocks,
+            [
+                ChainBlock {
+                    from_start: 0,
+                    to_start: 0,
+                    size: 2
+                },
+                ChainBlock {
+                    from_start: 2,
+                    to_start: 3,
+                    size: 2
+                },
+                ChainBlock {
+                    from_start: 4,
+                    to_start: 6,
+                    size: 6
+                },
+            ]
+        );
+        assert_eq!(chain.lift(0), Some(0));
+        assert_eq!(chain.lift(2), Some(3));
+        assert_eq!(chain.lift(4), Some(6));
+        assert_eq!(chain.lift(9), Some(11));
+        assert_eq!(chain.lift(10), None);
+
+        let mut output = Vec::new();
+        chain.write(&mut output, 1).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "chain 10 ctg1 10 + 0 10 ctg1 13 + 0 12 1\n\
+             2\t0\t1\n\
+             2\t0\t1\n\
+             6\n\n"
+        );
+    }
+
+    #[test]
+    fn test_chain_rescales_within_interval() {
+        // An interval that expands by 1.3 maps its middle to the middle of the expanded interval.
+        let chain = Chain::from_anchors("ctg1", 100, 130, &[(0, 0)]);
+        assert_eq!(chain.lift(50), Some(65));
+        assert_eq!(chain.lift(99), Some(128));
+        let mapped: u64 = chain.blocks.iter().map(|block| block.size).sum();
+        assert_eq!(mapped, 100);
+
+        // An interval that shrinks leaves out every other input position.
+        let chain = Chain::from_anchors("ctg1", 10, 5, &[(0, 0)]);
+        assert_eq!(chain.lift(4), Some(2));
+        assert_eq!(chain.lift(5), None);
+        assert_eq!(chain.lift(8), Some(4));
+    }
+
+    #[test]
+    fn test_lift_bed() {
+        let chain_path = test_path("lift.chain");
+        let mut chain_writer = ChainWriter::create(&chain_path, 16).unwrap();
+        chain_writer.start_contig("ctg1", 10);
+        chain_writer.add_edge(0, 0);
+        chain_writer.add_edge(4, 6);
+        chain_writer.finish_contig(13).unwrap();
+        chain_writer.start_contig("ctg2", 10);
+        chain_writer.add_edge(0, 0);
+        chain_writer.finish_contig(20).unwrap();
+        assert_eq!(chain_writer.finish().unwrap(), 2);
+
+        let liftover = Liftover::from_chain_file(&chain_path, 16).unwrap();
+        let chain = Chain::from_anchors("ctg1", 10, 13, &[(0, 0), (4, 6)]);
+        for position in 0..11 {
+            assert_eq!(
+                liftover.lift("ctg1", position),
+                chain.lift(position).map(|position| ("ctg1", position))
+            );
+        }
+        assert_eq!(liftover.lift("ctg2", 5), Some(("ctg2", 10)));
+        assert_eq!(liftover.lift("ctg3", 0), None);
+
+        let bed_path = test_path("input.bed");
+        let lifted_path = test_path("lifted.bed");
+        fs::write(
+            &bed_path,
+            "track name=genes\nctg1\t2\t5\tgene1\t0\t+\nctg2\t1\t3\nctg3\t0\t1\tgene2\n",
+        )
+        .unwrap();
+        assert_eq!(
+            lift_bed(&liftover, &bed_path, &lifted_path, 16).unwrap(),
+            (2, 1)
+        );
+        assert_eq!(
+            fs::read_to_string(&lifted_path).unwrap(),
+            "track name=genes\nctg1\t3\t7\tgene1\t0\t+\nctg2\t2\t5\n"
+        );
+
+        // Reversed intervals are malformed.
+        fs::write(&bed_path, "ctg1\t5\t2\n").unwrap();
+        assert!(lift_bed(&liftover, &bed_path, &lifted_path, 16).is_err());
+        for path in [chain_path, bed_path, lifted_path] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_reverse_chain() {
+        let chain_path = test_path("reverse.chain");
+        fs::write(
+            &chain_path,
+            "chain 10 ctg1 10 + 0 10 ctg1 10 - 0 10 1\n10\n\n",
+        )
+        .unwrap();
+        assert!(Liftover::from_chain_file(&chain_path, 16).is_err());
+        fs::remove_file(chain_path).unwrap();
+    }
+}
@@ -1,19 +1,70 @@
-use crate::decompress::decompress;
+use crate::checkpoint::Checkpoint;
+use crate::consensus::ConsensusDispatcher;
+use crate::contig_filter::ContigFilter;
+use crate::ctg_lay_index::CtgLayIndex;
+use crate::draft::{DraftWriter, SegmentChoice};
+use crate::error::{join_result, merge_results, Cancellation, Error, IoContext, Result};
+use crate::extract::extract_contigs;
 use crate::fasta_sequence_index::FastaSequenceIndex;
-use crate::wtdbg2_ctg_lay::{LineContext, Wtdbg2CtgLayLine, Wtdbg2CtgLayLineWithContext};
-use clap::Parser;
+use crate::gfa::GfaWriter;
+use crate::layout::ContigLayoutReader;
+use crate::layout_diff::{diff_layouts, write_differences, DifferenceKind};
+use crate::layout_statistics::LayoutStatistics;
+use crate::liftover::{lift_bed, Liftover};
+use crate::memory_budget::{parse_byte_size, MemoryBudget};
+use crate::output_sink::OutputSink;
+use crate::pipeline::{lock_read_cache, Mode, Pipeline};
+use crate::progress::{Phase, Progress};
+use crate::read_cache::ReadCache;
+use crate::run_length_consensus::decompress_contig;
+use crate::statistics::RunStatistics;
+use crate::validate::validate_file;
+use bio::io::fasta;
+use clap::{Args, Parser, Subcommand};
 use crossbeam::channel;
-use log::{info, trace, LevelFilter};
+use log::{error, info, warn, LevelFilter};
 use simplelog::{ColorChoice, TermLogger, TerminalMode};
-use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fs;
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::AtomicU64;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
+mod alignment_map;
+mod anomaly;
+mod checkpoint;
+mod consensus;
+mod contig_filter;
+mod ctg_lay_index;
 mod decompress;
+mod draft;
+mod error;
+mod extract;
 mod fasta_sequence_index;
+mod fingerprint;
+mod gfa;
+mod layout;
+mod layout_diff;
+mod layout_statistics;
+mod liftover;
+mod memory_budget;
+mod output;
+mod output_sink;
+mod paf;
+mod pipeline;
+mod progress;
+mod read_cache;
+mod record_writer;
+mod reorder_buffer;
+mod rescue;
+mod run_length_consensus;
+mod statistics;
+mod summary;
+mod validate;
 mod wtdbg2_ctg_lay;
 
 #[derive(Parser, Clone)]
@@ -34,6 +85,262 @@ struct Configuration {
     #[clap(long, default_value = "32768")]
     queue_size: usize,
 
+    /// The maximum number of bytes of input lines, read sequences and read segments that are queued between threads,
+    /// including the lines that wait to be put back into order and the read segments that wait for --consensus.
+    /// The input is throttled while the limit is reached. Accepts the suffixes K, M, G and T.
+    #[clap(long, parse(try_from_str = parse_byte_size))]
+    max_memory: Option<u64>,
+
+    /// Split the output into this many .ctg.lay shards with roughly equal numbers of decompressed bases,
+    /// such that consensus can be computed in parallel.
+    /// The shard index is inserted before the .ctg.lay suffix of the output file,
+    /// and a manifest listing the shards is written next to the output file.
+    #[clap(long, default_value = "1")]
+    shards: usize,
+
+    #[clap(flatten)]
+    contig_selection: ContigSelection,
+
+    /// Resume an interrupted run from its last checkpoint.
+    /// The read index is reused if it is still valid, the output is truncated to the last complete contig,
+    /// and the input is parsed from the first contig that was not completely written.
+    /// Fails if the input, the reads or the options that affect the output changed since the checkpoint.
+    /// The files of --paf, --alignment-map, --chain, --draft, --consensus and --stats are rewritten from scratch,
+    /// so they only cover the contigs of the resumed run.
+    #[clap(long)]
+    resume: bool,
+
+    /// The minimum time between two checkpoints in seconds.
+    #[clap(long, default_value = "60")]
+    checkpoint_interval: u64,
+
+    /// The number of threads that fetch read sequences from the read index.
+    /// Using more than one thread helps to saturate storage with deep request queues, like NVMe or network filesystems.
+    #[clap(long, default_value = "1")]
+    fetch_threads: usize,
+
+    /// The number of recently used reads to keep in memory.
+    /// Adjacent edges mostly share their reads, so this saves most of the read lookups.
+    #[clap(long, default_value = "1024")]
+    read_cache_size: usize,
+
+    /// The read index persisted by the index subcommand, which is reused if it is still valid.
+    /// Otherwise, the index is built and persisted at this path.
+    /// Defaults to the output file with the suffix .normal_index, where the index is only reused by --resume.
+    #[clap(long, parse(from_os_str))]
+    read_index: Option<PathBuf>,
+
+    /// The time between two progress reports in seconds. Zero disables periodic reports.
+    #[clap(long, default_value = "10")]
+    progress_interval: u64,
+
+    /// Additionally write the progress reports to this file as a stream of JSON objects, one per line.
+    #[clap(long, parse(from_os_str))]
+    progress_json: Option<PathBuf>,
+
+    /// Write a summary of the run to this file, with the lengths of each contig before and after decompression
+    /// and the time spent in each pipeline stage.
+    /// The summary is written as tab-separated values if the file name ends in .tsv, and as JSON otherwise.
+    #[clap(long, parse(from_os_str))]
+    stats: Option<PathBuf>,
+
+    /// Write the alignments whose expansion ratio is far from the typical ratio of their contig to this file.
+    /// Such alignments usually stem from a wrong read, a wrong strand or a corrupted read id.
+    #[clap(long, parse(from_os_str))]
+    anomaly_report: Option<PathBuf>,
+
+    /// Alignments whose expansion ratio has a robust z-score above this threshold within their contig are anomalies.
+    #[clap(long, default_value = "3.5")]
+    anomaly_threshold: f64,
+
+    /// Remove anomalous alignments from the output.
+    /// If all alignments of an edge are anomalous, the least anomalous one is kept, such that every edge keeps
+    /// an alignment. The edge offsets are not recomputed.
+    #[clap(long)]
+    drop_anomalies: bool,
+
+    /// Write the placement of each decompressed read segment on its decompressed contig to this file in PAF format.
+    /// A segment is placed at the offset of its edge, without a base-level alignment,
+    /// so the number of matching bases is zero and the mapping quality is 255 (missing).
+    /// With --resume, only the contigs of the resumed run are written.
+    #[clap(long, parse(from_os_str))]
+    paf: Option<PathBuf>,
+
+    /// Write the original and the output coordinates of each output alignment on its read to this file
+    /// as tab-separated values, together with its contig, edge index, alignment index, read id and strand.
+    /// The indices count the edges of each contig and the alignments of each edge in the input, starting from zero.
+    /// With --resume, only the contigs of the resumed run are written.
+    #[clap(long, parse(from_os_str))]
+    alignment_map: Option<PathBuf>,
+
+    /// Write the mapping from the input to the output contig coordinates to this file in UCSC chain format,
+    /// e.g. to lift annotations of the compressed contigs to the decompressed contigs.
+    /// Each edge offset is mapped to its rescaled offset, and the positions between two edges are rescaled
+    /// proportionally, with the remaining positions of the longer side spread evenly as unmapped gaps.
+    /// With --resume, only the contigs of the resumed run are written.
+    #[clap(long, parse(from_os_str))]
+    chain: Option<PathBuf>,
+
+    /// Write a draft sequence of each output contig to this file in FASTA format, without computing a consensus.
+    /// The draft tiles one read segment per edge, placed at the offset of the edge,
+    /// where each segment is cut off at the offset of the next edge with a segment.
+    /// Alignments removed by --drop-anomalies are not used.
+    #[clap(long, parse(from_os_str))]
+    draft: Option<PathBuf>,
+
+    /// The read segment of each edge that is used in the --draft sequence.
+    #[clap(long, arg_enum, default_value = "longest")]
+    draft_segment: SegmentChoice,
+
+    /// Compute the consensus of each output contig and write it to this file in FASTA format.
+    /// The consensus of each edge is computed from the partial-order alignment of its read segments
+    /// and the edge consensuses are tiled like the --draft segments.
+    /// The consensus is computed by --compute-threads threads in addition to the decompression threads,
+    /// plus one thread that writes the consensus, so up to twice as many compute threads are busy.
+    /// Alignments removed by --drop-anomalies are not used.
+    #[clap(long, parse(from_os_str))]
+    consensus: Option<PathBuf>,
+
+    #[clap(flatten)]
+    segment_checks: SegmentChecks,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+/// Checks of the alignments against the compressed read segments embedded in the input lines.
+/// Only decompress has these options, since the layouts written by decompress embed no segments.
+#[derive(Args, Clone, Default)]
+struct SegmentChecks {
+    /// Check the direction of each alignment against the compressed read segment embedded in its input line,
+    /// and correct it if the read segment compresses to the embedded segment better in the other direction.
+    /// The direction is kept if both directions match equally well, e.g. for reverse complement palindromes.
+    #[clap(long)]
+    fix_strand: bool,
+
+    /// Write the alignments whose direction was corrected by --fix-strand to this file.
+    #[clap(long, parse(from_os_str))]
+    strand_report: Option<PathBuf>,
+
+    /// Search the read for the compressed segment embedded in an alignment line
+    /// if the segment at the given coordinates does not match it, e.g. because the reads differ slightly.
+    /// The segment is anchored with exact k-mer matches near the given coordinates and then aligned in a band.
+    /// Alignments that cannot be rescued are removed from the output.
+    #[clap(long)]
+    rescue: bool,
+
+    /// The number of compressed characters before and after the given coordinates that are searched by --rescue.
+    #[clap(long, default_value = "1000")]
+    rescue_window: usize,
+
+    /// The length of the exact matches used by --rescue to anchor the segment.
+    #[clap(long, default_value = "12")]
+    rescue_k: usize,
+}
+
+#[derive(Args, Clone)]
+struct CompressConfiguration {
+    /// The input file. Must be a decompressed .ctg.lay file, e.g. written by the decompress subcommand.
+    #[clap(long, parse(from_os_str))]
+    input: PathBuf,
+
+    /// The output file. Must be in wtdbg2's .ctg.lay format.
+    #[clap(long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// A fasta file containing the normal (uncompressed) reads.
+    #[clap(long, parse(from_os_str))]
+    normal_reads: PathBuf,
+
+    /// The size of the queues between threads.
+    #[clap(long, default_value = "32768")]
+    queue_size: usize,
+
+    /// The maximum number of bytes of input lines, read sequences and read segments that are queued between threads,
+    /// including the lines that wait to be put back into order.
+    /// The input is throttled while the limit is reached. Accepts the suffixes K, M, G and T.
+    #[clap(long, parse(try_from_str = parse_byte_size))]
+    max_memory: Option<u64>,
+
+    #[clap(flatten)]
+    contig_selection: ContigSelection,
+
+    /// The number of threads that fetch read sequences from the read index.
+    #[clap(long, default_value = "1")]
+    fetch_threads: usize,
+
+    /// The number of recently used reads to keep in memory.
+    #[clap(long, default_value = "1024")]
+    read_cache_size: usize,
+
+    /// The read index persisted by the index subcommand, which is reused if it is still valid.
+    /// Otherwise, the index is built and persisted at this path.
+    /// Defaults to the output file with the suffix .normal_index.
+    #[clap(long, parse(from_os_str))]
+    read_index: Option<PathBuf>,
+
+    /// The time between two progress reports in seconds. Zero disables periodic reports.
+    #[clap(long, default_value = "10")]
+    progress_interval: u64,
+
+    /// Additionally write the progress reports to this file as a stream of JSON objects, one per line.
+    #[clap(long, parse(from_os_str))]
+    progress_json: Option<PathBuf>,
+
+    /// Write a summary of the run to this file, with the lengths of each contig before and after compression
+    /// and the time spent in each pipeline stage.
+    /// The summary is written as tab-separated values if the file name ends in .tsv, and as JSON otherwise.
+    #[clap(long, parse(from_os_str))]
+    stats: Option<PathBuf>,
+
+    /// Write the original and the output coordinates of each output alignment on its read to this file
+    /// as tab-separated values, together with its contig, edge index, alignment index, read id and strand.
+    /// The indices count the edges of each contig and the alignments of each edge in the input, starting from zero.
+    #[clap(long, parse(from_os_str))]
+    alignment_map: Option<PathBuf>,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+impl From<CompressConfiguration> for Configuration {
+    /// The options of decompress that compress does not have are disabled.
+    fn from(configuration: CompressConfiguration) -> Self {
+        Self {
+            input: configuration.input,
+            output: configuration.output,
+            normal_reads: configuration.normal_reads,
+            queue_size: configuration.queue_size,
+            max_memory: configuration.max_memory,
+            shards: 1,
+            contig_selection: configuration.contig_selection,
+            resume: false,
+            // Compress cannot resume, so it never stores a checkpoint.
+            checkpoint_interval: u64::MAX,
+            fetch_threads: configuration.fetch_threads,
+            read_cache_size: configuration.read_cache_size,
+            read_index: configuration.read_index,
+            progress_interval: configuration.progress_interval,
+            progress_json: configuration.progress_json,
+            stats: configuration.stats,
+            anomaly_report: None,
+            anomaly_threshold: f64::INFINITY,
+            drop_anomalies: false,
+            paf: None,
+            alignment_map: configuration.alignment_map,
+            chain: None,
+            draft: None,
+            draft_segment: SegmentChoice::Longest,
+            consensus: None,
+            segment_checks: SegmentChecks::default(),
+            common: configuration.common,
+        }
+    }
+}
+
+/// Options shared by all subcommands.
+#[derive(Args, Clone)]
+struct CommonOptions {
     /// The size of the I/O buffers in bytes.
     #[clap(long, default_value = "67108864")]
     io_buffer_size: usize,
@@ -48,425 +355,911 @@ struct Configuration {
     log_level: LevelFilter,
 }
 
-fn initialise_logging(log_level: &LevelFilter) {
+/// Selects the contigs that are processed.
+#[derive(Args, Clone)]
+struct ContigSelection {
+    /// Only process the contigs with these names (comma separated).
+    #[clap(long, use_value_delimiter = true)]
+    contigs: Vec<String>,
+
+    /// Only process the contigs whose names are listed in this file, one per line.
+    #[clap(long, parse(from_os_str))]
+    contigs_file: Option<PathBuf>,
+
+    /// Only process the contigs whose names match this regular expression.
+    /// If names are given as well, then contigs matching either are processed.
+    #[clap(long)]
+    contig_regex: Option<String>,
+
+    /// Only process contigs with at least this length, as given in the input contig line.
+    #[clap(long)]
+    min_contig_len: Option<u64>,
+
+    /// Only process contigs with at most this length, as given in the input contig line.
+    #[clap(long)]
+    max_contig_len: Option<u64>,
+}
+
+impl Configuration {
+    /// The options that change the output, which must not change when resuming from a checkpoint.
+    fn output_options(&self, mode: Mode) -> String {
+        let selection = &self.contig_selection;
+        format!(
+            "compress={} shards={} contigs={:?} contigs_file={:?} contig_regex={:?} min_contig_len={:?} \
+             max_contig_len={:?} anomaly_threshold={} drop_anomalies={} fix_strand={} rescue={} \
+             rescue_window={} rescue_k={}",
+            mode == Mode::Compress,
+            self.shards,
+            selection.contigs,
+            selection.contigs_file,
+            selection.contig_regex,
+            selection.min_contig_len,
+            selection.max_contig_len,
+            self.anomaly_threshold,
+            self.drop_anomalies,
+            self.segment_checks.fix_strand,
+            self.segment_checks.rescue,
+            self.segment_checks.rescue_window,
+            self.segment_checks.rescue_k,
+        )
+    }
+}
+
+impl ContigSelection {
+    fn filter(&self) -> Result<ContigFilter> {
+        ContigFilter::new(
+            &self.contigs,
+            self.contigs_file.as_ref(),
+            self.contig_regex.as_deref(),
+            self.min_contig_len,
+            self.max_contig_len,
+        )
+    }
+}
+
+#[derive(Args, Clone)]
+struct IndexConfiguration {
+    /// A fasta file containing the normal (uncompressed) reads.
+    #[clap(long, parse(from_os_str))]
+    normal_reads: PathBuf,
+
+    /// The file to write the index to. Defaults to the reads file with the suffix .normal_index.
+    /// Pass the same path to --read-index of the decompress subcommand to reuse the index.
+    #[clap(long, parse(from_os_str))]
+    read_index: Option<PathBuf>,
+
+    /// The size of the queues between threads.
+    #[clap(long, default_value = "32768")]
+    queue_size: usize,
+
+    /// The time between two progress reports in seconds. Zero disables periodic reports.
+    #[clap(long, default_value = "10")]
+    progress_interval: u64,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Args, Clone)]
+struct StatsConfiguration {
+    /// The input file. Must be in wtdbg2's .ctg.lay format.
+    #[clap(long, parse(from_os_str))]
+    input: PathBuf,
+
+    /// The file to write the statistics to.
+    /// They are written as tab-separated values if the file name ends in .tsv, and as JSON otherwise.
+    /// Defaults to writing tab-separated values to standard output.
+    #[clap(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Args, Clone)]
+struct ValidateConfiguration {
+    /// The input file. Must be in wtdbg2's .ctg.lay format.
+    #[clap(long, parse(from_os_str))]
+    input: PathBuf,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Args, Clone)]
+struct DiffConfiguration {
+    /// The old .ctg.lay file.
+    #[clap(long, parse(from_os_str))]
+    old: PathBuf,
+
+    /// The new .ctg.lay file.
+    #[clap(long, parse(from_os_str))]
+    new: PathBuf,
+
+    /// The file to write the differences to as tab-separated values. Defaults to standard output.
+    #[clap(long, parse(from_os_str))]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Args, Clone)]
+struct GfaConfiguration {
+    /// The input file. Must be in wtdbg2's .ctg.lay format, either compressed or decompressed.
+    #[clap(long, parse(from_os_str))]
+    input: PathBuf,
+
+    /// The output file in GFA 1.0 format.
+    #[clap(long, parse(from_os_str))]
+    output: PathBuf,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Args, Clone)]
+struct ExtractConfiguration {
+    /// The input file. Must be in wtdbg2's .ctg.lay format.
+    #[clap(long, parse(from_os_str))]
+    input: PathBuf,
+
+    /// The output file, containing the selected contigs of the input file unmodified.
+    #[clap(long, parse(from_os_str))]
+    output: PathBuf,
+
+    #[clap(flatten)]
+    contig_selection: ContigSelection,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Args, Clone)]
+struct LiftoverConfiguration {
+    /// The chain file written by the --chain option of decompress, or any other UCSC chain file
+    /// with all chains on the forward strand.
+    #[clap(long, parse(from_os_str))]
+    chain: PathBuf,
+
+    /// The input file in BED format, with coordinates on the input contigs of the chain file.
+    #[clap(long, parse(from_os_str))]
+    input: PathBuf,
+
+    /// The output file in BED format, with coordinates on the output contigs of the chain file.
+    #[clap(long, parse(from_os_str))]
+    output: PathBuf,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Args, Clone)]
+struct RunLengthConsensusConfiguration {
+    /// The homopolymer compressed contigs in FASTA format, e.g. after polishing in compressed space.
+    #[clap(long, parse(from_os_str))]
+    contigs: PathBuf,
+
+    /// The compressed layout of the contigs. Must be in wtdbg2's .ctg.lay format.
+    #[clap(long, parse(from_os_str))]
+    input: PathBuf,
+
+    /// A fasta file containing the normal (uncompressed) reads.
+    #[clap(long, parse(from_os_str))]
+    normal_reads: PathBuf,
+
+    /// The read index persisted by the index subcommand, which is reused if it is still valid.
+    /// Otherwise, the index is built and persisted at this path.
+    /// Defaults to the reads file with the suffix .normal_index.
+    #[clap(long, parse(from_os_str))]
+    read_index: Option<PathBuf>,
+
+    /// The output file, containing the decompressed contigs in FASTA format.
+    #[clap(long, parse(from_os_str))]
+    output: PathBuf,
+
+    /// The number of compressed bases around the offset of its edge that are searched for a read segment.
+    /// Must cover the shift of the contig coordinates by polishing.
+    #[clap(long, default_value = "1000")]
+    window: usize,
+
+    /// The length of the exact matches used to anchor the read segments on the contigs.
+    #[clap(long, default_value = "12")]
+    k: usize,
+
+    /// The number of recently used run-length encoded reads to keep in memory.
+    /// Adjacent edges mostly share their reads, so this saves most of the read lookups and encodings.
+    #[clap(long, default_value = "1024")]
+    read_cache_size: usize,
+
+    /// The size of the queues between threads while building the read index.
+    #[clap(long, default_value = "32768")]
+    queue_size: usize,
+
+    #[clap(flatten)]
+    common: CommonOptions,
+}
+
+#[derive(Parser)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Replace the homopolymer compressed reads in a .ctg.lay file with the normal reads.
+    Decompress(Configuration),
+    /// Replace the normal reads in a decompressed .ctg.lay file with the homopolymer compressed reads,
+    /// as the inverse of decompress.
+    /// Edge offsets and contig lengths are rescaled like during decompression,
+    /// so they may differ from the original .ctg.lay file by rounding.
+    Compress(CompressConfiguration),
+    /// Build the index of the normal reads and persist it, such that multiple decompress runs can share it.
+    Index(IndexConfiguration),
+    /// Summarise the contigs, edges and alignments of a .ctg.lay file.
+    Stats(StatsConfiguration),
+    /// Check the structure of a .ctg.lay file, without looking at the reads.
+    /// Checks that the edges of each contig chain its nodes with non-decreasing offsets,
+    /// that each edge has alignments, and that the alignment lengths match their embedded sequences.
+    /// Problems are reported with their line numbers, and the exit code is non-zero if there are any.
+    Validate(ValidateConfiguration),
+    /// Copy selected contigs of a .ctg.lay file.
+    Extract(ExtractConfiguration),
+    /// Compare two .ctg.lay files, matching contigs by name, edges by their node pair and alignments by their read.
+    /// Repeated node pairs within a contig are matched in order and numbered from their second occurrence on.
+    /// Reports added and removed contigs, edges and reads, as well as changed contig lengths, edge offsets
+    /// and orientations.
+    Diff(DiffConfiguration),
+    /// Export the layout graph of a .ctg.lay file as GFA, with the nodes as segments, the edges as links
+    /// and the contigs as paths.
+    /// The segment lengths are derived from the edge offsets.
+    Gfa(GfaConfiguration),
+    /// Lift the intervals of a BED file along a chain file, e.g. from the compressed to the decompressed contigs.
+    /// Intervals whose first or last position is not mapped are left out.
+    Liftover(LiftoverConfiguration),
+    /// Decompress homopolymer compressed contigs, e.g. after polishing in compressed space,
+    /// by voting over the run lengths of the reads in their layout, similar to Shasta's run-length consensus.
+    /// The compressed read segments of each edge are aligned near the offset of the edge,
+    /// and each contig base gets the most frequent run length of the read bases aligned to it.
+    /// Contig bases without aligned read bases, and contigs without layout, keep a run length of one.
+    RunLengthConsensus(RunLengthConsensusConfiguration),
+}
+
+impl Command {
+    fn common(&self) -> &CommonOptions {
+        match self {
+            Command::Decompress(configuration) => &configuration.common,
+            Command::Compress(configuration) => &configuration.common,
+            Command::Index(configuration) => &configuration.common,
+            Command::Stats(configuration) => &configuration.common,
+            Command::Validate(configuration) => &configuration.common,
+            Command::Extract(configuration) => &configuration.common,
+            Command::Diff(configuration) => &configuration.common,
+            Command::Gfa(configuration) => &configuration.common,
+            Command::Liftover(configuration) => &configuration.common,
+            Command::RunLengthConsensus(configuration) => &configuration.common,
+        }
+    }
+}
+
+fn initialise_logging(log_level: &LevelFilter) -> Result<()> {
     TermLogger::init(
         *log_level,
         Default::default(),
         TerminalMode::Stderr,
         ColorChoice::Auto,
     )
-    .unwrap();
-    info!("Logging initialised successfully")
+    .map_err(|error| Error::Consistency(format!("Could not initialise logging: {error}")))?;
+    info!("Logging initialised successfully");
+    Ok(())
 }
 
 fn main() {
-    let configuration = Configuration::parse();
-    initialise_logging(&configuration.log_level);
-
-    let mut normal_sequence_index_path = configuration.output.clone().into_os_string();
-    normal_sequence_index_path.push(".normal_index");
-    let normal_sequence_index_path = normal_sequence_index_path;
-    let mut contig_tmp_path = configuration.output.clone().into_os_string();
-    contig_tmp_path.push(".current_contig");
-    let contig_tmp_path = contig_tmp_path;
-    // Create/open the files here already to abort early if it cannot be created.
-    let tmp_file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(&contig_tmp_path)
-        .unwrap();
-    let input_file = File::open(&configuration.input).unwrap();
-    let output_file = File::create(&configuration.output).unwrap();
+    let command = Cli::parse().command;
+    if let Err(error) = initialise_logging(&command.common().log_level) {
+        eprintln!("{error}");
+        process::exit(error.exit_code());
+    }
 
-    info!("Building reads sequence indices...");
-    // parallel builds seem to be a little faster on my laptop.
-    let mut normal_sequence_index = {
-        let configuration = configuration.clone();
-        crossbeam::scope(|scope| {
-            let normal_sequence_index = scope
-                .builder()
-                .name("normal_index_builder_thread".to_string())
-                .spawn(|scope| {
-                    FastaSequenceIndex::build_parallel(
-                        configuration.normal_reads,
-                        normal_sequence_index_path,
-                        scope,
-                        configuration.queue_size,
-                        configuration.io_buffer_size,
-                    )
-                    //FastaSequenceIndex::build(configuration.normal_reads, normal_sequence_index_path, configuration.io_buffer_size)
-                })
-                .unwrap();
-
-            normal_sequence_index.join().unwrap()
-        })
-        .unwrap()
+    let result = match command {
+        Command::Decompress(configuration) => run(configuration, Mode::Decompress),
+        Command::Compress(configuration) => run(configuration.into(), Mode::Compress),
+        Command::Index(configuration) => run_index(configuration),
+        Command::Stats(configuration) => run_stats(configuration),
+        Command::Validate(configuration) => run_validate(configuration),
+        Command::Extract(configuration) => run_extract(configuration),
+        Command::Diff(configuration) => run_diff(configuration),
+        Command::Gfa(configuration) => run_gfa(configuration),
+        Command::Liftover(configuration) => run_liftover(configuration),
+        Command::RunLengthConsensus(configuration) => run_run_length_consensus(configuration),
     };
-    info!("Built read sequence indices");
+    if let Err(error) = result {
+        error!("{error}");
+        process::exit(error.exit_code());
+    }
+}
 
-    info!("Decompressing...");
-    crossbeam::scope(|scope| {
-        // Read input file.
-        let (input_sender, input_receiver) = channel::bounded(configuration.queue_size);
-        scope
-            .builder()
-            .name("input_reader".to_string())
-            .spawn(move |_| {
-                let mut line_number = 0;
-                for line in
-                    BufReader::with_capacity(configuration.io_buffer_size, input_file).lines()
-                {
-                    let line = line.unwrap();
-                    line_number += 1;
-                    trace!("Read line {line_number}");
-                    input_sender.send(line).unwrap();
-                }
-            })
-            .unwrap();
-
-        // Parse input lines.
-        let (alignment_sender, alignment_receiver) = channel::bounded(configuration.queue_size);
-        let (decompressed_alignment_sender, decompressed_alignment_receiver) =
-            channel::bounded(configuration.queue_size);
-        {
-            let decompressed_alignment_sender = decompressed_alignment_sender.clone();
-            scope
-                .builder()
-                .name("input_parser".to_string())
-                .spawn(move |_| {
-                    let mut context = LineContext::default();
-
-                    while let Ok(input) = input_receiver.recv() {
-                        let line = Wtdbg2CtgLayLine::from_str(&input)
-                            .unwrap_or_else(|_| panic!("Could not parse: {input}"));
-                        match line {
-                            Wtdbg2CtgLayLine::Contig { .. } => {
-                                trace!("Parsed contig line {input}");
-                                if context.contig_index != -1 {
-                                    context.previous_contig_edge_count = context.edge_index + 1;
-                                    context.previous_edge_alignment_count =
-                                        context.alignment_index + 1;
-                                }
-                                context.contig_index += 1;
-                                context.edge_index = -1;
-                                context.alignment_index = -1;
-                                decompressed_alignment_sender
-                                    .send((
-                                        Wtdbg2CtgLayLineWithContext {
-                                            line,
-                                            context: context.clone(),
-                                        },
-                                        None,
-                                    ))
-                                    .unwrap();
-                            }
-                            Wtdbg2CtgLayLine::Edge { .. } => {
-                                trace!("Parsed edge line {input}");
-                                assert!(context.contig_index >= 0);
-                                if context.edge_index != -1 {
-                                    context.previous_edge_alignment_count =
-                                        context.alignment_index + 1;
-                                }
-                                context.edge_index += 1;
-                                context.alignment_index = -1;
-                                decompressed_alignment_sender
-                                    .send((
-                                        Wtdbg2CtgLayLineWithContext {
-                                            line,
-                                            context: context.clone(),
-                                        },
-                                        None,
-                                    ))
-                                    .unwrap();
-                            }
-                            Wtdbg2CtgLayLine::Alignment { .. } => {
-                                trace!("Parsed alignment line");
-                                assert!(context.contig_index >= 0);
-                                assert!(context.edge_index >= 0);
-                                context.alignment_index += 1;
-                                alignment_sender
-                                    .send(Wtdbg2CtgLayLineWithContext {
-                                        line,
-                                        context: context.clone(),
-                                    })
-                                    .unwrap();
-                            }
-                        }
-                    }
-                })
-                .unwrap();
+fn run(configuration: Configuration, mode: Mode) -> Result<()> {
+    let normal_sequence_index_path = configuration.read_index.clone().unwrap_or_else(|| {
+        let mut normal_sequence_index_path = configuration.output.clone().into_os_string();
+        normal_sequence_index_path.push(".normal_index");
+        normal_sequence_index_path.into()
+    });
+    let mut checkpoint_path = configuration.output.clone().into_os_string();
+    checkpoint_path.push(".checkpoint");
+    let checkpoint_path = PathBuf::from(checkpoint_path);
+
+    let run_statistics = &RunStatistics::new();
+    let contig_filter = configuration.contig_selection.filter()?;
+
+    let checkpoint = if configuration.resume {
+        let checkpoint = Checkpoint::load(&checkpoint_path)?;
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.verify(
+                &configuration.input,
+                &configuration.normal_reads,
+                &configuration.output_options(mode),
+                configuration.common.io_buffer_size,
+            )?;
+            info!(
+                "Resuming after contig {} ({} contigs completed)",
+                checkpoint.last_contig_name, checkpoint.completed_contigs
+            );
+        } else {
+            info!("No checkpoint found, starting from the beginning");
         }
+        checkpoint
+    } else {
+        None
+    };
+    let normal_sequence_index_path = checkpoint
+        .as_ref()
+        .map(|checkpoint| checkpoint.index_path.clone())
+        .unwrap_or(normal_sequence_index_path);
 
-        // Decorate alignments with read sequences.
-        let (decorated_alignment_sender, decorated_alignment_receiver) =
-            channel::bounded(configuration.queue_size);
-        scope
-            .builder()
-            .name("read_sequence_reader".to_string())
-            .spawn(move |_| {
-                while let Ok(line_with_context) = alignment_receiver.recv() {
-                    let read_id = match &line_with_context.line {
-                        Wtdbg2CtgLayLine::Alignment { read_id, .. } => read_id,
-                        _ => unreachable!("Not an alignment: {line_with_context:?}"),
-                    };
-                    let mut sequence = Vec::new();
-                    let read_id_string = String::from_utf8(read_id.clone()).unwrap();
-                    trace!("Reading read {read_id_string}");
-                    normal_sequence_index.get_sequence(read_id, &mut sequence);
-                    decorated_alignment_sender
-                        .send((line_with_context, sequence))
-                        .unwrap();
+    // Create/open the files here already to abort early if it cannot be created.
+    let input_file = File::open(&configuration.input)
+        .io_context(|| format!("Could not open {:?}", configuration.input))?;
+    let consensus_writer = if let Some(consensus_path) = &configuration.consensus {
+        Some(DraftWriter::new(
+            BufWriter::with_capacity(
+                configuration.common.io_buffer_size,
+                File::create(consensus_path)
+                    .io_context(|| format!("Could not create {consensus_path:?}"))?,
+            ),
+            SegmentChoice::Longest,
+        ))
+    } else {
+        None
+    };
+    let input_offset = checkpoint
+        .as_ref()
+        .map(|checkpoint| checkpoint.input_offset)
+        .unwrap_or(0);
+
+    // If contigs are selected by name only, then seek to them directly instead of streaming the whole input.
+    let input_ranges = if let Some(names) = contig_filter.exact_names() {
+        let ctg_lay_index =
+            CtgLayIndex::load_or_build(&configuration.input, configuration.common.io_buffer_size)?;
+        let mut input_ranges = Vec::new();
+        let mut edge_count = 0;
+        let mut alignment_count = 0;
+        for name in names {
+            if let (Some(entry), Some(range)) =
+                (ctg_lay_index.get(name), ctg_lay_index.byte_range(name))
+            {
+                if range.start >= input_offset {
+                    edge_count += entry.edge_count;
+                    alignment_count += entry.alignment_count;
+                    input_ranges.push(range);
                 }
-            })
-            .unwrap();
-
-        // Decompress.
-        for thread_index in 0..configuration.compute_threads {
-            let decorated_alignment_receiver = decorated_alignment_receiver.clone();
-            let decompressed_alignment_sender = decompressed_alignment_sender.clone();
-            scope
-                .builder()
-                .name(format!("decompressor_{thread_index}"))
-                .spawn(move |_| {
-                    while let Ok((
-                        Wtdbg2CtgLayLineWithContext {
-                            line:
-                                Wtdbg2CtgLayLine::Alignment {
-                                    read_id,
-                                    direction,
-                                    offset,
-                                    length,
-                                    original_length,
-                                },
-                            context,
-                        },
-                        sequence,
-                    )) = decorated_alignment_receiver.recv()
-                    {
-                        trace!("Decompressing {context:?}");
-                        let limit = offset + length;
-                        let (shifted_offset, shifted_limit) = decompress(offset, limit, &sequence);
-                        let shifted_length = shifted_limit - shifted_offset;
-                        let shifted_sequence = &sequence[shifted_offset..shifted_limit];
-                        decompressed_alignment_sender
-                            .send((
-                                Wtdbg2CtgLayLineWithContext {
-                                    line: Wtdbg2CtgLayLine::Alignment {
-                                        read_id,
-                                        direction,
-                                        offset: shifted_offset,
-                                        length: shifted_length,
-                                        original_length,
-                                    },
-                                    context,
-                                },
-                                Some(if direction {
-                                    shifted_sequence.to_owned()
-                                } else {
-                                    reverse_complement(shifted_sequence.iter().cloned())
-                                }),
-                            ))
-                            .unwrap();
-                    }
-                })
-                .unwrap();
+            } else {
+                warn!("Contig {name} does not exist in the input");
+            }
         }
+        input_ranges.sort_unstable_by_key(|range| range.start);
+        info!(
+            "Seeking to {} contigs with {edge_count} edges and {alignment_count} alignments",
+            input_ranges.len()
+        );
+        input_ranges
+    } else {
+        // Stream everything after the resume point.
+        let whole_input = input_offset..u64::MAX;
+        vec![whole_input]
+    };
 
-        // Decompression with multiple threads will shuffle the alignments a bit, so we need to put them back into order.
-        let (output_sender, output_receiver) = channel::bounded(configuration.queue_size);
-        scope
-            .builder()
-            .name("sorter".to_owned())
-            .spawn(move |_| {
-                let mut current_context = LineContext::default();
-                let mut sorted_lines = BTreeMap::new();
-                let mut alignment_count = 0;
-                let mut original_alignment_length_sum = 0;
-                let mut shifted_alignment_length_sum = 0;
-                let mut original_previous_offset = 0;
-                let mut shifted_previous_offset = 0;
-
-                while let Ok((Wtdbg2CtgLayLineWithContext { line, context }, shifted_sequence)) =
-                    decompressed_alignment_receiver.recv()
-                {
-                    trace!("Received {context:?}");
-                    assert!(sorted_lines
-                        .insert(context, (line, shifted_sequence))
-                        .is_none());
-
-                    while let Some(context) = sorted_lines.keys().next().cloned() {
-                        trace!(
-                            "Last context is {current_context:?}, and next known is {context:?}"
-                        );
-                        if current_context.directly_precedes(&context) {
-                            let (mut line, shifted_sequence) =
-                                sorted_lines.remove(&context).unwrap();
-                            match &mut line {
-                                Wtdbg2CtgLayLine::Contig { .. } => {
-                                    alignment_count = 0;
-                                    original_alignment_length_sum = 0;
-                                    shifted_alignment_length_sum = 0;
-                                    original_previous_offset = 0;
-                                    shifted_previous_offset = 0;
-                                    assert!(shifted_sequence.is_none());
-                                    output_sender.send((line, None)).unwrap()
-                                }
-                                Wtdbg2CtgLayLine::Edge { offset, .. } => {
-                                    let original_offset = *offset;
-                                    *offset = shifted_previous_offset
-                                        + ((*offset - original_previous_offset) as f64
-                                            * shifted_alignment_length_sum as f64
-                                            / original_alignment_length_sum as f64)
-                                            .round()
-                                            as u64;
-                                    alignment_count = 0;
-                                    original_alignment_length_sum = 0;
-                                    shifted_alignment_length_sum = 0;
-                                    original_previous_offset = original_offset;
-                                    shifted_previous_offset = *offset;
-                                    assert!(shifted_sequence.is_none());
-                                    output_sender.send((line, None)).unwrap()
-                                }
-                                Wtdbg2CtgLayLine::Alignment {
-                                    length,
-                                    original_length,
-                                    ..
-                                } => {
-                                    alignment_count += 1;
-                                    original_alignment_length_sum += *original_length;
-                                    shifted_alignment_length_sum += *length;
-                                    let estimated_length = (shifted_alignment_length_sum as f64
-                                        / alignment_count as f64)
-                                        .round()
-                                        as u64;
-                                    assert!(shifted_sequence.is_some());
-                                    output_sender
-                                        .send((
-                                            line,
-                                            shifted_sequence.map(|shifted_sequence| {
-                                                (shifted_sequence, estimated_length)
-                                            }),
-                                        ))
-                                        .unwrap();
-                                }
-                            }
-                            current_context = context;
-                        } else {
-                            break;
-                        }
-                    }
-                }
-            })
-            .unwrap();
+    let input_length = input_file
+        .metadata()
+        .io_context(|| format!("Could not read metadata of {:?}", configuration.input))?
+        .len();
+    let normal_reads_length = fs::metadata(&configuration.normal_reads)
+        .io_context(|| {
+            format!(
+                "Could not read metadata of {:?}",
+                configuration.normal_reads
+            )
+        })?
+        .len();
+    let progress = &Progress::new(
+        Some(Duration::from_secs(configuration.progress_interval))
+            .filter(|interval| !interval.is_zero()),
+        configuration.progress_json.as_ref(),
+        normal_reads_length,
+        input_ranges
+            .iter()
+            .map(|range| range.end.min(input_length).saturating_sub(range.start))
+            .sum(),
+    )?;
+    let pipeline = Pipeline {
+        configuration: &configuration,
+        mode,
+        cancellation: &Cancellation::default(),
+        memory_budget: &MemoryBudget::new(configuration.max_memory),
+        run_statistics,
+        progress,
+    };
+    let (consensus_task_sender, consensus_task_receiver) =
+        channel::bounded(configuration.queue_size);
+    let output_sink = OutputSink::create(
+        pipeline,
+        checkpoint.as_ref(),
+        &checkpoint_path,
+        &normal_sequence_index_path,
+        consensus_writer
+            .is_some()
+            .then(|| ConsensusDispatcher::new(consensus_task_sender, pipeline.cancellation)),
+    )?;
 
-        // Write output.
-        scope
-            .builder()
-            .name("output_writer".to_owned())
-            .spawn(move |_| {
-                let mut output_writer =
-                    BufWriter::with_capacity(configuration.io_buffer_size, output_file);
-                let mut tmp_writer =
-                    BufWriter::with_capacity(configuration.io_buffer_size, tmp_file);
-                let mut append_file_buffer = vec![0; configuration.io_buffer_size];
-                let mut current_offset = 0;
-                let mut current_last_edge_length = 0;
-                let mut current_contig_line = None;
-                while let Ok((mut line, sequence_and_length)) = output_receiver.recv() {
-                    trace!("Writing line {line:?}");
-                    match &mut line {
-                        Wtdbg2CtgLayLine::Contig { .. } => {
-                            tmp_writer = finalise_contig_line(
-                                &mut current_contig_line,
-                                &mut current_offset,
-                                &mut current_last_edge_length,
-                                &mut output_writer,
-                                tmp_writer,
-                                &mut append_file_buffer,
-                                &configuration,
-                            );
-
-                            current_contig_line = Some(line);
-                        }
-                        Wtdbg2CtgLayLine::Edge { offset, .. } => {
-                            current_offset = *offset;
-                            tmp_writer.write_all(line.to_string().as_bytes()).unwrap();
-                            tmp_writer.write_all(&[b'\n']).unwrap();
-                        }
-                        Wtdbg2CtgLayLine::Alignment { .. } => {
-                            let (sequence, edge_length) = sequence_and_length.unwrap();
-                            current_last_edge_length = edge_length;
-
-                            tmp_writer.write_all(line.to_string().as_bytes()).unwrap();
-                            tmp_writer.write_all(&sequence).unwrap();
-                            tmp_writer.write_all(&[b'\n']).unwrap();
-                        }
-                    }
+    let reusable_sequence_index = if checkpoint.is_some() || configuration.read_index.is_some() {
+        let normal_sequence_index = FastaSequenceIndex::load(
+            &configuration.normal_reads,
+            &normal_sequence_index_path,
+            configuration.common.io_buffer_size,
+        )?;
+        if normal_sequence_index.is_none() {
+            info!("The persisted read sequence index is missing or outdated");
+        }
+        normal_sequence_index
+    } else {
+        None
+    };
+    let index_start = Instant::now();
+    let normal_sequence_index = if let Some(normal_sequence_index) = reusable_sequence_index {
+        info!("Reusing persisted read sequence index");
+        normal_sequence_index
+    } else {
+        progress.run(Phase::IndexingReads, || {
+            build_normal_sequence_index(
+                &configuration.normal_reads,
+                &normal_sequence_index_path,
+                configuration.queue_size,
+                configuration.common.io_buffer_size,
+                &progress.counters.read_bytes,
+            )
+        })?
+    };
+
+    run_statistics.add_stage_time("read_index", index_start.elapsed())?;
+    run_statistics.set_reads_indexed(normal_sequence_index.read_count() as u64)?;
+
+    info!("Decompressing...");
+    let normal_sequence_index = &normal_sequence_index;
+    let read_cache = &Mutex::new(ReadCache::new(configuration.read_cache_size));
+    progress.run(Phase::Decompressing, || {
+        crossbeam::scope(|scope| {
+            let mut threads = Vec::new();
+
+            let (input_sender, input_receiver) = channel::bounded(configuration.queue_size);
+            threads.push(pipeline.spawn(
+                scope,
+                "input_reader",
+                input_sender,
+                move |input_sender| pipeline.read_input(input_file, input_ranges, input_sender),
+            )?);
+
+            let (alignment_sender, alignment_receiver) = channel::bounded(configuration.queue_size);
+            let (decompressed_line_sender, decompressed_line_receiver) =
+                channel::bounded(configuration.queue_size);
+            {
+                let decompressed_line_sender = decompressed_line_sender.clone();
+                threads.push(pipeline.spawn(
+                    scope,
+                    "input_parser",
+                    (input_receiver, alignment_sender, decompressed_line_sender),
+                    move |(input_receiver, alignment_sender, decompressed_line_sender)| {
+                        pipeline.parse_input(
+                            contig_filter,
+                            input_receiver,
+                            alignment_sender,
+                            decompressed_line_sender,
+                        )
+                    },
+                )?);
+            }
+
+            let (fetched_alignment_sender, fetched_alignment_receiver) =
+                channel::bounded(configuration.queue_size);
+            for thread_index in 0..configuration.fetch_threads {
+                let alignment_receiver = alignment_receiver.clone();
+                let fetched_alignment_sender = fetched_alignment_sender.clone();
+                threads.push(pipeline.spawn(
+                    scope,
+                    &format!("read_sequence_reader_{thread_index}"),
+                    (alignment_receiver, fetched_alignment_sender),
+                    move |(alignment_receiver, fetched_alignment_sender)| {
+                        pipeline.fetch_reads(
+                            normal_sequence_index,
+                            read_cache,
+                            alignment_receiver,
+                            fetched_alignment_sender,
+                        )
+                    },
+                )?);
+            }
+            drop(alignment_receiver);
+            drop(fetched_alignment_sender);
+
+            for thread_index in 0..configuration.common.compute_threads {
+                let fetched_alignment_receiver = fetched_alignment_receiver.clone();
+                let decompressed_line_sender = decompressed_line_sender.clone();
+                threads.push(pipeline.spawn(
+                    scope,
+                    &format!("decompressor_{thread_index}"),
+                    (fetched_alignment_receiver, decompressed_line_sender),
+                    move |(fetched_alignment_receiver, decompressed_line_sender)| {
+                        pipeline.decompress_alignments(
+                            fetched_alignment_receiver,
+                            decompressed_line_sender,
+                        )
+                    },
+                )?);
+            }
+            // Drop the original channel endpoints such that the neighbouring stages notice when all decompressors terminate.
+            drop(fetched_alignment_receiver);
+            drop(decompressed_line_sender);
+
+            let (sorted_line_sender, sorted_line_receiver) =
+                channel::bounded(configuration.queue_size);
+            threads.push(pipeline.spawn(
+                scope,
+                "sorter",
+                (decompressed_line_receiver, sorted_line_sender),
+                move |(decompressed_line_receiver, sorted_line_sender)| {
+                    pipeline.sort_lines(decompressed_line_receiver, sorted_line_sender)
+                },
+            )?);
+
+            threads.push(pipeline.spawn(
+                scope,
+                "output_writer",
+                sorted_line_receiver,
+                move |sorted_line_receiver| {
+                    pipeline.write_output(output_sink, sorted_line_receiver)
+                },
+            )?);
+
+            if let Some(consensus_writer) = consensus_writer {
+                let (edge_consensus_sender, edge_consensus_receiver) =
+                    channel::bounded(configuration.queue_size);
+                for thread_index in 0..configuration.common.compute_threads {
+                    let consensus_task_receiver = consensus_task_receiver.clone();
+                    let edge_consensus_sender = edge_consensus_sender.clone();
+                    threads.push(pipeline.spawn(
+                        scope,
+                        &format!("consensus_{thread_index}"),
+                        (consensus_task_receiver, edge_consensus_sender),
+                        move |(consensus_task_receiver, edge_consensus_sender)| {
+                            pipeline
+                                .compute_consensus(consensus_task_receiver, edge_consensus_sender)
+                        },
+                    )?);
                 }
+                drop(edge_consensus_sender);
+
+                threads.push(pipeline.spawn(
+                    scope,
+                    "consensus_writer",
+                    edge_consensus_receiver,
+                    move |edge_consensus_receiver| {
+                        pipeline.write_consensus(consensus_writer, edge_consensus_receiver)
+                    },
+                )?);
+            }
+            drop(consensus_task_receiver);
+
+            merge_results(
+                threads
+                    .into_iter()
+                    .map(|(name, thread)| join_result(thread.join(), &name)),
+            )
+        })
+        .unwrap_or_else(|_| {
+            Err(Error::Consistency(
+                "A thread of the decompression pipeline panicked".to_string(),
+            ))
+        })
+    })?;
+
+    info!(
+        "Peak memory of queued lines and sequences: {:.1} MiB",
+        pipeline.memory_budget.peak() as f64 / (1 << 20) as f64
+    );
+    let statistics = lock_read_cache(read_cache)?.statistics();
+    info!(
+        "Read cache: {} hits, {} misses, hit rate {:.1}%",
+        statistics.hits,
+        statistics.misses,
+        statistics.hit_rate() * 100.0
+    );
 
-                finalise_contig_line(
-                    &mut current_contig_line,
-                    &mut current_offset,
-                    &mut current_last_edge_length,
-                    &mut output_writer,
-                    tmp_writer,
-                    &mut append_file_buffer,
-                    &configuration,
-                );
+    if configuration.segment_checks.rescue {
+        let rescue_counts = run_statistics.rescue_counts()?;
+        info!(
+            "Rescued {} alignments and dropped {} that could not be rescued",
+            rescue_counts.rescued, rescue_counts.dropped
+        );
+    }
+    if let Some(stats_path) = &configuration.stats {
+        run_statistics.write(stats_path)?;
+    }
+
+    // The run is complete, so there is nothing to resume anymore.
+    Checkpoint::remove(&checkpoint_path)?;
+
+    info!("Done");
+    Ok(())
+}
+
+fn build_normal_sequence_index(
+    normal_reads: &Path,
+    normal_sequence_index_path: &Path,
+    queue_size: usize,
+    io_buffer_size: usize,
+    read_bytes: &AtomicU64,
+) -> Result<FastaSequenceIndex> {
+    info!("Building reads sequence indices...");
+    // parallel builds seem to be a little faster on my laptop.
+    let normal_sequence_index = crossbeam::scope(|scope| {
+        let normal_sequence_index = scope
+            .builder()
+            .name("normal_index_builder_thread".to_string())
+            .spawn(|scope| {
+                FastaSequenceIndex::build_parallel(
+                    normal_reads,
+                    normal_sequence_index_path,
+                    scope,
+                    queue_size,
+                    io_buffer_size,
+                    read_bytes,
+                )
+                //FastaSequenceIndex::build(normal_reads, normal_sequence_index_path, io_buffer_size)
             })
-            .unwrap();
+            .io_context(|| "Could not spawn normal_index_builder_thread".to_string())?;
+
+        join_result(normal_sequence_index.join(), "normal_index_builder_thread")
     })
-    .unwrap();
+    .unwrap_or_else(|_| {
+        Err(Error::Consistency(
+            "A thread of the index builder panicked".to_string(),
+        ))
+    })?;
+    // Persist the read ids as well, such that an interrupted run can be resumed without rebuilding the index.
+    normal_sequence_index.persist(normal_reads, normal_sequence_index_path, io_buffer_size)?;
+    info!("Built read sequence indices");
 
-    // Remove tmp file as it is not needed anymore.
-    fs::remove_file(&contig_tmp_path).unwrap();
+    Ok(normal_sequence_index)
+}
 
-    info!("Done");
+fn run_index(configuration: IndexConfiguration) -> Result<()> {
+    let normal_sequence_index_path = configuration.read_index.clone().unwrap_or_else(|| {
+        let mut normal_sequence_index_path = configuration.normal_reads.clone().into_os_string();
+        normal_sequence_index_path.push(".normal_index");
+        normal_sequence_index_path.into()
+    });
+    let normal_reads_length = fs::metadata(&configuration.normal_reads)
+        .io_context(|| {
+            format!(
+                "Could not read metadata of {:?}",
+                configuration.normal_reads
+            )
+        })?
+        .len();
+    let progress = Progress::new(
+        Some(Duration::from_secs(configuration.progress_interval))
+            .filter(|interval| !interval.is_zero()),
+        None::<&Path>,
+        normal_reads_length,
+        0,
+    )?;
+    let normal_sequence_index = progress.run(Phase::IndexingReads, || {
+        build_normal_sequence_index(
+            &configuration.normal_reads,
+            &normal_sequence_index_path,
+            configuration.queue_size,
+            configuration.common.io_buffer_size,
+            &progress.counters.read_bytes,
+        )
+    })?;
+    info!(
+        "Indexed {} reads into {normal_sequence_index_path:?}",
+        normal_sequence_index.read_count()
+    );
+    Ok(())
 }
 
-fn finalise_contig_line<OutputWriter: Write>(
-    current_contig_line: &mut Option<Wtdbg2CtgLayLine>,
-    current_offset: &mut u64,
-    current_last_edge_length: &mut u64,
-    output_writer: &mut OutputWriter,
-    mut tmp_writer: BufWriter<File>,
-    append_file_buffer: &mut [u8],
-    configuration: &Configuration,
-) -> BufWriter<File> {
-    if let Some(mut current_contig_line) = current_contig_line.take() {
-        match &mut current_contig_line {
-            Wtdbg2CtgLayLine::Contig { length, .. } => {
-                *length = *current_offset + *current_last_edge_length;
-                *current_offset = 0;
-                *current_last_edge_length = 0;
-                output_writer
-                    .write_all(current_contig_line.to_string().as_bytes())
-                    .unwrap();
-                output_writer.write_all(&[b'\n']).unwrap();
-
-                // Append the tmp file to the actual file, now that we know how long the decompressed contig is.
-                let mut tmp_file = tmp_writer.into_inner().unwrap();
-                tmp_file.seek(SeekFrom::Start(0)).unwrap();
-                loop {
-                    let length = tmp_file.read(append_file_buffer).unwrap();
-                    if length > 0 {
-                        output_writer
-                            .write_all(&append_file_buffer[..length])
-                            .unwrap();
-                    } else {
-                        break;
-                    }
-                }
-                tmp_file.set_len(0).unwrap();
-                tmp_file.seek(SeekFrom::Start(0)).unwrap();
-                tmp_writer = BufWriter::with_capacity(configuration.io_buffer_size, tmp_file);
-            }
-            _ => unreachable!("Contig line is not a contig line {current_contig_line:?}"),
+fn run_stats(configuration: StatsConfiguration) -> Result<()> {
+    let layout_statistics =
+        LayoutStatistics::from_file(&configuration.input, configuration.common.io_buffer_size)?;
+    layout_statistics.write(configuration.output.as_ref())
+}
+
+fn run_validate(configuration: ValidateConfiguration) -> Result<()> {
+    let violations = validate_file(&configuration.input, configuration.common.io_buffer_size)?;
+    for violation in &violations {
+        error!("{violation}");
+    }
+    if violations.is_empty() {
+        info!("Found no problems in {:?}", configuration.input);
+        Ok(())
+    } else {
+        Err(Error::Input(format!(
+            "Found {} problems in {:?}",
+            violations.len(),
+            configuration.input
+        )))
+    }
+}
+
+fn run_extract(configuration: ExtractConfiguration) -> Result<()> {
+    let contig_filter = configuration.contig_selection.filter()?;
+    let contig_count = extract_contigs(
+        &configuration.input,
+        &configuration.output,
+        &contig_filter,
+        configuration.common.io_buffer_size,
+    )?;
+    info!("Extracted {contig_count} contigs");
+    Ok(())
+}
+
+fn run_diff(configuration: DiffConfiguration) -> Result<()> {
+    let io_buffer_size = configuration.common.io_buffer_size;
+    let old: Vec<_> = ContigLayoutReader::open(&configuration.old, io_buffer_size)?
+        .collect::<Result<_>>()
+        .map_err(|error| error.at(format_args!("{:?}", configuration.old)))?;
+    let new: Vec<_> = ContigLayoutReader::open(&configuration.new, io_buffer_size)?
+        .collect::<Result<_>>()
+        .map_err(|error| error.at(format_args!("{:?}", configuration.new)))?;
+
+    let differences = diff_layouts(&old, &new)?;
+    write_differences(&differences, configuration.output.as_ref())?;
+    for kind in DifferenceKind::ALL {
+        let count = differences
+            .iter()
+            .filter(|difference| difference.kind == kind)
+            .count();
+        if count > 0 {
+            info!("{kind}: {count}");
         }
     }
+    info!("Found {} differences", differences.len());
+    Ok(())
+}
+
+fn run_gfa(configuration: GfaConfiguration) -> Result<()> {
+    let io_buffer_size = configuration.common.io_buffer_size;
+    let output_path = &configuration.output;
+    let write_error = || format!("Could not write to {output_path:?}");
+    let mut gfa_writer = GfaWriter::new(BufWriter::with_capacity(
+        io_buffer_size,
+        File::create(output_path).io_context(|| format!("Could not create {output_path:?}"))?,
+    ))
+    .io_context(write_error)?;
+    for contig in ContigLayoutReader::open(&configuration.input, io_buffer_size)? {
+        gfa_writer.add_contig(&contig?).io_context(write_error)?;
+    }
+
+    let (segment_count, link_count, path_count) = gfa_writer.finish().io_context(write_error)?;
+    info!("Wrote {segment_count} segments, {link_count} links and {path_count} paths");
+    Ok(())
+}
+
+fn run_liftover(configuration: LiftoverConfiguration) -> Result<()> {
+    let io_buffer_size = configuration.common.io_buffer_size;
+    let liftover = Liftover::from_chain_file(&configuration.chain, io_buffer_size)?;
+    let (lifted_count, unmapped_count) = lift_bed(
+        &liftover,
+        &configuration.input,
+        &configuration.output,
+        io_buffer_size,
+    )?;
+    info!("Lifted {lifted_count} intervals, {unmapped_count} intervals could not be lifted");
+    Ok(())
+}
+
+fn run_run_length_consensus(configuration: RunLengthConsensusConfiguration) -> Result<()> {
+    let io_buffer_size = configuration.common.io_buffer_size;
+    let normal_sequence_index_path = configuration.read_index.clone().unwrap_or_else(|| {
+        let mut normal_sequence_index_path = configuration.normal_reads.clone().into_os_string();
+        normal_sequence_index_path.push(".normal_index");
+        normal_sequence_index_path.into()
+    });
+    let normal_sequence_index = if let Some(normal_sequence_index) = FastaSequenceIndex::load(
+        &configuration.normal_reads,
+        &normal_sequence_index_path,
+        io_buffer_size,
+    )? {
+        info!("Reusing persisted read sequence index");
+        normal_sequence_index
+    } else {
+        build_normal_sequence_index(
+            &configuration.normal_reads,
+            &normal_sequence_index_path,
+            configuration.queue_size,
+            io_buffer_size,
+            &AtomicU64::new(0),
+        )?
+    };
+
+    let mut layouts = HashMap::new();
+    for contig in ContigLayoutReader::open(&configuration.input, io_buffer_size)? {
+        let contig = contig?;
+        layouts.insert(contig.name.clone(), contig);
+    }
+
+    let contigs_path = &configuration.contigs;
+    let output_path = &configuration.output;
+    let reader = fasta::Reader::with_capacity(
+        io_buffer_size,
+        File::open(contigs_path).io_context(|| format!("Could not open {contigs_path:?}"))?,
+    );
+    let mut writer = fasta::Writer::new(BufWriter::with_capacity(
+        io_buffer_size,
+        File::create(output_path).io_context(|| format!("Could not create {output_path:?}"))?,
+    ));
+    let mut read_cache = ReadCache::new(configuration.read_cache_size);
+    let mut contig_count = 0;
+    let mut placed_count = 0;
+    let mut unplaced_count = 0;
+    for record in reader.records() {
+        let record =
+            record.io_context(|| format!("Could not read fasta record from {contigs_path:?}"))?;
+        let decompressed_contig = if let Some(layout) = layouts.get(record.id()) {
+            let (decompressed_contig, placed, unplaced) = decompress_contig(
+                record.seq(),
+                layout,
+                &normal_sequence_index,
+                &mut read_cache,
+                configuration.window,
+                configuration.k,
+            )?;
+            placed_count += placed;
+            unplaced_count += unplaced;
+            decompressed_contig
+        } else {
+            warn!(
+                "Contig {} is not in the layout, so all of its bases keep a run length of one",
+                record.id()
+            );
+            record.seq().to_ascii_uppercase()
+        };
+
+        writer
+            .write(record.id(), record.desc(), &decompressed_contig)
+            .io_context(|| format!("Could not write to {output_path:?}"))?;
+        contig_count += 1;
+    }
 
-    tmp_writer
+    writer
+        .flush()
+        .io_context(|| format!("Could not write to {output_path:?}"))?;
+    info!("Decompressed {contig_count} contigs");
+    info!(
+        "Aligned {placed_count} read segments, {unplaced_count} read segments could not be aligned"
+    );
+    Ok(())
 }
 
 fn reverse_complement<
@@ -474,16 +1267,113 @@ fn reverse_complement<
     DnaIterator: IntoIterator<Item = u8, IntoIter = IntoIter>,
 >(
     dna: DnaIterator,
-) -> Vec<u8> {
+) -> Result<Vec<u8>> {
     dna.into_iter()
+        .rev()
         .map(|c| match c {
-            b'A' => b'T',
-            b'C' => b'G',
-            b'G' => b'C',
-            b'T' => b'A',
-            b'N' => b'N',
-            other => panic!("Unknown dna character: {other}"),
+            b'A' => Ok(b'T'),
+            b'C' => Ok(b'G'),
+            b'G' => Ok(b'C'),
+            b'T' => Ok(b'A'),
+            b'N' => Ok(b'N'),
+            other => Err(Error::Input(format!(
+                "Unknown dna character: {}",
+                char::from(other)
+            ))),
         })
-        .rev()
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::pipeline::Mode;
+    use crate::{run, Configuration};
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn test_failed_stage_does_not_finalise_output() {
+        let directory =
+            std::env::temp_dir().join(format!("pipeline_test_{}_failure", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("in.ctg.lay");
+        let reads = directory.join("reads.fa");
+        let output = directory.join("out.ctg.lay");
+        // The read of the last alignment is missing, so the read fetcher fails after the input was parsed completely.
+        fs::write(
+            &input,
+            ">ctg1 nodes=2 len=4\nE\t0\tN1\t+\tN2\t+\nS\tread1\t+\t0\t4\tACGT\n\
+             >ctg2 nodes=2 len=4\nE\t0\tN3\t+\tN4\t+\nS\tmissing\t+\t0\t4\tACGT\n",
+        )
+        .unwrap();
+        fs::write(&reads, ">read1\nAACCGGTT\n").unwrap();
+
+        let configuration = Configuration::parse_from([
+            "decompress".as_ref(),
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--normal-reads".as_ref(),
+            reads.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+            "--fetch-threads".as_ref(),
+            "1".as_ref(),
+        ]);
+        let result = run(configuration, Mode::Decompress);
+        assert!(matches!(result, Err(Error::Input(_))), "{result:?}");
+        // The last contig is not finalised without its alignment.
+        assert!(!fs::read_to_string(&output).unwrap().contains(">ctg2"));
+        assert!(directory.join("out.ctg.lay.current_contig").exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_alignments_are_not_in_draft_or_consensus() {
+        let directory =
+            std::env::temp_dir().join(format!("pipeline_test_{}_draft", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("in.ctg.lay");
+        let reads = directory.join("reads.fa");
+        let output = directory.join("out.ctg.lay");
+        let draft = directory.join("draft.fa");
+        let consensus = directory.join("consensus.fa");
+        // The read of the second alignment expands three times more than the others, and it is the longest segment.
+        fs::write(
+            &input,
+            ">ctg1 nodes=3 len=8\n\
+             E\t0\tN1\t+\tN2\t+\nS\tread1\t+\t0\t4\tACGT\nS\tlong\t+\t0\t4\tACGT\nS\tread1\t+\t0\t4\tACGT\n\
+             E\t4\tN2\t+\tN3\t+\nS\tread1\t+\t0\t4\tACGT\nS\tread1\t+\t0\t4\tACGT\nS\tread1\t+\t0\t4\tACGT\n",
+        )
+        .unwrap();
+        fs::write(
+            &reads,
+            ">read1\nAACCGGTT\n>long\nAAAAAACCCCCCGGGGGGTTTTTT\n",
+        )
+        .unwrap();
+
+        let configuration = Configuration::parse_from([
+            "decompress".as_ref(),
+            "--input".as_ref(),
+            input.as_os_str(),
+            "--normal-reads".as_ref(),
+            reads.as_os_str(),
+            "--output".as_ref(),
+            output.as_os_str(),
+            "--draft".as_ref(),
+            draft.as_os_str(),
+            "--consensus".as_ref(),
+            consensus.as_os_str(),
+            "--drop-anomalies".as_ref(),
+        ]);
+        run(configuration, Mode::Decompress).unwrap();
+        assert!(!fs::read_to_string(&output).unwrap().contains("long"));
+        let draft = fs::read_to_string(&draft).unwrap();
+        assert!(draft.starts_with(">ctg1\nAACCGGTT"), "{draft}");
+        assert!(!draft.contains("AAAAAA"), "{draft}");
+        let consensus = fs::read_to_string(&consensus).unwrap();
+        assert!(consensus.starts_with(">ctg1\nAACCGGTT"), "{consensus}");
+        assert!(!consensus.contains("AAAAAA"), "{consensus}");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
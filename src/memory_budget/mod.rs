@@ -0,0 +1,166 @@
+use crate::error::{Cancellation, Result};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// Limits the number of payload bytes that are in flight between the stages of the pipeline.
+/// Each input line stays charged until it is written or dropped, each read sequence until it was decompressed,
+/// and each decompressed segment until it was written. With a consensus, the segments of a contig are charged again
+/// when they are read back after the contig was written, until their consensus was written.
+/// Only the input is throttled, while later stages charge and release bytes without blocking.
+/// Like this, the stages after the input can always drain the pipeline, so it cannot deadlock
+/// even if the reorder buffer holds many items waiting for a slow predecessor.
+/// The price is that the read sequences of lines that were admitted before the limit was reached
+/// may exceed the limit temporarily.
+pub struct MemoryBudget {
+    /// The maximum number of bytes in flight, or `None` if unlimited.
+    limit: Option<u64>,
+    state: Mutex<MemoryBudgetState>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct MemoryBudgetState {
+    used: u64,
+    peak: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            state: Default::default(),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until the given number of bytes fits into the budget and charge them.
+    /// An item that is larger than the whole budget is admitted once nothing else is in flight.
+    pub fn acquire(&self, bytes: usize, cancellation: &Cancellation) -> Result<()> {
+        let bytes = bytes as u64;
+        let mut state = self.lock();
+        if let Some(limit) = self.limit {
+            while state.used > 0 && state.used + bytes > limit {
+                cancellation.check()?;
+                // Wake up regularly to notice if the pipeline was cancelled.
+                state = self
+                    .released
+                    .wait_timeout(state, Duration::from_millis(100))
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+        }
+        state.used += bytes;
+        state.peak = state.peak.max(state.used);
+        Ok(())
+    }
+
+    /// Charge the given number of bytes without waiting.
+    pub fn charge(&self, bytes: usize) {
+        let mut state = self.lock();
+        state.used += bytes as u64;
+        state.peak = state.peak.max(state.used);
+    }
+
+    pub fn release(&self, bytes: usize) {
+        let mut state = self.lock();
+        state.used = state.used.saturating_sub(bytes as u64);
+        self.released.notify_all();
+    }
+
+    /// The maximum number of bytes that were in flight at the same time.
+    pub fn peak(&self) -> u64 {
+        self.lock().peak
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryBudgetState> {
+        // The state is consistent after every operation, so a panic while holding the lock does not corrupt it.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Parse a number of bytes with an optional binary unit suffix, e.g. `512M` or `16G`.
+pub fn parse_byte_size(input: &str) -> std::result::Result<u64, String> {
+    let input = input.trim();
+    let (number, multiplier) = match input.char_indices().last() {
+        Some((index, unit)) if unit.is_ascii_alphabetic() => {
+            let multiplier = match unit.to_ascii_uppercase() {
+                'K' => 1u64 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                'T' => 1 << 40,
+                _ => return Err(format!("Unknown unit in byte size: {input}")),
+            };
+            (&input[..index], multiplier)
+        }
+        _ => (input, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("Not a valid byte size: {input}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Cancellation;
+    use crate::memory_budget::{parse_byte_size, MemoryBudget};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_memory_budget() {
+        let cancellation = Cancellation::default();
+        let budget = MemoryBudget::new(Some(10));
+        // Oversized items are admitted if nothing else is in flight.
+        budget.acquire(20, &cancellation).unwrap();
+        budget.release(20);
+        budget.acquire(6, &cancellation).unwrap();
+        budget.charge(8);
+        budget.release(8);
+        budget.acquire(4, &cancellation).unwrap();
+        assert_eq!(budget.peak(), 20);
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let cancellation = Cancellation::default();
+        let budget = MemoryBudget::new(Some(10));
+        let acquired = AtomicBool::new(false);
+        budget.acquire(8, &cancellation).unwrap();
+        crossbeam::scope(|scope| {
+            let waiter = scope.spawn(|_| {
+                budget.acquire(5, &cancellation).unwrap();
+                acquired.store(true, Ordering::SeqCst);
+            });
+            // The second item does not fit until the first one is released.
+            std::thread::sleep(Duration::from_millis(200));
+            assert!(!acquired.load(Ordering::SeqCst));
+            budget.release(8);
+            waiter.join().unwrap();
+        })
+        .unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+        assert_eq!(budget.peak(), 8);
+
+        // A waiting stage notices when the pipeline is cancelled.
+        crossbeam::scope(|scope| {
+            let waiter = scope.spawn(|_| budget.acquire(10, &cancellation));
+            std::thread::sleep(Duration::from_millis(50));
+            cancellation.cancel();
+            assert!(waiter.join().unwrap().unwrap_err().is_cancelled());
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_parse_byte_size() {
+        assert_eq!(parse_byte_size("1024"), Ok(1024));
+        assert_eq!(parse_byte_size("512M"), Ok(512 << 20));
+        assert_eq!(parse_byte_size("2g"), Ok(2 << 30));
+        assert_eq!(parse_byte_size("4T"), Ok(4 << 40));
+        assert!(parse_byte_size("20000000T").is_err());
+        assert!(parse_byte_size("2X").is_err());
+        assert!(parse_byte_size("G").is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::error::{Error, IoContext, Result};
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// The amount of data written to an output shard.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ShardState {
+    /// The length of the shard file in bytes.
+    pub offset: u64,
+    pub contig_count: u64,
+    /// The sum of the decompressed lengths of the contigs in the shard.
+    pub base_count: u64,
+}
+
+/// The output files, one per shard.
+/// Contigs are assigned to the shard with the fewest bases at the time the contig is completed,
+/// such that all shards need roughly the same time for consensus.
+pub struct OutputShards {
+    paths: Vec<PathBuf>,
+    writers: Vec<BufWriter<File>>,
+    states: Vec<ShardState>,
+}
+
+impl OutputShards {
+    /// Create the shard files, or, if `resume_states` is given, truncate existing shard files to the given states.
+    pub fn create(
+        paths: Vec<PathBuf>,
+        resume_states: Option<&[ShardState]>,
+        io_buffer_size: usize,
+    ) -> Result<Self> {
+        if let Some(resume_states) = resume_states {
+            if resume_states.len() != paths.len() {
+                return Err(Error::Input(format!(
+                    "The checkpoint was written for {} output shards, but {} shards were requested",
+                    resume_states.len(),
+                    paths.len()
+                )));
+            }
+        }
+
+        let mut writers = Vec::new();
+        for (shard, path) in paths.iter().enumerate() {
+            let file = if let Some(resume_states) = resume_states {
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .open(path)
+                    .io_context(|| format!("Could not open {path:?}"))?;
+                file.set_len(resume_states[shard].offset)
+                    .and_then(|()| file.seek(SeekFrom::End(0)))
+                    .io_context(|| format!("Could not truncate {path:?}"))?;
+                file
+            } else {
+                File::create(path).io_context(|| format!("Could not create {path:?}"))?
+            };
+            writers.push(BufWriter::with_capacity(io_buffer_size, file));
+        }
+
+        Ok(Self {
+            states: resume_states
+                .map(|resume_states| resume_states.to_vec())
+                .unwrap_or_else(|| vec![ShardState::default(); paths.len()]),
+            paths,
+            writers,
+        })
+    }
+
+    /// The paths of the shards of the given output path.
+    /// A single shard is written to the output path itself.
+    /// Otherwise, the shard index is inserted before the `.ctg.lay` suffix, or appended if there is none.
+    pub fn shard_paths(output: &Path, shard_count: usize) -> Vec<PathBuf> {
+        if shard_count <= 1 {
+            return vec![output.to_owned()];
+        }
+
+        let output = output.to_string_lossy();
+        let (stem, suffix) = match output.strip_suffix(".ctg.lay") {
+            Some(stem) => (stem, ".ctg.lay"),
+            None => (output.as_ref(), ""),
+        };
+        (0..shard_count)
+            .map(|shard| PathBuf::from(format!("{stem}.shard{shard}{suffix}")))
+            .collect()
+    }
+
+    /// The shard that should receive the next contig.
+    pub fn next_shard(&self) -> usize {
+        self.states
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, state)| state.base_count)
+            .map(|(shard, _)| shard)
+            .unwrap_or(0)
+    }
+
+    pub fn writer(&mut self, shard: usize) -> &mut BufWriter<File> {
+        &mut self.writers[shard]
+    }
+
+    pub fn path(&self, shard: usize) -> &Path {
+        &self.paths[shard]
+    }
+
+    /// Account for a contig that was completely written to the given shard.
+    pub fn complete_contig(&mut self, shard: usize, base_count: u64) {
+        self.states[shard].contig_count += 1;
+        self.states[shard].base_count += base_count;
+    }
+
+    /// Flush all shards to disk and return their states.
+    pub fn sync(&mut self) -> Result<Vec<ShardState>> {
+        for (shard, writer) in self.writers.iter_mut().enumerate() {
+            let path = &self.paths[shard];
+            writer
+                .flush()
+                .and_then(|()| writer.get_ref().sync_data())
+                .io_context(|| format!("Could not write to {path:?}"))?;
+            self.states[shard].offset = writer
+                .stream_position()
+                .io_context(|| format!("Could not query the length of {path:?}"))?;
+        }
+        Ok(self.states.clone())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        for (shard, writer) in self.writers.iter_mut().enumerate() {
+            let path = &self.paths[shard];
+            writer
+                .flush()
+                .io_context(|| format!("Could not write to {path:?}"))?;
+        }
+        Ok(())
+    }
+
+    /// Write a tab-separated manifest listing each shard with its number of contigs and decompressed bases.
+    pub fn write_manifest<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut writer =
+            BufWriter::new(File::create(path).io_context(|| format!("Could not create {path:?}"))?);
+        writeln!(writer, "shard\tpath\tcontigs\tbases")
+            .io_context(|| format!("Could not write to {path:?}"))?;
+        for (shard, (shard_path, state)) in self.paths.iter().zip(&self.states).enumerate() {
+            writeln!(
+                writer,
+                "{shard}\t{}\t{}\t{}",
+                shard_path.to_string_lossy(),
+                state.contig_count,
+                state.base_count
+            )
+            .io_context(|| format!("Could not write to {path:?}"))?;
+        }
+        writer
+            .flush()
+            .io_context(|| format!("Could not write to {path:?}"))
+    }
+
+    /// The path of the manifest of the given output path.
+    pub fn manifest_path(output: &Path) -> PathBuf {
+        let mut manifest_path = OsString::from(output.as_os_str());
+        manifest_path.push(".shards.tsv");
+        manifest_path.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output::{OutputShards, ShardState};
+    use std::fs;
+    use std::io::Write;
+    use std::path::{Path, PathBuf};
+
+    fn temp_output(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("output_test_{}_{name}.ctg.lay", std::process::id()))
+    }
+
+    /// Write contigs with the given lengths, returning the shard of each contig.
+    fn write_contigs(output_shards: &mut OutputShards, lengths: &[u64]) -> Vec<usize> {
+        lengths
+            .iter()
+            .map(|&length| {
+                let shard = output_shards.next_shard();
+                writeln!(output_shards.writer(shard), ">ctg nodes=2 len={length}").unwrap();
+                output_shards.complete_contig(shard, length);
+                shard
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_balancing() {
+        let output = temp_output("balancing");
+        let paths = OutputShards::shard_paths(&output, 3);
+        let mut output_shards = OutputShards::create(paths.clone(), None, 16).unwrap();
+        // Each contig goes to the shard with the fewest bases, preferring the first shard on ties.
+        assert_eq!(
+            write_contigs(&mut output_shards, &[100, 50, 70, 10, 30, 200, 5]),
+            vec![0, 1, 2, 1, 1, 2, 1]
+        );
+        let states = output_shards.sync().unwrap();
+        let base_counts: Vec<_> = states.iter().map(|state| state.base_count).collect();
+        assert_eq!(base_counts, vec![100, 95, 270]);
+        let contig_counts: Vec<_> = states.iter().map(|state| state.contig_count).collect();
+        assert_eq!(contig_counts, vec![1, 4, 2]);
+        for (path, state) in paths.iter().zip(&states) {
+            assert_eq!(fs::metadata(path).unwrap().len(), state.offset);
+        }
+
+        // Resuming continues the balancing with the bases of the checkpoint.
+        let resume_states = vec![
+            states[0].clone(),
+            states[1].clone(),
+            ShardState {
+                base_count: 0,
+                ..states[2].clone()
+            },
+        ];
+        drop(output_shards);
+        let mut output_shards =
+            OutputShards::create(paths.clone(), Some(&resume_states), 16).unwrap();
+        assert_eq!(
+            write_contigs(&mut output_shards, &[40, 80, 30]),
+            vec![2, 2, 1]
+        );
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_manifest() {
+        let output = temp_output("manifest");
+        let paths = OutputShards::shard_paths(&output, 2);
+        let mut output_shards = OutputShards::create(paths.clone(), None, 16).unwrap();
+        write_contigs(&mut output_shards, &[100, 30, 20]);
+        output_shards.flush().unwrap();
+        let manifest_path = OutputShards::manifest_path(&output);
+        output_shards.write_manifest(&manifest_path).unwrap();
+
+        let manifest = fs::read_to_string(&manifest_path).unwrap();
+        assert_eq!(
+            manifest,
+            format!(
+                "shard\tpath\tcontigs\tbases\n0\t{}\t1\t100\n1\t{}\t2\t50\n",
+                paths[0].to_string_lossy(),
+                paths[1].to_string_lossy()
+            )
+        );
+        fs::remove_file(manifest_path).unwrap();
+        for path in &paths {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_shard_paths() {
+        assert_eq!(
+            OutputShards::shard_paths(Path::new("asm.ctg.lay"), 1),
+            vec![PathBuf::from("asm.ctg.lay")]
+        );
+        assert_eq!(
+            OutputShards::shard_paths(Path::new("asm.ctg.lay"), 2),
+            vec![
+                PathBuf::from("asm.shard0.ctg.lay"),
+                PathBuf::from("asm.shard1.ctg.lay")
+            ]
+        );
+        assert_eq!(
+            OutputShards::shard_paths(Path::new("asm.lay"), 2),
+            vec![
+                PathBuf::from("asm.lay.shard0"),
+                PathBuf::from("asm.lay.shard1")
+            ]
+        );
+    }
+}
@@ -0,0 +1,666 @@
+use crate::alignment_map::{self, AlignmentMapping};
+use crate::anomaly::{AlignmentRatio, AnomalyDetector};
+use crate::checkpoint::Checkpoint;
+use crate::consensus::ConsensusDispatcher;
+use crate::decompress::alignment_limit;
+use crate::draft::DraftWriter;
+use crate::error::{Error, IoContext, Result};
+use crate::fingerprint::file_fingerprint;
+use crate::liftover::ChainWriter;
+use crate::output::OutputShards;
+use crate::paf::PafRecord;
+use crate::pipeline::Pipeline;
+use crate::record_writer::AlignmentRecordWriter;
+use crate::statistics::ContigStatistics;
+use crate::wtdbg2_ctg_lay::{LineContext, Wtdbg2CtgLayLine};
+use log::{info, trace};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// The contig whose edges and alignments are currently written to the tmp file.
+struct CurrentContig {
+    line: Wtdbg2CtgLayLine,
+    /// The byte offset of the contig line in the input.
+    input_offset: u64,
+    /// The offset of the last edge in the output.
+    edge_offset: u64,
+    /// The estimated length of the last edge with alignments.
+    last_edge_length: u64,
+    statistics: ContigStatistics,
+}
+
+/// Stores a checkpoint after a completed contig, at most once per interval.
+struct Checkpointer {
+    path: PathBuf,
+    interval: Duration,
+    last_time: Instant,
+    completed_contigs: u64,
+    index_path: PathBuf,
+    input_fingerprint: String,
+    reads_fingerprint: String,
+    options: String,
+}
+
+/// Receives the decompressed layout in order and writes it to the output shards and all side outputs.
+/// The edges and alignments of each contig are buffered in a tmp file until the contig is finished,
+/// since the decompressed length in the contig line is only known then.
+pub struct OutputSink<'a> {
+    pipeline: Pipeline<'a>,
+    shards: OutputShards,
+    tmp_path: PathBuf,
+    tmp_writer: BufWriter<File>,
+    append_file_buffer: Vec<u8>,
+    contig: Option<CurrentContig>,
+    checkpointer: Checkpointer,
+    anomaly_detector: Option<AnomalyDetector>,
+    strand_report: Option<BufWriter<File>>,
+    strand_correction_count: u64,
+    paf_writer: Option<AlignmentRecordWriter<PafRecord>>,
+    alignment_map_writer: Option<AlignmentRecordWriter<AlignmentMapping>>,
+    chain_writer: Option<ChainWriter>,
+    draft_writer: Option<DraftWriter<BufWriter<File>>>,
+    consensus_dispatcher: Option<ConsensusDispatcher<'a>>,
+}
+
+impl<'a> OutputSink<'a> {
+    /// Create the output files, such that the run aborts early if one of them cannot be created.
+    /// If resuming, the output shards are truncated to the state of the checkpoint.
+    pub fn create(
+        pipeline: Pipeline<'a>,
+        checkpoint: Option<&Checkpoint>,
+        checkpoint_path: &Path,
+        index_path: &Path,
+        consensus_dispatcher: Option<ConsensusDispatcher<'a>>,
+    ) -> Result<Self> {
+        let configuration = pipeline.configuration;
+        let io_buffer_size = configuration.common.io_buffer_size;
+        let mut tmp_path = configuration.output.clone().into_os_string();
+        tmp_path.push(".current_contig");
+        let tmp_path = PathBuf::from(tmp_path);
+        let tmp_file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .io_context(|| format!("Could not create {tmp_path:?}"))?;
+        if configuration.shards == 0 {
+            return Err(Error::Input(
+                "The number of shards must be positive".to_string(),
+            ));
+        }
+        let shards = OutputShards::create(
+            OutputShards::shard_paths(&configuration.output, configuration.shards),
+            checkpoint.map(|checkpoint| checkpoint.shards.as_slice()),
+            io_buffer_size,
+        )?;
+
+        let anomaly_detector =
+            if configuration.anomaly_report.is_some() || configuration.drop_anomalies {
+                Some(AnomalyDetector::new(
+                    configuration.anomaly_threshold,
+                    configuration.drop_anomalies,
+                    configuration.anomaly_report.as_ref(),
+                )?)
+            } else {
+                None
+            };
+        let strand_report =
+            if let Some(strand_report_path) = &configuration.segment_checks.strand_report {
+                let mut strand_report = BufWriter::new(
+                    File::create(strand_report_path)
+                        .io_context(|| format!("Could not create {strand_report_path:?}"))?,
+                );
+                writeln!(
+                    strand_report,
+                    "contig\tedge\talignment\tread\tinput_direction\tcorrected_direction"
+                )
+                .io_context(|| format!("Could not write to {strand_report_path:?}"))?;
+                Some(strand_report)
+            } else {
+                None
+            };
+        let paf_writer = configuration
+            .paf
+            .as_ref()
+            .map(|paf_path| AlignmentRecordWriter::create(paf_path, io_buffer_size, None))
+            .transpose()?;
+        let alignment_map_writer = configuration
+            .alignment_map
+            .as_ref()
+            .map(|alignment_map_path| {
+                AlignmentRecordWriter::create(
+                    alignment_map_path,
+                    io_buffer_size,
+                    Some(alignment_map::HEADER),
+                )
+            })
+            .transpose()?;
+        let chain_writer = configuration
+            .chain
+            .as_ref()
+            .map(|chain_path| ChainWriter::create(chain_path, io_buffer_size))
+            .transpose()?;
+        let draft_writer = if let Some(draft_path) = &configuration.draft {
+            Some(DraftWriter::new(
+                BufWriter::with_capacity(
+                    io_buffer_size,
+                    File::create(draft_path)
+                        .io_context(|| format!("Could not create {draft_path:?}"))?,
+                ),
+                configuration.draft_segment,
+            ))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            pipeline,
+            shards,
+            tmp_path,
+            tmp_writer: BufWriter::with_capacity(io_buffer_size, tmp_file),
+            append_file_buffer: vec![0; io_buffer_size],
+            contig: None,
+            checkpointer: Checkpointer {
+                path: checkpoint_path.to_owned(),
+                interval: Duration::from_secs(configuration.checkpoint_interval),
+                last_time: Instant::now(),
+                completed_contigs: checkpoint
+                    .map(|checkpoint| checkpoint.completed_contigs)
+                    .unwrap_or(0),
+                index_path: index_path.to_owned(),
+                input_fingerprint: file_fingerprint(&configuration.input)?,
+                reads_fingerprint: file_fingerprint(&configuration.normal_reads)?,
+                options: configuration.output_options(pipeline.mode),
+            },
+            anomaly_detector,
+            strand_report,
+            strand_correction_count: 0,
+            paf_writer,
+            alignment_map_writer,
+            chain_writer,
+            draft_writer,
+            consensus_dispatcher,
+        })
+    }
+
+    /// Finish the current contig and start the given one.
+    pub fn start_contig(&mut self, line: Wtdbg2CtgLayLine, context: &LineContext) -> Result<()> {
+        self.finish_contig(Some(context.input_offset))?;
+
+        let (name, length) = match &line {
+            Wtdbg2CtgLayLine::Contig { name, length, .. } => (name, *length),
+            _ => {
+                return Err(Error::Consistency(format!(
+                    "Contig line is not a contig line {line:?}"
+                )))
+            }
+        };
+        if let Some(chain_writer) = &mut self.chain_writer {
+            chain_writer.start_contig(name, length);
+        }
+        if let Some(anomaly_detector) = &mut self.anomaly_detector {
+            anomaly_detector.start_contig(name);
+        }
+        self.contig = Some(CurrentContig {
+            statistics: ContigStatistics {
+                name: name.clone(),
+                compressed_length: length,
+                ..Default::default()
+            },
+            line,
+            input_offset: context.input_offset,
+            edge_offset: 0,
+            last_edge_length: 0,
+        });
+        self.pipeline
+            .progress
+            .counters
+            .contigs
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Write an edge of the current contig, with its offset already rescaled.
+    pub fn start_edge(&mut self, line: &Wtdbg2CtgLayLine) -> Result<()> {
+        let contig = current_contig(&mut self.contig)?;
+        let (offset, original_offset) = match line {
+            Wtdbg2CtgLayLine::Edge {
+                offset,
+                original_offset,
+                ..
+            } => (*offset, *original_offset),
+            _ => return Err(Error::Consistency(format!("Not an edge: {line:?}"))),
+        };
+        contig.edge_offset = offset;
+        contig.statistics.edge_count += 1;
+        if let Some(chain_writer) = &mut self.chain_writer {
+            chain_writer.add_edge(original_offset, offset);
+        }
+        writeln!(self.tmp_writer, "{line}")
+            .io_context(|| "Could not write to the contig tmp file".to_string())?;
+        self.pipeline
+            .progress
+            .counters
+            .edges
+            .fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Write a decompressed alignment of the current edge together with its read segment,
+    /// given the estimated length of its edge so far.
+    pub fn add_alignment(
+        &mut self,
+        line: &Wtdbg2CtgLayLine,
+        context: &LineContext,
+        sequence: Vec<u8>,
+        edge_length: u64,
+    ) -> Result<()> {
+        let contig = current_contig(&mut self.contig)?;
+        let (read_id, direction, length, original_length) = match line {
+            Wtdbg2CtgLayLine::Alignment {
+                read_id,
+                direction,
+                offset,
+                length,
+                original_offset,
+                original_length,
+                original_direction,
+                read_length,
+                ..
+            } => {
+                if let Some(alignment_map_writer) = &mut self.alignment_map_writer {
+                    alignment_map_writer.add_record(AlignmentMapping {
+                        edge_index: context.edge_index,
+                        alignment_index: context.alignment_index,
+                        read_id: read_id.clone(),
+                        direction: *direction,
+                        original_offset: *original_offset,
+                        original_length: *original_length,
+                        offset: *offset,
+                        length: *length,
+                    });
+                }
+                if let Some(paf_writer) = &mut self.paf_writer {
+                    paf_writer.add_record(PafRecord {
+                        read_id: read_id.clone(),
+                        read_length: *read_length,
+                        read_start: *offset,
+                        read_end: alignment_limit(*offset, *length).map_err(|error| {
+                            error.at(format_args!("Read {}", String::from_utf8_lossy(read_id)))
+                        })?,
+                        direction: *direction,
+                        contig_start: contig.edge_offset,
+                    });
+                }
+                if direction != original_direction {
+                    self.strand_correction_count += 1;
+                    if let Some(strand_report) = &mut self.strand_report {
+                        let direction_str = |direction| if direction { '+' } else { '-' };
+                        writeln!(
+                            strand_report,
+                            "{}\t{}\t{}\t{}\t{}\t{}",
+                            contig.statistics.name,
+                            context.edge_index,
+                            context.alignment_index,
+                            String::from_utf8_lossy(read_id),
+                            direction_str(*original_direction),
+                            direction_str(*direction),
+                        )
+                        .io_context(|| "Could not write to the strand report".to_string())?;
+                    }
+                }
+                (read_id, *direction, *length, *original_length)
+            }
+            _ => return Err(Error::Consistency(format!("Not an alignment: {line:?}"))),
+        };
+
+        if let Some(anomaly_detector) = &mut self.anomaly_detector {
+            anomaly_detector.add_alignment(AlignmentRatio {
+                edge_index: context.edge_index,
+                alignment_index: context.alignment_index,
+                read_id: read_id.clone(),
+                direction,
+                compressed_length: original_length,
+                decompressed_length: length,
+            });
+        }
+        contig.statistics.alignment_count += 1;
+        contig.statistics.compressed_bases += original_length as u64;
+        contig.statistics.decompressed_bases += length as u64;
+        contig.last_edge_length = edge_length;
+
+        write!(self.tmp_writer, "{line}")
+            .and_then(|()| self.tmp_writer.write_all(&sequence))
+            .and_then(|()| self.tmp_writer.write_all(b"\n"))
+            .io_context(|| "Could not write to the contig tmp file".to_string())?;
+        let counters = &self.pipeline.progress.counters;
+        counters.alignments.fetch_add(1, Ordering::Relaxed);
+        counters
+            .bases
+            .fetch_add(sequence.len() as u64, Ordering::Relaxed);
+        self.pipeline.memory_budget.release(sequence.len());
+        Ok(())
+    }
+
+    /// Finish the last contig, flush all outputs and remove the tmp file.
+    pub fn finish(mut self) -> Result<()> {
+        self.finish_contig(None)?;
+        let configuration = self.pipeline.configuration;
+        if let Some(paf_writer) = self.paf_writer {
+            let record_count = paf_writer.finish()?;
+            info!("Wrote {record_count} read placements");
+        }
+        if let Some(alignment_map_writer) = self.alignment_map_writer {
+            let mapping_count = alignment_map_writer.finish()?;
+            info!("Wrote {mapping_count} alignment mappings");
+        }
+        if let Some(chain_writer) = self.chain_writer {
+            let chain_count = chain_writer.finish()?;
+            info!("Wrote {chain_count} chains");
+        }
+        if let Some(draft_writer) = self.draft_writer {
+            let contig_count = draft_writer.finish().io_context(draft_write_error)?;
+            info!("Wrote {contig_count} draft contigs");
+        }
+        if let Some(consensus_dispatcher) = self.consensus_dispatcher {
+            consensus_dispatcher.finish()?;
+        }
+        if configuration.segment_checks.fix_strand {
+            info!(
+                "Corrected the direction of {} alignments",
+                self.strand_correction_count
+            );
+        }
+        if let Some(strand_report) = &mut self.strand_report {
+            strand_report
+                .flush()
+                .io_context(|| "Could not write to the strand report".to_string())?;
+        }
+        if let Some(anomaly_detector) = &mut self.anomaly_detector {
+            let (anomaly_count, dropped_count) = anomaly_detector.finish()?;
+            info!("Found {anomaly_count} anomalous alignments and dropped {dropped_count}");
+        }
+        self.shards.flush()?;
+        if configuration.shards > 1 {
+            self.shards
+                .write_manifest(OutputShards::manifest_path(&configuration.output))?;
+        }
+
+        drop(self.tmp_writer);
+        fs::remove_file(&self.tmp_path)
+            .io_context(|| format!("Could not remove {:?}", self.tmp_path))
+    }
+
+    /// Write the current contig, if any, with its decompressed length, followed by its edges and alignments,
+    /// and finish it in all side outputs.
+    /// If the input offset of the next contig is given, then a checkpoint may be stored after the contig.
+    fn finish_contig(&mut self, next_input_offset: Option<u64>) -> Result<()> {
+        let mut contig = if let Some(contig) = self.contig.take() {
+            contig
+        } else {
+            return Ok(());
+        };
+        let (anomaly_count, dropped_alignments) = self.detect_anomalies()?;
+        let contig_length = contig.edge_offset + contig.last_edge_length;
+        if let Wtdbg2CtgLayLine::Contig { length, .. } = &mut contig.line {
+            *length = contig_length;
+        }
+        self.write_contig(&contig.line, contig_length, &dropped_alignments)?;
+        let contig_name = contig.statistics.name.as_str();
+        self.add_kept_segments(contig_name, &dropped_alignments)?;
+        self.clear_tmp_file()?;
+
+        if let Some(paf_writer) = &mut self.paf_writer {
+            paf_writer.finish_contig(contig_name, contig_length, &dropped_alignments)?;
+        }
+        if let Some(alignment_map_writer) = &mut self.alignment_map_writer {
+            alignment_map_writer.finish_contig(contig_name, contig_length, &dropped_alignments)?;
+        }
+        if let Some(chain_writer) = &mut self.chain_writer {
+            chain_writer.finish_contig(contig_length)?;
+        }
+        if let Some(draft_writer) = &mut self.draft_writer {
+            draft_writer.finish_contig().io_context(draft_write_error)?;
+        }
+
+        let mut statistics = contig.statistics;
+        statistics.decompressed_length = contig_length;
+        statistics.anomalies = anomaly_count;
+        statistics.dropped_alignments = dropped_alignments.len() as u64;
+        let contig_name = statistics.name.clone();
+        self.pipeline.run_statistics.add_contig(statistics)?;
+
+        let checkpointer = &mut self.checkpointer;
+        checkpointer.completed_contigs += 1;
+        if let Some(next_input_offset) = next_input_offset {
+            if checkpointer.last_time.elapsed() >= checkpointer.interval {
+                // The checkpoint must not point past data that is not yet on disk.
+                let shards = self.shards.sync()?;
+                Checkpoint {
+                    completed_contigs: checkpointer.completed_contigs,
+                    last_contig_name: contig_name,
+                    shards,
+                    last_contig_offset: contig.input_offset,
+                    input_offset: next_input_offset,
+                    index_path: checkpointer.index_path.clone(),
+                    input_fingerprint: checkpointer.input_fingerprint.clone(),
+                    reads_fingerprint: checkpointer.reads_fingerprint.clone(),
+                    options: checkpointer.options.clone(),
+                }
+                .store(&checkpointer.path)?;
+                trace!(
+                    "Stored checkpoint after {} contigs",
+                    checkpointer.completed_contigs
+                );
+                checkpointer.last_time = Instant::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Detect the anomalies of the current contig, if anomalies are detected at all.
+    /// Returns the number of anomalies and the sorted indices of the alignments to drop among all alignments of the contig.
+    fn detect_anomalies(&mut self) -> Result<(u64, Vec<usize>)> {
+        if let Some(anomaly_detector) = &mut self.anomaly_detector {
+            let anomalies = anomaly_detector.finish_contig()?;
+            Ok((
+                anomalies.len() as u64,
+                anomalies
+                    .iter()
+                    .filter(|anomaly| anomaly.dropped)
+                    .map(|anomaly| anomaly.ordinal)
+                    .collect(),
+            ))
+        } else {
+            Ok((0, Vec::new()))
+        }
+    }
+
+    /// Write the contig line to the output, followed by the edges and alignments from the tmp file.
+    /// The alignments with the given indices among all alignments of the contig are left out.
+    fn write_contig(
+        &mut self,
+        contig_line: &Wtdbg2CtgLayLine,
+        contig_length: u64,
+        dropped_alignments: &[usize],
+    ) -> Result<()> {
+        let shard = self.shards.next_shard();
+        let output_path = self.shards.path(shard).to_owned();
+        let output_writer = self.shards.writer(shard);
+        writeln!(output_writer, "{contig_line}")
+            .io_context(|| format!("Could not write to {output_path:?}"))?;
+
+        // Append the tmp file to the actual file, now that we know how long the decompressed contig is.
+        self.tmp_writer
+            .flush()
+            .io_context(|| "Could not write to the contig tmp file".to_string())?;
+        let io_buffer_size = self.tmp_writer.capacity();
+        let tmp_file = self.tmp_writer.get_mut();
+        tmp_file
+            .seek(SeekFrom::Start(0))
+            .io_context(|| "Could not seek in the contig tmp file".to_string())?;
+        if !dropped_alignments.is_empty() {
+            copy_without_alignments(
+                tmp_file,
+                output_writer,
+                &output_path,
+                dropped_alignments,
+                io_buffer_size,
+            )?;
+        } else {
+            loop {
+                let length = tmp_file
+                    .read(&mut self.append_file_buffer)
+                    .io_context(|| "Could not read from the contig tmp file".to_string())?;
+                if length > 0 {
+                    output_writer
+                        .write_all(&self.append_file_buffer[..length])
+                        .io_context(|| format!("Could not write to {output_path:?}"))?;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.shards.complete_contig(shard, contig_length);
+        Ok(())
+    }
+
+    /// Read the edges and alignments of the current contig back from the tmp file and add them to the draft
+    /// and the consensus. The alignments with the given indices among all alignments of the contig are left out,
+    /// such that the draft and the consensus are built from the same alignments as the output layout.
+    fn add_kept_segments(&mut self, contig_name: &str, dropped_alignments: &[usize]) -> Result<()> {
+        let mut draft_writer = self.draft_writer.as_mut();
+        let mut consensus_dispatcher = self.consensus_dispatcher.as_mut();
+        if draft_writer.is_none() && consensus_dispatcher.is_none() {
+            return Ok(());
+        }
+        if let Some(draft_writer) = &mut draft_writer {
+            draft_writer.start_contig(contig_name);
+        }
+        if let Some(consensus_dispatcher) = &mut consensus_dispatcher {
+            consensus_dispatcher.start_contig(contig_name)?;
+        }
+
+        let io_buffer_size = self.tmp_writer.capacity();
+        let tmp_file = self.tmp_writer.get_mut();
+        tmp_file
+            .seek(SeekFrom::Start(0))
+            .io_context(|| "Could not seek in the contig tmp file".to_string())?;
+        let mut reader = BufReader::with_capacity(io_buffer_size, &*tmp_file);
+        let mut dropped_alignments = dropped_alignments.iter().peekable();
+        let mut alignment_index = 0;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let length = reader
+                .read_until(b'\n', &mut line)
+                .io_context(|| "Could not read from the contig tmp file".to_string())?;
+            if length == 0 {
+                return Ok(());
+            }
+
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            match line.first() {
+                Some(b'E') => {
+                    let line = std::str::from_utf8(line).map_err(|_| tmp_line_error(line))?;
+                    let offset = match Wtdbg2CtgLayLine::parse(line, false)? {
+                        Wtdbg2CtgLayLine::Edge { offset, .. } => offset,
+                        _ => return Err(tmp_line_error(line.as_bytes())),
+                    };
+                    if let Some(draft_writer) = &mut draft_writer {
+                        draft_writer.start_edge(offset);
+                    }
+                    if let Some(consensus_dispatcher) = &mut consensus_dispatcher {
+                        consensus_dispatcher.start_edge(offset)?;
+                    }
+                }
+                Some(b'S') => {
+                    let dropped = dropped_alignments.next_if_eq(&&alignment_index).is_some();
+                    alignment_index += 1;
+                    if dropped {
+                        continue;
+                    }
+                    // The read segment follows the five fields of the alignment.
+                    let segment = line
+                        .splitn(6, |&byte| byte == b'\t')
+                        .nth(5)
+                        .ok_or_else(|| tmp_line_error(line))?;
+                    if let Some(draft_writer) = &mut draft_writer {
+                        draft_writer.add_segment(segment);
+                    }
+                    if let Some(consensus_dispatcher) = &mut consensus_dispatcher {
+                        // The segment stays charged until its consensus was written.
+                        self.pipeline.memory_budget.charge(segment.len());
+                        consensus_dispatcher.add_segment(segment.to_vec())?;
+                    }
+                }
+                _ => return Err(tmp_line_error(line)),
+            }
+        }
+    }
+
+    /// Empty the tmp file for the next contig.
+    fn clear_tmp_file(&mut self) -> Result<()> {
+        let tmp_file = self.tmp_writer.get_mut();
+        tmp_file
+            .set_len(0)
+            .and_then(|()| tmp_file.seek(SeekFrom::Start(0)).map(|_| ()))
+            .io_context(|| "Could not truncate the contig tmp file".to_string())
+    }
+}
+
+fn current_contig(contig: &mut Option<CurrentContig>) -> Result<&mut CurrentContig> {
+    contig
+        .as_mut()
+        .ok_or_else(|| Error::Consistency("Received a line before the first contig".to_string()))
+}
+
+fn tmp_line_error(line: &[u8]) -> Error {
+    Error::Consistency(format!(
+        "Malformed line in the contig tmp file: {}",
+        String::from_utf8_lossy(line)
+    ))
+}
+
+fn draft_write_error() -> String {
+    "Could not write to the draft file".to_string()
+}
+
+/// Copy the lines of the tmp file, leaving out the alignments with the given sorted indices.
+fn copy_without_alignments(
+    tmp_file: &File,
+    output_writer: &mut BufWriter<File>,
+    output_path: &Path,
+    dropped_alignments: &[usize],
+    io_buffer_size: usize,
+) -> Result<()> {
+    let mut reader = BufReader::with_capacity(io_buffer_size, tmp_file);
+    let mut dropped_alignments = dropped_alignments.iter().peekable();
+    let mut alignment_index = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let length = reader
+            .read_until(b'\n', &mut line)
+            .io_context(|| "Could not read from the contig tmp file".to_string())?;
+        if length == 0 {
+            return Ok(());
+        }
+
+        if line.first() == Some(&b'S') {
+            let dropped = dropped_alignments.next_if_eq(&&alignment_index).is_some();
+            alignment_index += 1;
+            if dropped {
+                continue;
+            }
+        }
+        output_writer
+            .write_all(&line)
+            .io_context(|| format!("Could not write to {output_path:?}"))?;
+    }
+}
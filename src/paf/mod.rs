@@ -0,0 +1,73 @@
+use crate::record_writer::AlignmentRecord;
+use std::io::Write;
+
+/// A read segment placed at the offset of its edge on a contig.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PafRecord {
+    pub read_id: Vec<u8>,
+    pub read_length: usize,
+    /// The interval of the segment on the forward strand of the read.
+    pub read_start: usize,
+    pub read_end: usize,
+    /// True for forwards (+), false for backwards (-).
+    pub direction: bool,
+    pub contig_start: u64,
+}
+
+impl PafRecord {
+    /// Format the record as PAF line for the given contig, without the trailing newline.
+    /// The segment is assumed to cover the contig without gaps from its start, clipped to the contig end.
+    /// Since the placement is not based on a base-level alignment,
+    /// the number of matching bases is zero and the mapping quality is 255 (missing).
+    pub fn to_paf_line(&self, contig_name: &str, contig_length: u64) -> String {
+        let contig_start = self.contig_start.min(contig_length);
+        let contig_end =
+            (contig_start + (self.read_end - self.read_start) as u64).min(contig_length);
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{contig_name}\t{contig_length}\t{contig_start}\t{contig_end}\t0\t{}\t255",
+            String::from_utf8_lossy(&self.read_id),
+            self.read_length,
+            self.read_start,
+            self.read_end,
+            if self.direction { '+' } else { '-' },
+            (self.read_end - self.read_start).max((contig_end - contig_start) as usize),
+        )
+    }
+}
+
+impl AlignmentRecord for PafRecord {
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        contig_name: &str,
+        contig_length: u64,
+    ) -> std::io::Result<()> {
+        write!(writer, "{}", self.to_paf_line(contig_name, contig_length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paf::PafRecord;
+
+    #[test]
+    fn test_to_paf_line() {
+        let record = PafRecord {
+            read_id: b"read1".to_vec(),
+            read_length: 1000,
+            read_start: 100,
+            read_end: 400,
+            direction: false,
+            contig_start: 50,
+        };
+        assert_eq!(
+            record.to_paf_line("ctg1", 1000),
+            "read1\t1000\t100\t400\t-\tctg1\t1000\t50\t350\t0\t300\t255"
+        );
+        // Clipped to the end of the contig.
+        assert_eq!(
+            record.to_paf_line("ctg1", 200),
+            "read1\t1000\t100\t400\t-\tctg1\t200\t50\t200\t0\t300\t255"
+        );
+    }
+}
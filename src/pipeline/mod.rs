@@ -0,0 +1,755 @@
+use crate::consensus::ConsensusTask;
+use crate::contig_filter::ContigFilter;
+use crate::decompress::{
+    alignment_limit, compress, decompress, detect_strand, homopolymer_compress, matches_compressed,
+    oriented_segment, run_count,
+};
+use crate::draft::DraftWriter;
+use crate::error::{Cancellation, Error, IoContext, Result};
+use crate::fasta_sequence_index::FastaSequenceIndex;
+use crate::memory_budget::MemoryBudget;
+use crate::output_sink::OutputSink;
+use crate::progress::Progress;
+use crate::read_cache::ReadCache;
+use crate::reorder_buffer::ReorderBuffer;
+use crate::rescue::{RescueCounts, Rescuer};
+use crate::statistics::{RunStatistics, StageTimer};
+use crate::wtdbg2_ctg_lay::{LineContext, Wtdbg2CtgLayLine, Wtdbg2CtgLayLineWithContext};
+use crate::Configuration;
+use crossbeam::channel::{Receiver, Sender};
+use crossbeam::thread::{Scope, ScopedJoinHandle};
+use log::{debug, info, trace};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// An input line with its byte offset in the input.
+pub type InputLine = (String, u64);
+/// An alignment together with its read.
+pub type FetchedAlignment = (Wtdbg2CtgLayLineWithContext, Arc<FetchedRead>);
+/// A line, and for alignments their decompressed segment.
+pub type DecompressedLine = (Wtdbg2CtgLayLineWithContext, Option<Vec<u8>>);
+/// A line in input order, and for alignments their decompressed segment and the estimated length of their edge.
+pub type SortedLine = (Wtdbg2CtgLayLineWithContext, Option<(Vec<u8>, u64)>);
+/// A consensus task with its sequence number.
+pub type NumberedConsensusTask = (u64, ConsensusTask);
+
+/// A read fetched from the read index.
+pub struct FetchedRead {
+    sequence: Vec<u8>,
+    /// The length of the homopolymer compressed sequence, only computed by the compress subcommand.
+    compressed_length: Option<usize>,
+}
+
+/// Whether the pipeline decompresses the reads of a layout or compresses them again.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Replace the homopolymer compressed reads with the normal reads.
+    Decompress,
+    /// Translate the coordinates back to the compressed reads, as the inverse of decompression.
+    Compress,
+}
+
+/// The state shared by all stages of the decompression pipeline.
+#[derive(Clone, Copy)]
+pub struct Pipeline<'a> {
+    pub configuration: &'a Configuration,
+    pub mode: Mode,
+    pub cancellation: &'a Cancellation,
+    pub memory_budget: &'a MemoryBudget,
+    pub run_statistics: &'a RunStatistics,
+    pub progress: &'a Progress,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Spawn a named pipeline stage on its channel endpoints into the given scope,
+    /// which cancels all other stages if it fails.
+    /// The stage only borrows the endpoints, such that they stay open until the cancellation flag is set.
+    pub fn spawn<
+        'scope,
+        Endpoints: Send + 'a,
+        Stage: FnOnce(&Endpoints) -> Result<()> + Send + 'a,
+    >(
+        self,
+        scope: &'scope Scope<'a>,
+        name: &str,
+        endpoints: Endpoints,
+        stage: Stage,
+    ) -> Result<(String, ScopedJoinHandle<'scope, Result<()>>)> {
+        let cancellation = self.cancellation;
+        let thread = scope
+            .builder()
+            .name(name.to_string())
+            .spawn(move |_| cancellation.run(endpoints, stage))
+            .io_context(|| format!("Could not spawn thread {name}"))?;
+        Ok((name.to_string(), thread))
+    }
+
+    /// Read the lines of the input within the given byte ranges of the input file.
+    pub fn read_input(
+        self,
+        input_file: File,
+        input_ranges: Vec<Range<u64>>,
+        sender: &Sender<InputLine>,
+    ) -> Result<()> {
+        let configuration = self.configuration;
+        let stage_timer = StageTimer::new();
+        let mut reader = BufReader::with_capacity(configuration.common.io_buffer_size, input_file);
+        let mut line_number = 0;
+        for input_range in input_ranges {
+            let mut input_offset = reader
+                .seek(SeekFrom::Start(input_range.start))
+                .io_context(|| format!("Could not seek in {:?}", configuration.input))?;
+            while input_offset < input_range.end {
+                self.cancellation.check()?;
+                let mut line = String::new();
+                let length = reader.read_line(&mut line).io_context(|| {
+                    format!("Could not read line {} of the input", line_number + 1)
+                })?;
+                if length == 0 {
+                    break;
+                }
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+
+                line_number += 1;
+                trace!("Read line {line_number}");
+                self.memory_budget.acquire(line.len(), self.cancellation)?;
+                sender
+                    .send((line, input_offset))
+                    .map_err(|_| Error::Cancelled)?;
+                input_offset += length as u64;
+                self.progress
+                    .counters
+                    .input_bytes
+                    .fetch_add(length as u64, Ordering::Relaxed);
+            }
+        }
+        self.run_statistics
+            .add_stage_time("input_reader", stage_timer.busy())
+    }
+
+    /// Parse the input lines of the selected contigs, and number them in input order.
+    /// Alignments are sent to `alignment_sender` to fetch their reads, while contigs and edges go to the sorter directly.
+    pub fn parse_input(
+        self,
+        contig_filter: ContigFilter,
+        receiver: &Receiver<InputLine>,
+        alignment_sender: &Sender<Wtdbg2CtgLayLineWithContext>,
+        line_sender: &Sender<DecompressedLine>,
+    ) -> Result<()> {
+        let configuration = self.configuration;
+        let mut context = LineContext::default();
+        let mut line_number = 0;
+        // Every forwarded line gets the next sequence number, such that the sorter can restore their order.
+        let mut sequence_number = 0;
+        let mut contig_count = 0;
+        let mut edge_count = 0;
+        let mut alignment_count = 0;
+        // Lines of contigs that are not selected are skipped without looking up their reads.
+        let mut skip_contig = false;
+        let mut total_contig_count = 0;
+        let mut selected_contig_count = 0;
+        // Only collect the referenced reads if they are reported.
+        let mut referenced_reads = configuration.stats.is_some().then(HashSet::new);
+        let mut stage_timer = StageTimer::new();
+
+        while let Ok((input, input_offset)) = stage_timer.recv(receiver) {
+            self.cancellation.check()?;
+            line_number += 1;
+            context.input_offset = input_offset;
+            context.input_length = input.len();
+            context.sequence_number = sequence_number;
+            // The embedded sequence is only needed to check the coordinates.
+            let line = Wtdbg2CtgLayLine::parse(
+                &input,
+                configuration.segment_checks.fix_strand || configuration.segment_checks.rescue,
+            )
+            .map_err(|error| error.at(format_args!("Line {line_number}")))?;
+            match line {
+                Wtdbg2CtgLayLine::Contig {
+                    ref name, length, ..
+                } => {
+                    trace!("Parsed contig line {input}");
+                    total_contig_count += 1;
+                    skip_contig = !contig_filter.is_selected(name, length);
+                    if skip_contig {
+                        trace!("Skipping contig {name}");
+                        self.memory_budget.release(input.len());
+                        continue;
+                    }
+                    selected_contig_count += 1;
+
+                    context.contig_index = contig_count;
+                    context.edge_index = 0;
+                    context.alignment_index = 0;
+                    contig_count += 1;
+                    edge_count = 0;
+                    alignment_count = 0;
+                    sequence_number += 1;
+                    send_line(line_sender, line, &context, None)?;
+                }
+                Wtdbg2CtgLayLine::Edge { .. } if skip_contig => {
+                    self.memory_budget.release(input.len());
+                }
+                Wtdbg2CtgLayLine::Edge { .. } => {
+                    trace!("Parsed edge line {input}");
+                    if contig_count == 0 {
+                        return Err(Error::Input(format!(
+                            "Line {line_number}: edge before the first contig"
+                        )));
+                    }
+                    context.edge_index = edge_count;
+                    context.alignment_index = 0;
+                    edge_count += 1;
+                    alignment_count = 0;
+                    sequence_number += 1;
+                    send_line(line_sender, line, &context, None)?;
+                }
+                Wtdbg2CtgLayLine::Alignment { .. } if skip_contig => {
+                    self.memory_budget.release(input.len());
+                }
+                Wtdbg2CtgLayLine::Alignment { ref read_id, .. } => {
+                    trace!("Parsed alignment line");
+                    if edge_count == 0 {
+                        return Err(Error::Input(format!(
+                            "Line {line_number}: alignment before the first edge"
+                        )));
+                    }
+                    if let Some(referenced_reads) = &mut referenced_reads {
+                        // Look up first to clone only new ids, since most reads are referenced repeatedly.
+                        if !referenced_reads.contains(read_id) {
+                            referenced_reads.insert(read_id.clone());
+                        }
+                    }
+                    context.alignment_index = alignment_count;
+                    alignment_count += 1;
+                    sequence_number += 1;
+                    alignment_sender
+                        .send(Wtdbg2CtgLayLineWithContext {
+                            line,
+                            context: context.clone(),
+                        })
+                        .map_err(|_| Error::Cancelled)?;
+                }
+            }
+        }
+
+        self.cancellation.check()?;
+        info!("Selected {selected_contig_count} out of {total_contig_count} contigs");
+        if let Some(referenced_reads) = referenced_reads {
+            self.run_statistics
+                .set_reads_referenced(referenced_reads.len() as u64)?;
+        }
+        self.run_statistics
+            .add_stage_time("input_parser", stage_timer.busy())
+    }
+
+    /// Decorate alignments with the sequences of their reads, which are fetched from the read index or the read cache.
+    pub fn fetch_reads(
+        self,
+        read_index: &FastaSequenceIndex,
+        read_cache: &Mutex<ReadCache<FetchedRead>>,
+        receiver: &Receiver<Wtdbg2CtgLayLineWithContext>,
+        sender: &Sender<FetchedAlignment>,
+    ) -> Result<()> {
+        let mut stage_timer = StageTimer::new();
+        while let Ok(line_with_context) = stage_timer.recv(receiver) {
+            self.cancellation.check()?;
+            let read_id = match &line_with_context.line {
+                Wtdbg2CtgLayLine::Alignment { read_id, .. } => read_id,
+                _ => {
+                    return Err(Error::Consistency(format!(
+                        "Not an alignment: {line_with_context:?}"
+                    )))
+                }
+            };
+            let cached_read = lock_read_cache(read_cache)?.get(read_id);
+            let read = if let Some(read) = cached_read {
+                read
+            } else {
+                // Fetch without holding the lock, such that fetches can run in parallel.
+                let mut sequence = Vec::new();
+                trace!("Reading read {}", String::from_utf8_lossy(read_id));
+                read_index.get_sequence(read_id, &mut sequence)?;
+                let compressed_length = (self.mode == Mode::Compress).then(|| run_count(&sequence));
+                let read = Arc::new(FetchedRead {
+                    sequence,
+                    compressed_length,
+                });
+                lock_read_cache(read_cache)?.insert(read_id, read.clone());
+                read
+            };
+            self.memory_budget.charge(read.sequence.len());
+            sender
+                .send((line_with_context, read))
+                .map_err(|_| Error::Cancelled)?;
+        }
+
+        self.cancellation.check()?;
+        self.run_statistics
+            .add_stage_time("read_sequence_reader", stage_timer.busy())
+    }
+
+    /// Translate the coordinates of the alignments and extract their read segments.
+    pub fn decompress_alignments(
+        self,
+        receiver: &Receiver<FetchedAlignment>,
+        sender: &Sender<DecompressedLine>,
+    ) -> Result<()> {
+        let segment_checks = &self.configuration.segment_checks;
+        let rescuer = segment_checks.rescue.then(|| Rescuer {
+            window: segment_checks.rescue_window,
+            k: segment_checks.rescue_k,
+        });
+        let mut stage_timer = StageTimer::new();
+        let mut rescue_counts = RescueCounts::default();
+        while let Ok((line_with_context, read)) = stage_timer.recv(receiver) {
+            self.cancellation.check()?;
+            let Wtdbg2CtgLayLineWithContext { mut line, context } = line_with_context;
+            trace!("Decompressing {context:?}");
+            let shifted_sequence = decompress_alignment(
+                &mut line,
+                &read.sequence,
+                read.compressed_length,
+                segment_checks.fix_strand,
+                rescuer.as_ref(),
+                &mut rescue_counts,
+            )?;
+            if let Some(shifted_sequence) = &shifted_sequence {
+                self.memory_budget.charge(shifted_sequence.len());
+            }
+            self.memory_budget.release(read.sequence.len());
+            // Dropped alignments are forwarded without sequence, such that the sorter does not wait for them.
+            sender
+                .send((
+                    Wtdbg2CtgLayLineWithContext { line, context },
+                    shifted_sequence,
+                ))
+                .map_err(|_| Error::Cancelled)?;
+        }
+
+        self.cancellation.check()?;
+        self.run_statistics.add_rescue_counts(rescue_counts)?;
+        self.run_statistics
+            .add_stage_time("decompressor", stage_timer.busy())
+    }
+
+    /// Put the lines back into input order, since decompression with multiple threads shuffles the alignments a bit,
+    /// and rescale the edge offsets to the decompressed alignment lengths.
+    pub fn sort_lines(
+        self,
+        receiver: &Receiver<DecompressedLine>,
+        sender: &Sender<SortedLine>,
+    ) -> Result<()> {
+        let mut stage_timer = StageTimer::new();
+        let mut reorder_buffer = ReorderBuffer::new();
+        let mut alignment_count = 0;
+        let mut original_alignment_length_sum = 0;
+        let mut shifted_alignment_length_sum = 0;
+        let mut original_previous_offset = 0;
+        let mut shifted_previous_offset = 0;
+        // Edges without alignments are scaled like the previous edge.
+        let mut scale = 1.0;
+
+        while let Ok((Wtdbg2CtgLayLineWithContext { line, context }, shifted_sequence)) =
+            stage_timer.recv(receiver)
+        {
+            self.cancellation.check()?;
+            trace!("Received {context:?}");
+            reorder_buffer.insert(context.sequence_number, (line, context, shifted_sequence))?;
+
+            while let Some((mut line, context, shifted_sequence)) = reorder_buffer.pop() {
+                match &mut line {
+                    Wtdbg2CtgLayLine::Contig { .. } => {
+                        alignment_count = 0;
+                        original_alignment_length_sum = 0;
+                        shifted_alignment_length_sum = 0;
+                        original_previous_offset = 0;
+                        shifted_previous_offset = 0;
+                        scale = 1.0;
+                        expect_no_sequence(&line, &shifted_sequence)?;
+                        send_line(sender, line, &context, None)?;
+                    }
+                    Wtdbg2CtgLayLine::Edge { offset, .. } => {
+                        let original_offset = *offset;
+                        if original_alignment_length_sum > 0 {
+                            scale = shifted_alignment_length_sum as f64
+                                / original_alignment_length_sum as f64;
+                        }
+                        *offset = shifted_previous_offset
+                            + ((*offset - original_previous_offset) as f64 * scale).round() as u64;
+                        alignment_count = 0;
+                        original_alignment_length_sum = 0;
+                        shifted_alignment_length_sum = 0;
+                        original_previous_offset = original_offset;
+                        shifted_previous_offset = *offset;
+                        expect_no_sequence(&line, &shifted_sequence)?;
+                        send_line(sender, line, &context, None)?;
+                    }
+                    Wtdbg2CtgLayLine::Alignment { .. } if shifted_sequence.is_none() => {
+                        trace!("Dropping alignment {context:?}");
+                        self.memory_budget.release(context.input_length);
+                    }
+                    Wtdbg2CtgLayLine::Alignment {
+                        length,
+                        original_length,
+                        ..
+                    } => {
+                        alignment_count += 1;
+                        original_alignment_length_sum += *original_length;
+                        shifted_alignment_length_sum += *length;
+                        let estimated_length = (shifted_alignment_length_sum as f64
+                            / alignment_count as f64)
+                            .round() as u64;
+                        let shifted_sequence = shifted_sequence.ok_or_else(|| {
+                            Error::Consistency(format!("Alignment has no sequence: {line:?}"))
+                        })?;
+                        send_line(
+                            sender,
+                            line,
+                            &context,
+                            Some((shifted_sequence, estimated_length)),
+                        )?;
+                    }
+                }
+            }
+        }
+
+        self.cancellation.check()?;
+        if !reorder_buffer.is_empty() {
+            return Err(Error::Consistency(format!(
+                "{} lines could not be put into order",
+                reorder_buffer.len()
+            )));
+        }
+        self.run_statistics
+            .add_stage_time("sorter", stage_timer.busy())
+    }
+
+    /// Pass the sorted lines to the output sink, and release them from the memory budget.
+    pub fn write_output(self, mut sink: OutputSink, receiver: &Receiver<SortedLine>) -> Result<()> {
+        let mut stage_timer = StageTimer::new();
+        while let Ok((Wtdbg2CtgLayLineWithContext { line, context }, sequence_and_length)) =
+            stage_timer.recv(receiver)
+        {
+            self.cancellation.check()?;
+            trace!("Writing line {line:?}");
+            match line {
+                Wtdbg2CtgLayLine::Contig { .. } => sink.start_contig(line, &context)?,
+                Wtdbg2CtgLayLine::Edge { .. } => sink.start_edge(&line)?,
+                Wtdbg2CtgLayLine::Alignment { .. } => {
+                    let (sequence, edge_length) = sequence_and_length.ok_or_else(|| {
+                        Error::Consistency(format!("Alignment has no sequence: {line:?}"))
+                    })?;
+                    sink.add_alignment(&line, &context, sequence, edge_length)?;
+                }
+            }
+            self.memory_budget.release(context.input_length);
+        }
+
+        self.cancellation.check()?;
+        sink.finish()?;
+        self.run_statistics
+            .add_stage_time("output_writer", stage_timer.busy())
+    }
+
+    /// Compute the consensus of the read segments of each edge.
+    pub fn compute_consensus(
+        self,
+        receiver: &Receiver<NumberedConsensusTask>,
+        sender: &Sender<NumberedConsensusTask>,
+    ) -> Result<()> {
+        let mut stage_timer = StageTimer::new();
+        while let Ok((sequence_number, task)) = stage_timer.recv(receiver) {
+            self.cancellation.check()?;
+            trace!("Computing the consensus of task {sequence_number}");
+            let segment_length = task_length(&task);
+            let task = task.compute_consensus();
+            self.memory_budget.charge(task_length(&task));
+            self.memory_budget.release(segment_length);
+            sender
+                .send((sequence_number, task))
+                .map_err(|_| Error::Cancelled)?;
+        }
+
+        self.cancellation.check()?;
+        self.run_statistics
+            .add_stage_time("consensus", stage_timer.busy())
+    }
+
+    /// Put the consensus tasks back into order and write the consensus contigs.
+    pub fn write_consensus(
+        self,
+        mut consensus_writer: DraftWriter<BufWriter<File>>,
+        receiver: &Receiver<NumberedConsensusTask>,
+    ) -> Result<()> {
+        let write_error = || "Could not write to the consensus file".to_string();
+        let mut stage_timer = StageTimer::new();
+        let mut reorder_buffer = ReorderBuffer::new();
+        let mut has_contig = false;
+        while let Ok((sequence_number, task)) = stage_timer.recv(receiver) {
+            self.cancellation.check()?;
+            reorder_buffer.insert(sequence_number, task)?;
+            while let Some(task) = reorder_buffer.pop() {
+                match task {
+                    ConsensusTask::Contig { name } => {
+                        if has_contig {
+                            consensus_writer.finish_contig().io_context(write_error)?;
+                        }
+                        consensus_writer.start_contig(&name);
+                        has_contig = true;
+                    }
+                    ConsensusTask::Edge { offset, segments } => {
+                        consensus_writer.start_edge(offset);
+                        // Edges without read segments do not change the contig.
+                        for consensus in segments.iter().filter(|consensus| !consensus.is_empty()) {
+                            consensus_writer.add_segment(consensus);
+                            self.memory_budget.release(consensus.len());
+                        }
+                    }
+                }
+            }
+        }
+
+        self.cancellation.check()?;
+        if !reorder_buffer.is_empty() {
+            return Err(Error::Consistency(format!(
+                "{} consensus tasks could not be put into order",
+                reorder_buffer.len()
+            )));
+        }
+        if has_contig {
+            consensus_writer.finish_contig().io_context(write_error)?;
+        }
+        let contig_count = consensus_writer.finish().io_context(write_error)?;
+        info!("Wrote {contig_count} consensus contigs");
+        self.run_statistics
+            .add_stage_time("consensus_writer", stage_timer.busy())
+    }
+}
+
+pub fn lock_read_cache(
+    read_cache: &Mutex<ReadCache<FetchedRead>>,
+) -> Result<MutexGuard<'_, ReadCache<FetchedRead>>> {
+    read_cache
+        .lock()
+        .map_err(|_| Error::Consistency("A read sequence reader panicked".to_string()))
+}
+
+fn send_line<Sequence>(
+    sender: &Sender<(Wtdbg2CtgLayLineWithContext, Option<Sequence>)>,
+    line: Wtdbg2CtgLayLine,
+    context: &LineContext,
+    sequence: Option<Sequence>,
+) -> Result<()> {
+    sender
+        .send((
+            Wtdbg2CtgLayLineWithContext {
+                line,
+                context: context.clone(),
+            },
+            sequence,
+        ))
+        .map_err(|_| Error::Cancelled)
+}
+
+/// The number of bases of the read segments of a consensus task.
+fn task_length(task: &ConsensusTask) -> usize {
+    match task {
+        ConsensusTask::Edge { segments, .. } => segments.iter().map(Vec::len).sum(),
+        ConsensusTask::Contig { .. } => 0,
+    }
+}
+
+fn expect_no_sequence(line: &Wtdbg2CtgLayLine, sequence: &Option<Vec<u8>>) -> Result<()> {
+    if sequence.is_some() {
+        Err(Error::Consistency(format!(
+            "Line has a sequence, but is not an alignment: {line:?}"
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Translate the coordinates of an alignment line to the uncompressed read
+/// and extract its uncompressed segment in the direction of the alignment.
+/// If the length of the compressed read is given, the coordinates are translated from the uncompressed
+/// to the compressed read instead, and the extracted segment is compressed.
+/// Returns `None` if the alignment does not match its embedded compressed segment and cannot be rescued.
+pub fn decompress_alignment(
+    line: &mut Wtdbg2CtgLayLine,
+    read: &[u8],
+    compressed_read_length: Option<usize>,
+    fix_strand: bool,
+    rescuer: Option<&Rescuer>,
+    rescue_counts: &mut RescueCounts,
+) -> Result<Option<Vec<u8>>> {
+    let (read_id, direction, offset, length, compressed_sequence, read_length) = match &mut *line {
+        Wtdbg2CtgLayLine::Alignment {
+            read_id,
+            direction,
+            offset,
+            length,
+            compressed_sequence,
+            read_length,
+            ..
+        } => (
+            read_id,
+            direction,
+            offset,
+            length,
+            compressed_sequence,
+            read_length,
+        ),
+        _ => return Err(Error::Consistency(format!("Not an alignment: {line:?}"))),
+    };
+    let read_error =
+        |error: Error| error.at(format_args!("Read {}", String::from_utf8_lossy(read_id)));
+
+    let limit = alignment_limit(*offset, *length).map_err(read_error)?;
+    if let Some(compressed_read_length) = compressed_read_length {
+        let (compressed_offset, compressed_limit) =
+            compress(*offset, limit, read).map_err(read_error)?;
+        let segment = oriented_segment(read, *offset, limit, *direction).map_err(read_error)?;
+        let compressed_segment = homopolymer_compress(&segment);
+        *compressed_sequence = None;
+        *read_length = compressed_read_length;
+        *offset = compressed_offset;
+        *length = compressed_limit - compressed_offset;
+        return Ok(Some(compressed_segment));
+    }
+
+    let mut interval = match decompress(*offset, limit, read) {
+        Ok(interval) => Some(interval),
+        // Coordinates out of the bounds of the read are the typical case for a rescue.
+        Err(_) if rescuer.is_some() && compressed_sequence.is_some() => None,
+        Err(error) => return Err(read_error(error)),
+    };
+
+    if let (true, Some(compressed_sequence)) = (fix_strand, compressed_sequence.as_deref()) {
+        if let Some((shifted_offset, shifted_limit)) = interval {
+            if let Some(detected_direction) = detect_strand(
+                &read[shifted_offset..shifted_limit],
+                compressed_sequence,
+                *direction,
+            ) {
+                *direction = detected_direction;
+            } else {
+                debug!(
+                    "Could not verify the direction of read {}",
+                    String::from_utf8_lossy(read_id)
+                );
+            }
+        }
+    }
+
+    if let (Some(rescuer), Some(compressed_sequence)) = (rescuer, compressed_sequence.as_deref()) {
+        let matches = interval.map_or(false, |(shifted_offset, shifted_limit)| {
+            matches_compressed(
+                &read[shifted_offset..shifted_limit],
+                compressed_sequence,
+                *direction,
+            )
+        });
+        if !matches {
+            if let Some(rescued) =
+                rescuer.rescue(read, *offset, limit, *direction, compressed_sequence)
+            {
+                debug!(
+                    "Rescued read {} from {}..{limit} to {}..{}",
+                    String::from_utf8_lossy(read_id),
+                    *offset,
+                    rescued.start,
+                    rescued.end
+                );
+                interval = Some(decompress(rescued.start, rescued.end, read).map_err(read_error)?);
+                rescue_counts.rescued += 1;
+            } else {
+                debug!(
+                    "Dropping read {} at {}..{limit}, since it could not be rescued",
+                    String::from_utf8_lossy(read_id),
+                    *offset
+                );
+                rescue_counts.dropped += 1;
+                return Ok(None);
+            }
+        }
+    }
+    // The embedded sequence is not needed anymore.
+    *compressed_sequence = None;
+
+    *read_length = read.len();
+    let (shifted_offset, shifted_limit) = interval.ok_or_else(|| {
+        Error::Consistency("Alignment has neither coordinates nor was dropped".to_string())
+    })?;
+    *offset = shifted_offset;
+    *length = shifted_limit - shifted_offset;
+    oriented_segment(read, shifted_offset, shifted_limit, *direction)
+        .map(Some)
+        .map_err(read_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decompress::run_count;
+    use crate::error::Error;
+    use crate::pipeline::decompress_alignment;
+    use crate::wtdbg2_ctg_lay::Wtdbg2CtgLayLine;
+
+    /// Process an alignment line like the output writer and return the output line.
+    fn process(line: &str, read: &[u8], compressed_read_length: Option<usize>) -> String {
+        let mut line = Wtdbg2CtgLayLine::parse(line, true).unwrap();
+        let segment = decompress_alignment(
+            &mut line,
+            read,
+            compressed_read_length,
+            false,
+            None,
+            &mut Default::default(),
+        )
+        .unwrap()
+        .unwrap();
+        format!("{line}{}", String::from_utf8(segment).unwrap())
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        // Compresses to ACGTAC.
+        let read = b"AACGGGTTAC";
+        for (line, decompressed_line) in [
+            ("S\tread1\t+\t1\t3\tCGT", "S\tread1\t+\t2\t6\tCGGGTT"),
+            ("S\tread1\t-\t0\t3\tCGT", "S\tread1\t-\t0\t6\tCCCGTT"),
+        ] {
+            assert_eq!(process(line, read, None), decompressed_line);
+            assert_eq!(
+                process(decompressed_line, read, Some(run_count(read))),
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn test_overflowing_alignment() {
+        let line = format!("S\tread1\t+\t{}\t3\tCGT", usize::MAX);
+        let mut line = Wtdbg2CtgLayLine::parse(&line, false).unwrap();
+        for compressed_read_length in [None, Some(6)] {
+            let result = decompress_alignment(
+                &mut line,
+                b"AACGGGTTAC",
+                compressed_read_length,
+                false,
+                None,
+                &mut Default::default(),
+            );
+            assert!(matches!(result, Err(Error::Input(_))));
+        }
+    }
+}
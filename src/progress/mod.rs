@@ -0,0 +1,318 @@
+use crate::error::{join_result, Error, IoContext, Result};
+use crossbeam::channel;
+use crossbeam::channel::RecvTimeoutError;
+use log::info;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// A long-running phase of the program whose progress is reported.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Phase {
+    IndexingReads,
+    Decompressing,
+}
+
+impl Phase {
+    /// The name of the phase in the JSON progress stream.
+    fn key(&self) -> &'static str {
+        match self {
+            Phase::IndexingReads => "indexing_reads",
+            Phase::Decompressing => "decompressing",
+        }
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase::IndexingReads => write!(f, "Indexing reads"),
+            Phase::Decompressing => write!(f, "Decompressing"),
+        }
+    }
+}
+
+/// Counters that the pipeline stages update, and that are periodically reported by a separate thread.
+#[derive(Default)]
+pub struct ProgressCounters {
+    /// Bytes of the reads file that were indexed.
+    pub read_bytes: AtomicU64,
+    /// Bytes of the input layout that were read.
+    pub input_bytes: AtomicU64,
+    pub contigs: AtomicU64,
+    pub edges: AtomicU64,
+    pub alignments: AtomicU64,
+    /// Decompressed bases written to the output.
+    pub bases: AtomicU64,
+}
+
+/// A snapshot of the counters of a phase.
+struct ProgressReport {
+    phase: Phase,
+    time: Instant,
+    done_bytes: u64,
+    total_bytes: u64,
+    contigs: u64,
+    edges: u64,
+    alignments: u64,
+    bases: u64,
+}
+
+/// Reports progress as log lines and optionally as a stream of JSON objects, one per line.
+pub struct Progress {
+    pub counters: ProgressCounters,
+    /// The time between two reports, or `None` to only report the end of each phase.
+    interval: Option<Duration>,
+    json_writer: Option<Mutex<BufWriter<File>>>,
+    read_total_bytes: u64,
+    input_total_bytes: u64,
+    start: Instant,
+}
+
+impl Progress {
+    pub fn new<P: AsRef<Path>>(
+        interval: Option<Duration>,
+        json_path: Option<P>,
+        read_total_bytes: u64,
+        input_total_bytes: u64,
+    ) -> Result<Self> {
+        let json_writer = if let Some(json_path) = json_path {
+            let json_path = json_path.as_ref();
+            Some(Mutex::new(BufWriter::new(
+                File::create(json_path).io_context(|| format!("Could not create {json_path:?}"))?,
+            )))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            counters: Default::default(),
+            interval,
+            json_writer,
+            read_total_bytes,
+            input_total_bytes,
+            start: Instant::now(),
+        })
+    }
+
+    /// Run the given phase while reporting its progress from a separate thread.
+    /// The end of the phase is reported as well if it completes successfully.
+    pub fn run<T, PhaseFn: FnOnce() -> Result<T>>(&self, phase: Phase, f: PhaseFn) -> Result<T> {
+        let phase_start = self.report(phase);
+        let (stop_sender, stop_receiver) = channel::bounded::<()>(0);
+
+        crossbeam::scope(|scope| {
+            let reporter = scope
+                .builder()
+                .name("progress_reporter".to_string())
+                .spawn(|_| -> Result<()> {
+                    let interval = if let Some(interval) = self.interval {
+                        interval
+                    } else {
+                        return Ok(());
+                    };
+                    let mut previous = self.report(phase);
+                    while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval)
+                    {
+                        let current = self.report(phase);
+                        self.write(&phase_start, &previous, &current, false)?;
+                        previous = current;
+                    }
+                    Ok(())
+                })
+                .io_context(|| "Could not spawn thread progress_reporter".to_string())?;
+
+            let result = f();
+            drop(stop_sender);
+            let reporter_result = join_result(reporter.join(), "progress_reporter");
+            let result = result?;
+            reporter_result?;
+
+            let end = self.report(phase);
+            self.write(&phase_start, &phase_start, &end, true)?;
+            Ok(result)
+        })
+        .unwrap_or_else(|_| {
+            Err(Error::Consistency(
+                "The progress reporter panicked".to_string(),
+            ))
+        })
+    }
+
+    fn report(&self, phase: Phase) -> ProgressReport {
+        let (done_bytes, total_bytes) = match phase {
+            Phase::IndexingReads => (
+                self.counters.read_bytes.load(Ordering::Relaxed),
+                self.read_total_bytes,
+            ),
+            Phase::Decompressing => (
+                self.counters.input_bytes.load(Ordering::Relaxed),
+                self.input_total_bytes,
+            ),
+        };
+        ProgressReport {
+            phase,
+            time: Instant::now(),
+            done_bytes,
+            total_bytes,
+            contigs: self.counters.contigs.load(Ordering::Relaxed),
+            edges: self.counters.edges.load(Ordering::Relaxed),
+            alignments: self.counters.alignments.load(Ordering::Relaxed),
+            bases: self.counters.bases.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Write the report `current`, with the throughput since `previous` and the ETA extrapolated from `phase_start`.
+    fn write(
+        &self,
+        phase_start: &ProgressReport,
+        previous: &ProgressReport,
+        current: &ProgressReport,
+        finished: bool,
+    ) -> Result<()> {
+        let seconds = |from: &ProgressReport| {
+            current
+                .time
+                .saturating_duration_since(from.time)
+                .as_secs_f64()
+        };
+        let throughput = |from: &ProgressReport| {
+            if seconds(from) > 0.0 {
+                (current.done_bytes - from.done_bytes) as f64 / seconds(from)
+            } else {
+                0.0
+            }
+        };
+        let bytes_per_second = throughput(if finished { phase_start } else { previous });
+        let average_bytes_per_second = throughput(phase_start);
+        let remaining_bytes = current.total_bytes.saturating_sub(current.done_bytes);
+        let eta_seconds = if finished {
+            Some(0.0)
+        } else if average_bytes_per_second > 0.0 {
+            Some(remaining_bytes as f64 / average_bytes_per_second)
+        } else {
+            None
+        };
+        let fraction = if current.total_bytes > 0 {
+            (current.done_bytes as f64 / current.total_bytes as f64).min(1.0)
+        } else {
+            1.0
+        };
+
+        let mut message = format!(
+            "{}: {:.1}% ({} / {})",
+            current.phase,
+            fraction * 100.0,
+            format_bytes(current.done_bytes as f64),
+            format_bytes(current.total_bytes as f64),
+        );
+        if current.phase == Phase::Decompressing {
+            message += &format!(
+                ", {} contigs, {} edges, {} alignments, {} bases emitted",
+                current.contigs, current.edges, current.alignments, current.bases
+            );
+        }
+        message += &format!(", {}/s", format_bytes(bytes_per_second));
+        if finished {
+            message += &format!(", done in {}", format_duration(seconds(phase_start)));
+        } else if let Some(eta_seconds) = eta_seconds {
+            message += &format!(", ETA {}", format_duration(eta_seconds));
+        }
+        info!("{message}");
+
+        if let Some(json_writer) = &self.json_writer {
+            let mut json_writer = json_writer.lock().map_err(|_| {
+                Error::Consistency("The progress stream lock was poisoned".to_string())
+            })?;
+            writeln!(
+                json_writer,
+                "{{\"phase\":\"{}\",\"finished\":{finished},\"elapsed_seconds\":{:.3},\
+                 \"done_bytes\":{},\"total_bytes\":{},\"fraction\":{fraction:.6},\
+                 \"contigs\":{},\"edges\":{},\"alignments\":{},\"bases\":{},\
+                 \"bytes_per_second\":{bytes_per_second:.1},\"eta_seconds\":{}}}",
+                current.phase.key(),
+                current
+                    .time
+                    .saturating_duration_since(self.start)
+                    .as_secs_f64(),
+                current.done_bytes,
+                current.total_bytes,
+                current.contigs,
+                current.edges,
+                current.alignments,
+                current.bases,
+                eta_seconds
+                    .map(|eta_seconds| format!("{eta_seconds:.1}"))
+                    .unwrap_or_else(|| "null".to_string()),
+            )
+            .and_then(|()| json_writer.flush())
+            .io_context(|| "Could not write to the progress stream".to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// A reader that counts the bytes read through it.
+pub struct CountingReader<'counter, R> {
+    inner: R,
+    counter: &'counter AtomicU64,
+}
+
+impl<'counter, R> CountingReader<'counter, R> {
+    pub fn new(inner: R, counter: &'counter AtomicU64) -> Self {
+        Self { inner, counter }
+    }
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.counter.fetch_add(length as u64, Ordering::Relaxed);
+        Ok(length)
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{value:.0} {}", UNITS[unit])
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    if seconds >= 3600 {
+        format!("{}h{:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m{:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{format_bytes, format_duration};
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bytes(512.0), "512 B");
+        assert_eq!(format_bytes(1536.0), "1.5 KiB");
+        assert_eq!(format_bytes(3.0 * 1024.0 * 1024.0 * 1024.0), "3.0 GiB");
+        assert_eq!(format_duration(42.4), "42s");
+        assert_eq!(format_duration(125.0), "2m05s");
+        assert_eq!(format_duration(7380.0), "2h03m");
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// A bounded cache of reads that evicts the least recently used read.
+/// Consecutive edges of a layout mostly share the same reads, so most lookups hit the cache.
+pub struct ReadCache<Read> {
+    /// The maximum number of reads in the cache.
+    capacity: usize,
+    entries: HashMap<Vec<u8>, CacheEntry<Read>>,
+    /// Maps the time of the last use of each entry to its read id.
+    recency: BTreeMap<u64, Vec<u8>>,
+    clock: u64,
+    statistics: ReadCacheStatistics,
+}
+
+struct CacheEntry<Read> {
+    read: Arc<Read>,
+    last_use: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ReadCacheStatistics {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl ReadCacheStatistics {
+    pub fn hit_rate(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            0.0
+        } else {
+            self.hits as f64 / (self.hits + self.misses) as f64
+        }
+    }
+}
+
+impl<Read> ReadCache<Read> {
+    /// Create a cache holding up to `capacity` reads. A capacity of zero disables caching.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            statistics: Default::default(),
+        }
+    }
+
+    /// Look up a read and mark it as recently used.
+    pub fn get(&mut self, id: &[u8]) -> Option<Arc<Read>> {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(id) {
+            self.statistics.hits += 1;
+            let id = self
+                .recency
+                .remove(&entry.last_use)
+                .expect("every entry has a recency");
+            entry.last_use = self.clock;
+            self.recency.insert(self.clock, id);
+            Some(entry.read.clone())
+        } else {
+            self.statistics.misses += 1;
+            None
+        }
+    }
+
+    /// Insert a read, evicting the least recently used read if the cache is full.
+    pub fn insert(&mut self, id: &[u8], read: Arc<Read>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(id) {
+            // Another fetcher inserted the read in the meantime.
+            self.recency.remove(&entry.last_use);
+            entry.last_use = self.clock;
+            self.recency.insert(self.clock, id.to_vec());
+            return;
+        }
+
+        if self.entries.len() >= self.capacity {
+            let oldest = *self.recency.keys().next().expect("the cache is not empty");
+            let oldest_id = self.recency.remove(&oldest).expect("key was just found");
+            self.entries.remove(&oldest_id);
+        }
+        self.entries.insert(
+            id.to_vec(),
+            CacheEntry {
+                read,
+                last_use: self.clock,
+            },
+        );
+        self.recency.insert(self.clock, id.to_vec());
+    }
+
+    pub fn statistics(&self) -> ReadCacheStatistics {
+        self.statistics
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::read_cache::ReadCache;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_read_cache() {
+        let mut cache = ReadCache::<Vec<u8>>::new(2);
+        assert!(cache.get(b"a").is_none());
+        cache.insert(b"a", Arc::new(b"AC".to_vec()));
+        cache.insert(b"b", Arc::new(b"GT".to_vec()));
+        assert_eq!(cache.get(b"a").unwrap().as_slice(), b"AC");
+        // b is the least recently used read now.
+        cache.insert(b"c", Arc::new(b"TT".to_vec()));
+        assert!(cache.get(b"b").is_none());
+        assert!(cache.get(b"a").is_some());
+        assert!(cache.get(b"c").is_some());
+
+        let statistics = cache.statistics();
+        assert_eq!((statistics.hits, statistics.misses), (3, 2));
+        assert!((statistics.hit_rate() - 0.6).abs() < 1e-9);
+
+        let mut cache = ReadCache::<Vec<u8>>::new(0);
+        cache.insert(b"a", Arc::new(b"AC".to_vec()));
+        assert!(cache.get(b"a").is_none());
+    }
+}
@@ -0,0 +1,118 @@
+use crate::error::{IoContext, Result};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// A buffered output file that counts the records written to it.
+pub struct RecordFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    record_count: u64,
+}
+
+impl RecordFile {
+    /// Create the file and write the header line, if any.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        io_buffer_size: usize,
+        header: Option<&str>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut writer = BufWriter::with_capacity(
+            io_buffer_size,
+            File::create(path).io_context(|| format!("Could not create {path:?}"))?,
+        );
+        if let Some(header) = header {
+            writeln!(writer, "{header}").io_context(|| format!("Could not write to {path:?}"))?;
+        }
+        Ok(Self {
+            path: path.to_owned(),
+            writer,
+            record_count: 0,
+        })
+    }
+
+    /// The number of records written so far.
+    pub fn record_count(&self) -> u64 {
+        self.record_count
+    }
+
+    /// Write a record with the given function and count it.
+    pub fn write_record(
+        &mut self,
+        write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+    ) -> Result<()> {
+        write(&mut self.writer).io_context(|| format!("Could not write to {:?}", self.path))?;
+        self.record_count += 1;
+        Ok(())
+    }
+
+    /// Flush the output and return the number of written records.
+    pub fn finish(mut self) -> Result<u64> {
+        self.writer
+            .flush()
+            .io_context(|| format!("Could not write to {:?}", self.path))?;
+        Ok(self.record_count)
+    }
+}
+
+/// A record of an output alignment, which is written once the alignments and the length of its contig are final.
+pub trait AlignmentRecord {
+    /// Write the record as a line of the given contig, without the trailing newline.
+    fn write<W: Write>(
+        &self,
+        writer: &mut W,
+        contig_name: &str,
+        contig_length: u64,
+    ) -> std::io::Result<()>;
+}
+
+/// Collects the records of the alignments of the current contig,
+/// and writes them once the contig is finished, leaving out the dropped alignments.
+pub struct AlignmentRecordWriter<Record> {
+    file: RecordFile,
+    records: Vec<Record>,
+}
+
+impl<Record: AlignmentRecord> AlignmentRecordWriter<Record> {
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        io_buffer_size: usize,
+        header: Option<&str>,
+    ) -> Result<Self> {
+        Ok(Self {
+            file: RecordFile::create(path, io_buffer_size, header)?,
+            records: Vec::new(),
+        })
+    }
+
+    /// Add the record of the next alignment of the current contig.
+    pub fn add_record(&mut self, record: Record) {
+        self.records.push(record);
+    }
+
+    /// Write the records of the current contig, leaving out the alignments with the given sorted indices.
+    pub fn finish_contig(
+        &mut self,
+        contig_name: &str,
+        contig_length: u64,
+        dropped_alignments: &[usize],
+    ) -> Result<()> {
+        let mut dropped_alignments = dropped_alignments.iter().peekable();
+        for (index, record) in self.records.drain(..).enumerate() {
+            if dropped_alignments.next_if_eq(&&index).is_some() {
+                continue;
+            }
+            self.file.write_record(|writer| {
+                record.write(writer, contig_name, contig_length)?;
+                writeln!(writer)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Flush the output and return the number of written records.
+    pub fn finish(self) -> Result<u64> {
+        self.file.finish()
+    }
+}
@@ -0,0 +1,87 @@
+use crate::error::{Error, Result};
+use std::collections::VecDeque;
+
+/// Restores the order of items that are numbered consecutively, but arrive shuffled.
+/// Items are stored in a ring buffer at the distance of their sequence number from the next expected one.
+pub struct ReorderBuffer<T> {
+    next_sequence_number: u64,
+    buffer: VecDeque<Option<T>>,
+    len: usize,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new() -> Self {
+        Self {
+            next_sequence_number: 0,
+            buffer: VecDeque::new(),
+            len: 0,
+        }
+    }
+
+    pub fn insert(&mut self, sequence_number: u64, item: T) -> Result<()> {
+        if sequence_number < self.next_sequence_number {
+            return Err(Error::Consistency(format!(
+                "Received sequence number {sequence_number} after it was already released"
+            )));
+        }
+
+        let index = (sequence_number - self.next_sequence_number) as usize;
+        if index >= self.buffer.len() {
+            self.buffer.resize_with(index + 1, || None);
+        }
+        if self.buffer[index].is_some() {
+            return Err(Error::Consistency(format!(
+                "Received sequence number {sequence_number} twice"
+            )));
+        }
+        self.buffer[index] = Some(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Remove the item with the next expected sequence number, if it has arrived.
+    pub fn pop(&mut self) -> Option<T> {
+        if let Some(Some(_)) = self.buffer.front() {
+            self.next_sequence_number += 1;
+            self.len -= 1;
+            self.buffer.pop_front().flatten()
+        } else {
+            None
+        }
+    }
+
+    /// The number of items waiting for their predecessors.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reorder_buffer::ReorderBuffer;
+
+    #[test]
+    fn test_reorder_buffer() {
+        let mut buffer = ReorderBuffer::new();
+        buffer.insert(2, 'c').unwrap();
+        buffer.insert(1, 'b').unwrap();
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.len(), 2);
+        assert!(buffer.insert(1, 'x').is_err());
+
+        buffer.insert(0, 'a').unwrap();
+        assert_eq!(buffer.pop(), Some('a'));
+        assert_eq!(buffer.pop(), Some('b'));
+        assert_eq!(buffer.pop(), Some('c'));
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+        assert!(buffer.insert(2, 'x').is_err());
+
+        buffer.insert(3, 'd').unwrap();
+        assert_eq!(buffer.pop(), Some('d'));
+    }
+}
@@ -0,0 +1,203 @@
+use crate::decompress::{complement, homopolymer_compress, MIN_SEGMENT_IDENTITY};
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The number of diagonals on each side of the anchor diagonal that are considered by the banded alignment.
+pub const BAND_RADIUS: usize = 32;
+
+/// The number of alignments that were rescued and dropped because they could not be rescued.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RescueCounts {
+    pub rescued: u64,
+    pub dropped: u64,
+}
+
+/// Searches a read for the compressed segment embedded in an alignment line,
+/// for alignments whose reported coordinates do not match their embedded segment.
+#[derive(Clone, Copy, Debug)]
+pub struct Rescuer {
+    /// The number of compressed characters searched before and after the reported interval.
+    pub window: usize,
+    /// The length of the exact matches used to anchor the segment.
+    pub k: usize,
+}
+
+impl Rescuer {
+    /// Find the compressed interval of `read` that the embedded `compressed_segment` stems from,
+    /// searching near the reported compressed interval `offset..limit`.
+    /// The compressed segment is reverse complemented if `direction` is false.
+    /// Returns `None` if the segment cannot be anchored or aligned well enough.
+    pub fn rescue(
+        &self,
+        read: &[u8],
+        offset: usize,
+        limit: usize,
+        direction: bool,
+        compressed_segment: &[u8],
+    ) -> Option<Range<usize>> {
+        let compressed_read = homopolymer_compress(read);
+        let pattern: Vec<_> = if direction {
+            compressed_segment.to_ascii_uppercase()
+        } else {
+            compressed_segment
+                .iter()
+                .rev()
+                .map(|&c| complement(c))
+                .collect()
+        };
+        let window_offset = offset
+            .saturating_sub(self.window)
+            .min(compressed_read.len());
+        let window_limit = limit
+            .saturating_add(self.window)
+            .min(compressed_read.len())
+            .max(window_offset);
+        let text = compressed_read[window_offset..window_limit].to_ascii_uppercase();
+
+        let diagonal = anchor_diagonal(&pattern, &text, self.k)?;
+        let (interval, distance) = banded_infix_alignment(&pattern, &text, diagonal)?;
+        let identity = 1.0 - distance as f64 / pattern.len().max(1) as f64;
+        if identity < MIN_SEGMENT_IDENTITY {
+            return None;
+        }
+        Some(window_offset + interval.start..window_offset + interval.end)
+    }
+}
+
+/// Find the diagonal `text_position - pattern_position` shared by the most exact k-mer matches.
+pub fn anchor_diagonal(pattern: &[u8], text: &[u8], k: usize) -> Option<isize> {
+    if k == 0 || pattern.len() < k || text.len() < k {
+        return None;
+    }
+
+    let mut text_kmers: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (position, kmer) in text.windows(k).enumerate() {
+        text_kmers.entry(kmer).or_default().push(position);
+    }
+
+    let mut votes: HashMap<isize, usize> = HashMap::new();
+    for (pattern_position, kmer) in pattern.windows(k).enumerate() {
+        if let Some(text_positions) = text_kmers.get(kmer) {
+            for &text_position in text_positions {
+                *votes
+                    .entry(text_position as isize - pattern_position as isize)
+                    .or_default() += 1;
+            }
+        }
+    }
+    // Prefer the smaller diagonal on ties, such that the result is deterministic.
+    votes
+        .into_iter()
+        .max_by_key(|&(diagonal, count)| (count, -diagonal))
+        .map(|(diagonal, _)| diagonal)
+}
+
+/// Align the whole pattern to a substring of the text with unit edit costs,
+/// considering only the diagonals within [BAND_RADIUS] of the given diagonal.
+/// Returns the aligned interval of the text and the edit distance.
+fn banded_infix_alignment(
+    pattern: &[u8],
+    text: &[u8],
+    diagonal: isize,
+) -> Option<(Range<usize>, usize)> {
+    let band_width = 2 * BAND_RADIUS + 1;
+    let first_diagonal = diagonal - BAND_RADIUS as isize;
+    // Each cell holds the edit distance and the text position where the alignment starts.
+    let mut previous_row = vec![None; band_width];
+    let mut current_row: Vec<Option<(usize, usize)>> = vec![None; band_width];
+    let text_position = |row: usize, band_index: usize| {
+        let position = row as isize + first_diagonal + band_index as isize;
+        if position >= 0 && position as usize <= text.len() {
+            Some(position as usize)
+        } else {
+            None
+        }
+    };
+
+    // The alignment may start anywhere in the text for free.
+    for (band_index, cell) in previous_row.iter_mut().enumerate() {
+        *cell = text_position(0, band_index).map(|position| (0, position));
+    }
+
+    for row in 1..=pattern.len() {
+        for band_index in 0..band_width {
+            current_row[band_index] = text_position(row, band_index).and_then(|position| {
+                let mut best: Option<(usize, usize)> = None;
+                let mut consider = |candidate: Option<(usize, usize)>, cost: usize| {
+                    if let Some((distance, start)) = candidate {
+                        if best.map_or(true, |(best_distance, _)| distance + cost < best_distance) {
+                            best = Some((distance + cost, start));
+                        }
+                    }
+                };
+                if position > 0 {
+                    // Match or substitution along the same diagonal.
+                    let cost = usize::from(pattern[row - 1] != text[position - 1]);
+                    consider(previous_row[band_index], cost);
+                    // Skip a text character.
+                    if band_index > 0 {
+                        consider(current_row[band_index - 1], 1);
+                    }
+                }
+                // Skip a pattern character.
+                if band_index + 1 < band_width {
+                    consider(previous_row[band_index + 1], 1);
+                }
+                best
+            });
+        }
+        std::mem::swap(&mut previous_row, &mut current_row);
+    }
+
+    previous_row
+        .iter()
+        .enumerate()
+        .filter_map(|(band_index, cell)| {
+            cell.map(|(distance, start)| {
+                let end = text_position(pattern.len(), band_index)
+                    .expect("valid cells have a text position");
+                (start..end, distance)
+            })
+        })
+        .min_by_key(|(interval, distance)| (*distance, interval.start))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rescue::{banded_infix_alignment, Rescuer};
+
+    #[test]
+    fn test_banded_infix_alignment() {
+        let text = b"GATTACACATGCATGCAGTC";
+        assert_eq!(
+            banded_infix_alignment(b"CATGCATG", text, 7),
+            Some((7..15, 0))
+        );
+        // One character of the text is missing from the pattern.
+        assert_eq!(
+            banded_infix_alignment(b"ATGCTGCAGT", text, 8),
+            Some((8..19, 1))
+        );
+    }
+
+    #[test]
+    fn test_rescue() {
+        let read = b"AACGTTGCAAATGCCTAGGCATTCGATCGGATCCAGTGTACA";
+        let rescuer = Rescuer { window: 20, k: 4 };
+        // The compressed interval 10..22 of the read, reported at offset 5.
+        let compressed_read = crate::decompress::homopolymer_compress(read);
+        let segment = &compressed_read[10..22];
+        assert_eq!(rescuer.rescue(read, 5, 17, true, segment), Some(10..22));
+
+        let reverse_segment: Vec<_> = segment
+            .iter()
+            .rev()
+            .map(|&c| crate::decompress::complement(c))
+            .collect();
+        assert_eq!(
+            rescuer.rescue(read, 5, 17, false, &reverse_segment),
+            Some(10..22)
+        );
+        assert_eq!(rescuer.rescue(read, 5, 17, true, b"CCCCCCCCCCCC"), None);
+    }
+}
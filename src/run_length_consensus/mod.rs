@@ -0,0 +1,349 @@
+use crate::decompress::{
+    alignment_limit, oriented_segment, run_length_encode, MIN_SEGMENT_IDENTITY,
+};
+use crate::error::Result;
+use crate::fasta_sequence_index::FastaSequenceIndex;
+use crate::layout::ContigLayout;
+use crate::read_cache::ReadCache;
+use crate::rescue::{anchor_diagonal, BAND_RADIUS};
+use std::sync::Arc;
+
+/// A read compressed into its runs, together with the length of each run.
+pub type EncodedRead = (Vec<u8>, Vec<usize>);
+
+/// Decompress a homopolymer compressed contig by voting over the run lengths of the reads in its layout.
+/// The reads are fetched from the read index unless they are in the read cache.
+/// Returns the decompressed contig and the numbers of read segments that could and could not be aligned.
+pub fn decompress_contig(
+    contig: &[u8],
+    layout: &ContigLayout,
+    read_index: &FastaSequenceIndex,
+    read_cache: &mut ReadCache<EncodedRead>,
+    window: usize,
+    k: usize,
+) -> Result<(Vec<u8>, u64, u64)> {
+    let mut consensus = RunLengthConsensus::new(contig, window, k);
+    let mut placed_count = 0;
+    let mut unplaced_count = 0;
+    for edge in &layout.edges {
+        for alignment in &edge.alignments {
+            let encoded_read = if let Some(encoded_read) = read_cache.get(&alignment.read_id) {
+                encoded_read
+            } else {
+                let mut read = Vec::new();
+                read_index.get_sequence(&alignment.read_id, &mut read)?;
+                let encoded_read = Arc::new(run_length_encode(&read));
+                read_cache.insert(&alignment.read_id, encoded_read.clone());
+                encoded_read
+            };
+            let (segment, run_lengths) = encoded_segment(
+                &encoded_read,
+                alignment.offset,
+                alignment.length,
+                alignment.direction,
+            )
+            .map_err(|error| {
+                error.at(format_args!(
+                    "Read {}",
+                    String::from_utf8_lossy(&alignment.read_id)
+                ))
+            })?;
+
+            if consensus.add_segment(edge.offset as usize, &segment, &run_lengths) {
+                placed_count += 1;
+            } else {
+                unplaced_count += 1;
+            }
+        }
+    }
+    Ok((consensus.finish(), placed_count, unplaced_count))
+}
+
+/// The compressed segment `offset..offset + length` of a run-length encoded read together with its run lengths,
+/// in the direction of the contig.
+fn encoded_segment(
+    (compressed_read, run_lengths): &EncodedRead,
+    offset: usize,
+    length: usize,
+    direction: bool,
+) -> Result<EncodedRead> {
+    let limit = alignment_limit(offset, length)?;
+    let segment = oriented_segment(compressed_read, offset, limit, direction)?;
+    // The run lengths are within bounds, since there is one per compressed base.
+    let mut run_lengths = run_lengths[offset..limit].to_vec();
+    if !direction {
+        run_lengths.reverse();
+    }
+    Ok((segment, run_lengths))
+}
+
+/// Decompresses a homopolymer compressed contig by voting over the run lengths of the read segments aligned to it.
+/// Each base of the contig gets the most frequent run length of the read bases aligned to it with the same base,
+/// preferring the shorter run length on ties. Bases without votes keep a run length of one.
+pub struct RunLengthConsensus {
+    contig: Vec<u8>,
+    /// The run lengths voted for each base of the contig and their number of votes.
+    votes: Vec<Vec<(usize, u32)>>,
+    /// The number of compressed bases searched before and after the expected position of a segment.
+    window: usize,
+    /// The length of the exact matches used to anchor a segment.
+    k: usize,
+}
+
+impl RunLengthConsensus {
+    pub fn new(contig: &[u8], window: usize, k: usize) -> Self {
+        Self {
+            contig: contig.to_ascii_uppercase(),
+            votes: vec![Vec::new(); contig.len()],
+            window,
+            k,
+        }
+    }
+
+    /// Align a compressed read segment near its expected position on the contig and add the votes of its run lengths.
+    /// The segment must be in the direction of the contig.
+    /// Returns false if the segment could not be aligned well enough, in which case it does not vote.
+    /// Panics if the segment and its run lengths differ in length.
+    pub fn add_segment(&mut self, position: usize, segment: &[u8], run_lengths: &[usize]) -> bool {
+        assert_eq!(
+            segment.len(),
+            run_lengths.len(),
+            "Each base of a segment needs a run length"
+        );
+        let segment = segment.to_ascii_uppercase();
+        let window_offset = position.saturating_sub(self.window).min(self.contig.len());
+        let window_limit = position
+            .saturating_add(segment.len())
+            .saturating_add(self.window)
+            .min(self.contig.len());
+        let text = &self.contig[window_offset..window_limit];
+
+        let matches = anchor_diagonal(&segment, text, self.k)
+            .and_then(|diagonal| banded_infix_matches(&segment, text, diagonal));
+        let matches = if let Some(matches) = matches {
+            matches
+        } else {
+            return false;
+        };
+        let identity = matches.len() as f64 / segment.len().max(1) as f64;
+        if identity < MIN_SEGMENT_IDENTITY {
+            return false;
+        }
+
+        for (segment_position, text_position) in matches {
+            let run_length = run_lengths[segment_position];
+            let votes = &mut self.votes[window_offset + text_position];
+            if let Some((_, count)) = votes
+                .iter_mut()
+                .find(|(voted_run_length, _)| *voted_run_length == run_length)
+            {
+                *count += 1;
+            } else {
+                votes.push((run_length, 1));
+            }
+        }
+        true
+    }
+
+    /// The decompressed contig.
+    pub fn finish(self) -> Vec<u8> {
+        let mut decompressed = Vec::with_capacity(self.contig.len());
+        for (&base, votes) in self.contig.iter().zip(&self.votes) {
+            let run_length = votes
+                .iter()
+                .max_by_key(|&&(run_length, count)| (count, std::cmp::Reverse(run_length)))
+                .map(|&(run_length, _)| run_length)
+                .unwrap_or(1);
+            decompressed.extend(std::iter::repeat(base).take(run_length));
+        }
+        decompressed
+    }
+}
+
+/// Align the whole pattern to a substring of the text with unit edit costs,
+/// considering only the diagonals within [BAND_RADIUS] of the given diagonal.
+/// Returns the pairs of pattern and text positions with equal characters on the alignment path.
+fn banded_infix_matches(
+    pattern: &[u8],
+    text: &[u8],
+    diagonal: isize,
+) -> Option<Vec<(usize, usize)>> {
+    let band_width = 2 * BAND_RADIUS + 1;
+    let first_diagonal = diagonal - BAND_RADIUS as isize;
+    let text_position = |row: usize, band_index: usize| {
+        let position = row as isize + first_diagonal + band_index as isize;
+        if position >= 0 && position as usize <= text.len() {
+            Some(position as usize)
+        } else {
+            None
+        }
+    };
+
+    // Each cell holds the edit distance of the pattern prefix of its row aligned to a text suffix ending at its position.
+    let mut distances: Vec<Option<usize>> = vec![None; (pattern.len() + 1) * band_width];
+    // The alignment may start anywhere in the text for free.
+    for (band_index, cell) in distances[..band_width].iter_mut().enumerate() {
+        *cell = text_position(0, band_index).map(|_| 0);
+    }
+    let substitution_cost =
+        |row: usize, position: usize| usize::from(pattern[row - 1] != text[position - 1]);
+    for row in 1..=pattern.len() {
+        for band_index in 0..band_width {
+            let cell = row * band_width + band_index;
+            distances[cell] = text_position(row, band_index).and_then(|position| {
+                let mut candidates = [None; 3];
+                if position > 0 {
+                    // Match or substitution along the same diagonal.
+                    candidates[0] = distances[cell - band_width]
+                        .map(|distance| distance + substitution_cost(row, position));
+                    // Skip a text character.
+                    if band_index > 0 {
+                        candidates[1] = distances[cell - 1].map(|distance| distance + 1);
+                    }
+                }
+                // Skip a pattern character.
+                if band_index + 1 < band_width {
+                    candidates[2] = distances[cell - band_width + 1].map(|distance| distance + 1);
+                }
+                candidates.iter().flatten().min().copied()
+            });
+        }
+    }
+
+    let (mut band_index, _) = (0..band_width)
+        .filter_map(|band_index| {
+            distances[pattern.len() * band_width + band_index]
+                .map(|distance| (band_index, distance))
+        })
+        .min_by_key(|&(band_index, distance)| (distance, band_index))?;
+    let mut row = pattern.len();
+    let mut matches = Vec::new();
+    while row > 0 {
+        let cell = row * band_width + band_index;
+        let distance = distances[cell].expect("the traceback only visits valid cells");
+        let position = text_position(row, band_index).expect("valid cells have a text position");
+        if position > 0
+            && distances[cell - band_width]
+                .map(|previous| previous + substitution_cost(row, position))
+                == Some(distance)
+        {
+            if pattern[row - 1] == text[position - 1] {
+                matches.push((row - 1, position - 1));
+            }
+            row -= 1;
+        } else if position > 0
+            && band_index > 0
+            && distances[cell - 1].map(|previous| previous + 1) == Some(distance)
+        {
+            band_index -= 1;
+        } else {
+            row -= 1;
+            band_index += 1;
+        }
+    }
+    matches.reverse();
+    Some(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decompress::run_length_encode;
+    use crate::fasta_sequence_index::FastaSequenceIndex;
+    use crate::layout::{AlignmentLayout, ContigLayout, EdgeLayout};
+    use crate::read_cache::ReadCache;
+    use crate::reverse_complement;
+    use crate::run_length_consensus::{decompress_contig, RunLengthConsensus};
+    use std::fs;
+
+    #[test]
+    fn test_run_length_consensus() {
+        let contig = b"ACGTACGATCGTAGCTAGCATCGA";
+        let truth = b"AACGGGTACCGATTCGTAGCTTAGCAATCGA";
+        let mut consensus = RunLengthConsensus::new(contig, 10, 4);
+
+        // Two reads agree with the truth, one has a different run length at the third base.
+        let (segment, run_lengths) = run_length_encode(&truth[..]);
+        assert!(consensus.add_segment(0, &segment, &run_lengths));
+        assert!(consensus.add_segment(3, &segment[2..], &run_lengths[2..]));
+        let (segment, run_lengths) = run_length_encode(b"AACGGTACCGATTCG");
+        assert!(consensus.add_segment(0, &segment, &run_lengths));
+        // A segment that does not occur in the contig does not vote.
+        let (segment, run_lengths) = run_length_encode(b"TTTGGGAAACCCTTTGGGAAACCC");
+        assert!(!consensus.add_segment(0, &segment, &run_lengths));
+
+        assert_eq!(consensus.finish(), truth);
+    }
+
+    #[test]
+    fn test_decompress_contig() {
+        let contig = b"ACGTACGATCGTAGCTAGCATCGA";
+        let truth = b"AACGGGTACCGATTCGTAGCTTAGCAATCGA";
+        let reverse_read = reverse_complement(truth.iter().copied()).unwrap();
+        let path = std::env::temp_dir().join(format!("rlc_test_{}_reads.fa", std::process::id()));
+        let index_path = path.with_extension("fa.normal_index");
+        fs::write(
+            &path,
+            format!(
+                ">forward\nGG{}\n>reverse\n{}\n>unrelated\nTTTGGGAAACCCTTTGGGAAACCC\n",
+                String::from_utf8_lossy(&truth[..]),
+                String::from_utf8_lossy(&reverse_read),
+            ),
+        )
+        .unwrap();
+        let read_index = FastaSequenceIndex::build(&path, &index_path, 16).unwrap();
+
+        let alignment = |read_id: &[u8], direction, offset, length| AlignmentLayout {
+            read_id: read_id.to_vec(),
+            direction,
+            offset,
+            length,
+        };
+        // The reverse read covers the whole contig, the forward read only its end after its first compressed base.
+        let layout = ContigLayout {
+            name: "ctg1".to_string(),
+            edges: vec![
+                EdgeLayout {
+                    offset: 0,
+                    alignments: vec![
+                        alignment(b"reverse", false, 0, contig.len()),
+                        alignment(b"unrelated", true, 0, 8),
+                    ],
+                    ..Default::default()
+                },
+                EdgeLayout {
+                    offset: 10,
+                    alignments: vec![
+                        alignment(b"forward", true, 11, contig.len() - 10),
+                        alignment(b"reverse", false, 0, contig.len() - 10),
+                    ],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let mut read_cache = ReadCache::new(16);
+        assert_eq!(
+            decompress_contig(contig, &layout, &read_index, &mut read_cache, 10, 4).unwrap(),
+            (truth.to_vec(), 3, 1)
+        );
+
+        // Alignments out of the bounds of their read are malformed.
+        let layout = ContigLayout {
+            edges: vec![EdgeLayout {
+                alignments: vec![alignment(b"reverse", false, 1, contig.len())],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(decompress_contig(contig, &layout, &read_index, &mut read_cache, 10, 4).is_err());
+        fs::remove_file(path).unwrap();
+        fs::remove_file(index_path).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_run_length_mismatch() {
+        let mut consensus = RunLengthConsensus::new(b"ACGTACGATCGTAGCT", 10, 4);
+        consensus.add_segment(0, b"ACGTACGA", &[1, 2, 1]);
+    }
+}
@@ -0,0 +1,271 @@
+use crate::error::{Error, Result};
+use crate::rescue::RescueCounts;
+use crate::summary::{format_ratio, Summary, Table};
+use crossbeam::channel::{Receiver, RecvError};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// The lengths of a contig before and after decompression.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ContigStatistics {
+    pub name: String,
+    /// The length given in the input contig line.
+    pub compressed_length: u64,
+    /// The length written to the output contig line.
+    pub decompressed_length: u64,
+    pub edge_count: u64,
+    pub alignment_count: u64,
+    /// The sum of the lengths of the alignments in the input.
+    pub compressed_bases: u64,
+    /// The sum of the lengths of the decompressed alignments.
+    pub decompressed_bases: u64,
+    /// The number of alignments with an anomalous expansion ratio.
+    pub anomalies: u64,
+    /// The number of anomalous alignments that were left out of the output.
+    pub dropped_alignments: u64,
+}
+
+/// The time the threads of a pipeline stage spent working.
+#[derive(Clone, Debug)]
+struct StageStatistics {
+    name: String,
+    thread_count: usize,
+    busy: Duration,
+}
+
+#[derive(Default)]
+struct RunStatisticsData {
+    contigs: Vec<ContigStatistics>,
+    stages: Vec<StageStatistics>,
+    reads_indexed: u64,
+    reads_referenced: u64,
+    rescue_counts: RescueCounts,
+}
+
+/// A summary of a run, collected from the pipeline stages.
+pub struct RunStatistics {
+    start: Instant,
+    data: Mutex<RunStatisticsData>,
+}
+
+impl RunStatistics {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            data: Default::default(),
+        }
+    }
+
+    pub fn add_contig(&self, contig: ContigStatistics) -> Result<()> {
+        self.lock()?.contigs.push(contig);
+        Ok(())
+    }
+
+    /// Add the busy time of a thread to its stage. Threads of the same stage are summed up.
+    pub fn add_stage_time(&self, name: &str, busy: Duration) -> Result<()> {
+        let mut data = self.lock()?;
+        if let Some(stage) = data.stages.iter_mut().find(|stage| stage.name == name) {
+            stage.thread_count += 1;
+            stage.busy += busy;
+        } else {
+            data.stages.push(StageStatistics {
+                name: name.to_owned(),
+                thread_count: 1,
+                busy,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn add_rescue_counts(&self, rescue_counts: RescueCounts) -> Result<()> {
+        let mut data = self.lock()?;
+        data.rescue_counts.rescued += rescue_counts.rescued;
+        data.rescue_counts.dropped += rescue_counts.dropped;
+        Ok(())
+    }
+
+    pub fn rescue_counts(&self) -> Result<RescueCounts> {
+        Ok(self.lock()?.rescue_counts)
+    }
+
+    pub fn set_reads_indexed(&self, reads_indexed: u64) -> Result<()> {
+        self.lock()?.reads_indexed = reads_indexed;
+        Ok(())
+    }
+
+    pub fn set_reads_referenced(&self, reads_referenced: u64) -> Result<()> {
+        self.lock()?.reads_referenced = reads_referenced;
+        Ok(())
+    }
+
+    /// Write the statistics as tab-separated values if the path ends in `.tsv`, and as JSON otherwise.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let data = self.lock()?;
+        self.to_summary(&data).write(Some(path))
+    }
+
+    fn summary(&self, data: &RunStatisticsData) -> Vec<(&'static str, String)> {
+        let sum = |value: fn(&ContigStatistics) -> u64| data.contigs.iter().map(value).sum::<u64>();
+        let compressed_bases = sum(|contig| contig.compressed_bases);
+        let decompressed_bases = sum(|contig| contig.decompressed_bases);
+        vec![
+            ("contigs", data.contigs.len().to_string()),
+            ("edges", sum(|contig| contig.edge_count).to_string()),
+            (
+                "alignments",
+                sum(|contig| contig.alignment_count).to_string(),
+            ),
+            ("compressed_bases", compressed_bases.to_string()),
+            ("decompressed_bases", decompressed_bases.to_string()),
+            (
+                "expansion_ratio",
+                format_ratio(decompressed_bases, compressed_bases),
+            ),
+            (
+                "compressed_contig_length",
+                sum(|contig| contig.compressed_length).to_string(),
+            ),
+            (
+                "decompressed_contig_length",
+                sum(|contig| contig.decompressed_length).to_string(),
+            ),
+            ("anomalies", sum(|contig| contig.anomalies).to_string()),
+            (
+                "dropped_alignments",
+                sum(|contig| contig.dropped_alignments).to_string(),
+            ),
+            ("rescued_alignments", data.rescue_counts.rescued.to_string()),
+            (
+                "unrescued_alignments",
+                data.rescue_counts.dropped.to_string(),
+            ),
+            ("reads_indexed", data.reads_indexed.to_string()),
+            ("reads_referenced", data.reads_referenced.to_string()),
+            (
+                "wall_seconds",
+                format!("{:.3}", self.start.elapsed().as_secs_f64()),
+            ),
+        ]
+    }
+
+    /// In TSV, the stages are written as comment lines before the table of contigs.
+    fn to_summary(&self, data: &RunStatisticsData) -> Summary {
+        let stages = Table {
+            key: "stages",
+            row_kind: "stage",
+            columns: vec!["threads", "busy_seconds"],
+            rows: data
+                .stages
+                .iter()
+                .map(|stage| {
+                    (
+                        stage.name.clone(),
+                        vec![
+                            stage.thread_count.to_string(),
+                            format!("{:.3}", stage.busy.as_secs_f64()),
+                        ],
+                    )
+                })
+                .collect(),
+        };
+        let contigs = Table {
+            key: "contig_statistics",
+            row_kind: "contig",
+            columns: vec![
+                "compressed_length",
+                "decompressed_length",
+                "expansion_ratio",
+                "edges",
+                "alignments",
+                "compressed_bases",
+                "decompressed_bases",
+                "anomalies",
+                "dropped_alignments",
+            ],
+            rows: data
+                .contigs
+                .iter()
+                .map(|contig| {
+                    (
+                        contig.name.clone(),
+                        vec![
+                            contig.compressed_length.to_string(),
+                            contig.decompressed_length.to_string(),
+                            format_ratio(contig.decompressed_length, contig.compressed_length),
+                            contig.edge_count.to_string(),
+                            contig.alignment_count.to_string(),
+                            contig.compressed_bases.to_string(),
+                            contig.decompressed_bases.to_string(),
+                            contig.anomalies.to_string(),
+                            contig.dropped_alignments.to_string(),
+                        ],
+                    )
+                })
+                .collect(),
+        };
+        Summary {
+            values: self.summary(data),
+            tables: vec![stages, contigs],
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, RunStatisticsData>> {
+        self.data
+            .lock()
+            .map_err(|_| Error::Consistency("The statistics lock was poisoned".to_string()))
+    }
+}
+
+/// Measures the time a pipeline stage spends working, i.e. not waiting for input from the previous stage.
+pub struct StageTimer {
+    start: Instant,
+    waiting: Duration,
+}
+
+impl StageTimer {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            waiting: Duration::from_secs(0),
+        }
+    }
+
+    /// Receive from the given channel, counting the time until something arrives as waiting time.
+    pub fn recv<T>(&mut self, receiver: &Receiver<T>) -> std::result::Result<T, RecvError> {
+        let start = Instant::now();
+        let result = receiver.recv();
+        self.waiting += start.elapsed();
+        result
+    }
+
+    pub fn busy(&self) -> Duration {
+        self.start.elapsed().saturating_sub(self.waiting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::statistics::{ContigStatistics, RunStatistics};
+
+    #[test]
+    fn test_summary() {
+        let statistics = RunStatistics::new();
+        statistics
+            .add_contig(ContigStatistics {
+                name: "ctg1".to_string(),
+                compressed_length: 100,
+                decompressed_length: 150,
+                edge_count: 2,
+                alignment_count: 3,
+                compressed_bases: 200,
+                decompressed_bases: 300,
+                ..Default::default()
+            })
+            .unwrap();
+        let data = statistics.lock().unwrap();
+        let summary = statistics.summary(&data);
+        assert_eq!(summary[0], ("contigs", "1".to_string()));
+        assert_eq!(summary[5], ("expansion_ratio", "1.5000".to_string()));
+    }
+}
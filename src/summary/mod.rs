@@ -0,0 +1,197 @@
+use crate::error::{IoContext, Result};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// A table of named rows, e.g. one row per contig.
+pub struct Table {
+    /// The key of the table in JSON.
+    pub key: &'static str,
+    /// What a row describes, e.g. `contig`. Titles the column of the row names in TSV.
+    pub row_kind: &'static str,
+    /// The columns after the row name.
+    pub columns: Vec<&'static str>,
+    /// The name of each row and its values, formatted as JSON numbers or `null`.
+    pub rows: Vec<(String, Vec<String>)>,
+}
+
+/// Key/value pairs followed by tables, written as JSON or as tab-separated values.
+/// In JSON, each table is an array of objects, with the row name under the key `name`.
+/// In TSV, the key/value pairs and the rows of all but the last table are written as comment lines,
+/// and the last table is written with a header.
+pub struct Summary {
+    /// The values are formatted as JSON numbers or `null`.
+    pub values: Vec<(&'static str, String)>,
+    pub tables: Vec<Table>,
+}
+
+impl Summary {
+    /// Write the summary as tab-separated values if the path ends in `.tsv`, and as JSON otherwise.
+    /// Without a path, tab-separated values are written to standard output.
+    pub fn write<P: AsRef<Path>>(&self, path: Option<P>) -> Result<()> {
+        if let Some(path) = path {
+            let path = path.as_ref();
+            let output = if path
+                .extension()
+                .map_or(false, |extension| extension == "tsv")
+            {
+                self.to_tsv()
+            } else {
+                self.to_json()
+            };
+            let mut writer = BufWriter::new(
+                File::create(path).io_context(|| format!("Could not create {path:?}"))?,
+            );
+            writer
+                .write_all(output.as_bytes())
+                .and_then(|()| writer.flush())
+                .io_context(|| format!("Could not write to {path:?}"))
+        } else {
+            std::io::stdout()
+                .lock()
+                .write_all(self.to_tsv().as_bytes())
+                .io_context(|| "Could not write to standard output".to_string())
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut output = "{\n".to_string();
+        for (index, (key, value)) in self.values.iter().enumerate() {
+            let separator = if index + 1 == self.values.len() && self.tables.is_empty() {
+                ""
+            } else {
+                ","
+            };
+            writeln!(output, "  \"{key}\": {value}{separator}").unwrap();
+        }
+
+        for (index, table) in self.tables.iter().enumerate() {
+            write!(output, "  \"{}\": [", table.key).unwrap();
+            for (row_index, (name, values)) in table.rows.iter().enumerate() {
+                let separator = if row_index == 0 { "" } else { "," };
+                write!(output, "{separator}\n    {{\"name\": {}", json_string(name)).unwrap();
+                for (column, value) in table.columns.iter().zip(values) {
+                    write!(output, ", \"{column}\": {value}").unwrap();
+                }
+                output += "}";
+            }
+            let separator = if index + 1 == self.tables.len() {
+                ""
+            } else {
+                ","
+            };
+            writeln!(output, "\n  ]{separator}").unwrap();
+        }
+        output += "}\n";
+        output
+    }
+
+    pub fn to_tsv(&self) -> String {
+        let mut output = String::new();
+        for (key, value) in &self.values {
+            writeln!(output, "#{key}\t{value}").unwrap();
+        }
+
+        if let Some((last_table, tables)) = self.tables.split_last() {
+            for table in tables {
+                for (name, values) in &table.rows {
+                    writeln!(output, "#{}\t{name}\t{}", table.row_kind, values.join("\t")).unwrap();
+                }
+            }
+
+            output += last_table.row_kind;
+            for column in &last_table.columns {
+                write!(output, "\t{column}").unwrap();
+            }
+            output += "\n";
+            for (name, values) in &last_table.rows {
+                writeln!(output, "{name}\t{}", values.join("\t")).unwrap();
+            }
+        }
+        output
+    }
+}
+
+pub fn format_ratio(numerator: u64, denominator: u64) -> String {
+    if denominator == 0 {
+        "null".to_string()
+    } else {
+        format!("{:.4}", numerator as f64 / denominator as f64)
+    }
+}
+
+/// Quote and escape a string for JSON.
+pub fn json_string(string: &str) -> String {
+    let mut output = String::with_capacity(string.len() + 2);
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output += "\\\"",
+            '\\' => output += "\\\\",
+            '\n' => output += "\\n",
+            '\t' => output += "\\t",
+            c if (c as u32) < 0x20 => write!(output, "\\u{:04x}", c as u32).unwrap(),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::summary::{json_string, Summary, Table};
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("ctg1"), "\"ctg1\"");
+        assert_eq!(json_string("a\"b\\c\u{1}"), "\"a\\\"b\\\\c\\u0001\"");
+    }
+
+    fn summary() -> Summary {
+        Summary {
+            values: vec![("contigs", "2".to_string()), ("ratio", "null".to_string())],
+            tables: vec![
+                Table {
+                    key: "stages",
+                    row_kind: "stage",
+                    columns: vec!["threads"],
+                    rows: vec![("parser".to_string(), vec!["1".to_string()])],
+                },
+                Table {
+                    key: "contig_statistics",
+                    row_kind: "contig",
+                    columns: vec!["length", "edges"],
+                    rows: vec![
+                        ("ctg1".to_string(), vec!["10".to_string(), "1".to_string()]),
+                        ("ctg2".to_string(), vec!["20".to_string(), "2".to_string()]),
+                    ],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!(
+            summary().to_json(),
+            "{\n  \"contigs\": 2,\n  \"ratio\": null,\n  \"stages\": [\n    {\"name\": \"parser\", \"threads\": 1}\n  ],\n  \
+             \"contig_statistics\": [\n    {\"name\": \"ctg1\", \"length\": 10, \"edges\": 1},\n    \
+             {\"name\": \"ctg2\", \"length\": 20, \"edges\": 2}\n  ]\n}\n"
+        );
+        let empty = Summary {
+            values: vec![("contigs", "0".to_string())],
+            tables: Vec::new(),
+        };
+        assert_eq!(empty.to_json(), "{\n  \"contigs\": 0\n}\n");
+    }
+
+    #[test]
+    fn test_tsv() {
+        assert_eq!(
+            summary().to_tsv(),
+            "#contigs\t2\n#ratio\tnull\n#stage\tparser\t1\ncontig\tlength\tedges\nctg1\t10\t1\nctg2\t20\t2\n"
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use crate::error::{IoContext, Result};
+use crate::wtdbg2_ctg_lay::Wtdbg2CtgLayLine;
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::str::FromStr;
+
+/// A problem found in a line of a .ctg.lay file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Violation {
+    pub line_number: u64,
+    pub message: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Line {}: {}", self.line_number, self.message)
+    }
+}
+
+/// The contig whose edges are currently checked.
+struct ContigState {
+    line_number: u64,
+    node_count: u64,
+    edge_count: u64,
+}
+
+/// The edge whose alignments are currently checked.
+struct EdgeState {
+    line_number: u64,
+    offset: u64,
+    to_node: String,
+    alignment_count: u64,
+}
+
+/// Checks the lines of a .ctg.lay file one after the other.
+/// Checks that span multiple lines are reported at the first line they concern.
+#[derive(Default)]
+pub struct Validator {
+    violations: Vec<Violation>,
+    contig: Option<ContigState>,
+    edge: Option<EdgeState>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn check_line(&mut self, line_number: u64, line: &str) {
+        let line = match Wtdbg2CtgLayLine::from_str(line) {
+            Ok(line) => line,
+            Err(_) => {
+                self.report(line_number, "could not parse line".to_string());
+                return;
+            }
+        };
+
+        match line {
+            Wtdbg2CtgLayLine::Contig { node_count, .. } => {
+                self.finish_contig();
+                self.contig = Some(ContigState {
+                    line_number,
+                    node_count,
+                    edge_count: 0,
+                });
+            }
+            Wtdbg2CtgLayLine::Edge {
+                offset,
+                from_node,
+                to_node,
+                ..
+            } => {
+                self.finish_edge();
+                if let Some(contig) = &mut self.contig {
+                    contig.edge_count += 1;
+                } else {
+                    self.report(line_number, "edge before the first contig".to_string());
+                }
+
+                if let Some(previous_edge) = self.edge.take() {
+                    if offset < previous_edge.offset {
+                        let message = format!(
+                            "edge offset {offset} is smaller than the offset {} of the previous edge",
+                            previous_edge.offset
+                        );
+                        self.report(line_number, message);
+                    }
+                    if from_node != previous_edge.to_node {
+                        let message = format!(
+                            "edge starts at node {from_node}, but the previous edge ends at node {}",
+                            previous_edge.to_node
+                        );
+                        self.report(line_number, message);
+                    }
+                }
+                self.edge = Some(EdgeState {
+                    line_number,
+                    offset,
+                    to_node,
+                    alignment_count: 0,
+                });
+            }
+            Wtdbg2CtgLayLine::Alignment {
+                length,
+                compressed_sequence,
+                ..
+            } => {
+                if let Some(edge) = &mut self.edge {
+                    edge.alignment_count += 1;
+                } else {
+                    self.report(line_number, "alignment before the first edge".to_string());
+                }
+
+                // Lines without an embedded sequence are allowed.
+                if let Some(compressed_sequence) = compressed_sequence {
+                    if compressed_sequence.len() != length {
+                        let message = format!(
+                            "alignment length {length} does not match the length {} of its sequence",
+                            compressed_sequence.len()
+                        );
+                        self.report(line_number, message);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finish the checks of the last contig and return the violations found in all lines checked so far.
+    pub fn finish(mut self) -> Vec<Violation> {
+        self.finish_contig();
+        self.violations
+    }
+
+    fn finish_contig(&mut self) {
+        self.finish_edge();
+        self.edge = None;
+        if let Some(contig) = self.contig.take() {
+            // The edges connect consecutive nodes of the contig.
+            let expected_edge_count = contig.node_count.saturating_sub(1);
+            if contig.edge_count != expected_edge_count {
+                let message = format!(
+                    "contig has {} nodes, so it should have {expected_edge_count} edges, but it has {}",
+                    contig.node_count, contig.edge_count
+                );
+                self.report(contig.line_number, message);
+            }
+        }
+    }
+
+    fn finish_edge(&mut self) {
+        if let Some(edge) = &self.edge {
+            if edge.alignment_count == 0 {
+                let line_number = edge.line_number;
+                self.report(line_number, "edge has no alignments".to_string());
+            }
+        }
+    }
+
+    fn report(&mut self, line_number: u64, message: String) {
+        self.violations.push(Violation {
+            line_number,
+            message,
+        });
+    }
+}
+
+/// Check all lines of the given .ctg.lay file.
+pub fn validate_file<P: AsRef<Path>>(
+    input_file: P,
+    io_buffer_size: usize,
+) -> Result<Vec<Violation>> {
+    let input_file = input_file.as_ref();
+    let reader = BufReader::with_capacity(
+        io_buffer_size,
+        File::open(input_file).io_context(|| format!("Could not open {input_file:?}"))?,
+    );
+    let mut validator = Validator::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.io_context(|| format!("Could not read {input_file:?}"))?;
+        validator.check_line(index as u64 + 1, line.trim_end_matches('\r'));
+    }
+    Ok(validator.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::validate::Validator;
+
+    #[test]
+    fn test_line_order() {
+        let mut validator = Validator::new();
+        let lines = [
+            "E\t0\tN1\t+\tN2\t-",
+            ">ctg1 nodes=2 len=100",
+            "S\tread1\t+\t0\t4\tACGT",
+            "E\t0\tN1\t+\tN2\t-",
+            "S\tread1\t+\t0\t4\tACGT",
+            "X",
+        ];
+        for (index, line) in lines.iter().enumerate() {
+            validator.check_line(index as u64 + 1, line);
+        }
+        let line_numbers: Vec<_> = validator
+            .finish()
+            .iter()
+            .map(|violation| violation.line_number)
+            .collect();
+        // The edge in line 1 has no contig and no alignments, and the alignment in line 3 has no edge.
+        assert_eq!(line_numbers, [1, 1, 3, 6]);
+    }
+
+    #[test]
+    fn test_structure() {
+        let mut validator = Validator::new();
+        let lines = [
+            ">ctg1 nodes=5 len=100",
+            "E\t0\tN1\t+\tN2\t-",
+            "S\tread1\t+\t0\t4\tACGT",
+            "E\t50\tN2\t-\tN3\t+",
+            "E\t40\tN4\t+\tN5\t+",
+            "S\tread2\t-\t10\t5\tACGT",
+            ">ctg2 nodes=2 len=50",
+            "E\t0\tN6\t+\tN7\t+",
+            "S\tread3\t+\t0\t3\tACG",
+        ];
+        for (index, line) in lines.iter().enumerate() {
+            validator.check_line(index as u64 + 1, line);
+        }
+        let line_numbers: Vec<_> = validator
+            .finish()
+            .iter()
+            .map(|violation| violation.line_number)
+            .collect();
+        // Edge 4 has no alignments, edge 5 has a smaller offset and does not chain,
+        // alignment 6 has the wrong length and contig 1 has too few edges.
+        assert_eq!(line_numbers, [4, 5, 5, 6, 1]);
+    }
+}
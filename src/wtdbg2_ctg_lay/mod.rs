@@ -1,4 +1,5 @@
-use std::cmp::Ordering;
+use crate::error::Error;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -23,6 +24,8 @@ pub enum Wtdbg2CtgLayLine {
         to_node: String,
         /// True for forwards (+), false for backwards (-).
         to_direction: bool,
+        /// The offset given in the input, which may have been rescaled in `offset`.
+        original_offset: u64,
     },
 
     Alignment {
@@ -31,29 +34,34 @@ pub enum Wtdbg2CtgLayLine {
         direction: bool,
         offset: usize,
         length: usize,
+        /// The offset given in the input, which may have been decompressed in `offset`.
+        original_offset: usize,
         original_length: usize,
+        /// The direction given in the input, which may have been corrected in `direction`.
+        original_direction: bool,
+        /// The homopolymer compressed segment of the read embedded in the input line,
+        /// if the line has one and it was kept by the parser.
+        compressed_sequence: Option<Vec<u8>>,
+        /// The length of the whole read in the coordinates of `offset`, known after decompression.
+        read_length: usize,
     },
 }
 
-impl FromStr for Wtdbg2CtgLayLine {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Wtdbg2CtgLayLine {
+    /// Parse a line, copying the compressed segment embedded in an alignment line only if it is kept.
+    pub fn parse(s: &str, keep_compressed_sequence: bool) -> Result<Self, Error> {
         match s.chars().next() {
             Some('>') => {
                 let mut columns = s[1..].split(' ');
-                let name = columns.next().ok_or(()).unwrap().to_owned();
-                let node_count = columns
-                    .next()
-                    .ok_or(())
-                    .unwrap_or_else(|_| panic!("Parse error: {s}"))[6..]
-                    .parse()
-                    .map_err(|_| ())
-                    .unwrap();
-                let length = columns.next().ok_or(()).unwrap()[4..]
-                    .parse()
-                    .map_err(|_| ())
-                    .unwrap();
+                let name = next_column(&mut columns, s)?.to_owned();
+                let node_count = parse_column(
+                    strip_column_prefix(next_column(&mut columns, s)?, "nodes=", s)?,
+                    s,
+                )?;
+                let length = parse_column(
+                    strip_column_prefix(next_column(&mut columns, s)?, "len=", s)?,
+                    s,
+                )?;
                 Ok(Self::Contig {
                     name,
                     node_count,
@@ -62,118 +70,107 @@ impl FromStr for Wtdbg2CtgLayLine {
             }
             Some('E') => {
                 let mut columns = s[1..].split('\t');
-                columns.next().ok_or(()).unwrap();
-                let offset = columns
-                    .next()
-                    .ok_or(())
-                    .unwrap()
-                    .parse()
-                    .map_err(|_| ())
-                    .unwrap();
-                let from_node = columns.next().ok_or(()).unwrap().to_owned();
-                let from_direction = match columns.next().ok_or(()).unwrap() {
-                    "+" => true,
-                    "-" => false,
-                    _ => panic!("Parse error: {s}"),
-                };
-                let to_node = columns.next().ok_or(()).unwrap().to_owned();
-                let to_direction = match columns.next().ok_or(()).unwrap() {
-                    "+" => true,
-                    "-" => false,
-                    _ => panic!("Parse error: {s}"),
-                };
+                next_column(&mut columns, s)?;
+                let offset = parse_column(next_column(&mut columns, s)?, s)?;
+                let from_node = next_column(&mut columns, s)?.to_owned();
+                let from_direction = parse_direction(next_column(&mut columns, s)?, s)?;
+                let to_node = next_column(&mut columns, s)?.to_owned();
+                let to_direction = parse_direction(next_column(&mut columns, s)?, s)?;
                 Ok(Self::Edge {
                     offset,
                     from_node,
                     from_direction,
                     to_node,
                     to_direction,
+                    original_offset: offset,
                 })
             }
             Some('S') => {
                 let mut columns = s[1..].split('\t');
-                columns.next().ok_or(()).unwrap();
-                let read_id = columns.next().ok_or(()).unwrap().as_bytes().to_owned();
-                let direction = match columns.next().ok_or(()).unwrap() {
-                    "+" => true,
-                    "-" => false,
-                    _ => panic!("Parse error: {s}"),
-                };
-                let offset = columns
+                next_column(&mut columns, s)?;
+                let read_id = next_column(&mut columns, s)?.as_bytes().to_owned();
+                let direction = parse_direction(next_column(&mut columns, s)?, s)?;
+                let offset = parse_column(next_column(&mut columns, s)?, s)?;
+                let length = parse_column(next_column(&mut columns, s)?, s)?;
+                let compressed_sequence = columns
                     .next()
-                    .ok_or(())
-                    .unwrap()
-                    .parse()
-                    .map_err(|_| ())
-                    .unwrap();
-                let length = columns
-                    .next()
-                    .ok_or(())
-                    .unwrap()
-                    .parse()
-                    .map_err(|_| ())
-                    .unwrap();
+                    .filter(|column| keep_compressed_sequence && !column.is_empty())
+                    .map(|column| column.as_bytes().to_owned());
                 Ok(Self::Alignment {
                     read_id,
                     direction,
                     offset,
                     length,
+                    original_offset: offset,
                     original_length: length,
+                    original_direction: direction,
+                    compressed_sequence,
+                    read_length: 0,
                 })
             }
-            _ => panic!("Parse error: {s}"),
+            _ => Err(parse_error(s)),
         }
     }
 }
 
-impl PartialOrd for LineContext {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl FromStr for Wtdbg2CtgLayLine {
+    type Err = Error;
+
+    /// Parse a line, including the compressed segment embedded in an alignment line.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s, true)
     }
 }
 
-impl Ord for LineContext {
-    fn cmp(&self, other: &Self) -> Ordering {
-        match self.contig_index.cmp(&other.contig_index) {
-            Ordering::Equal => {
-                assert_eq!(
-                    self.previous_contig_edge_count,
-                    other.previous_contig_edge_count
-                );
-                match self.edge_index.cmp(&other.edge_index) {
-                    Ordering::Equal => {
-                        assert_eq!(
-                            self.previous_edge_alignment_count,
-                            other.previous_edge_alignment_count
-                        );
-                        self.alignment_index.cmp(&other.alignment_index)
-                    }
-                    ordering => ordering,
-                }
-            }
-            ordering => ordering,
-        }
+fn parse_error(line: &str) -> Error {
+    Error::Input(format!("Could not parse line: {line}"))
+}
+
+fn next_column<'a, Columns: Iterator<Item = &'a str>>(
+    columns: &mut Columns,
+    line: &str,
+) -> Result<&'a str, Error> {
+    columns.next().ok_or_else(|| parse_error(line))
+}
+
+fn strip_column_prefix<'a>(column: &'a str, prefix: &str, line: &str) -> Result<&'a str, Error> {
+    column.strip_prefix(prefix).ok_or_else(|| parse_error(line))
+}
+
+fn parse_column<T: FromStr>(column: &str, line: &str) -> Result<T, Error> {
+    column.parse().map_err(|_| parse_error(line))
+}
+
+fn parse_direction(column: &str, line: &str) -> Result<bool, Error> {
+    match column {
+        "+" => Ok(true),
+        "-" => Ok(false),
+        _ => Err(parse_error(line)),
     }
 }
 
-impl ToString for Wtdbg2CtgLayLine {
-    fn to_string(&self) -> String {
+impl Display for Wtdbg2CtgLayLine {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             Wtdbg2CtgLayLine::Contig {
                 name,
                 node_count,
                 length,
-            } => format!(">{name} nodes={node_count} len={length}"),
+            } => write!(f, ">{name} nodes={node_count} len={length}"),
             Wtdbg2CtgLayLine::Edge {
                 offset,
                 from_node,
                 from_direction,
                 to_node,
                 to_direction,
+                ..
             } => {
                 let from_direction = if *from_direction { "+" } else { "-" };
                 let to_direction = if *to_direction { "+" } else { "-" };
-                format!("E\t{offset}\t{from_node}\t{from_direction}\t{to_node}\t{to_direction}")
+                write!(
+                    f,
+                    "E\t{offset}\t{from_node}\t{from_direction}\t{to_node}\t{to_direction}"
+                )
             }
             Wtdbg2CtgLayLine::Alignment {
                 read_id,
@@ -182,58 +179,26 @@ impl ToString for Wtdbg2CtgLayLine {
                 length,
                 ..
             } => {
-                let read_id = String::from_utf8(read_id.clone()).unwrap();
+                let read_id = String::from_utf8_lossy(read_id);
                 let direction = if *direction { "+" } else { "-" };
-                format!("S\t{read_id}\t{direction}\t{offset}\t{length}\t")
+                write!(f, "S\t{read_id}\t{direction}\t{offset}\t{length}\t")
             }
         }
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
+/// The position of a line in the layout.
+/// The indices of edges and alignments are relative to their contig and edge respectively.
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
 pub struct LineContext {
-    pub contig_index: i64,
-    pub edge_index: i64,
-    pub alignment_index: i64,
-    pub previous_contig_edge_count: i64,
-    pub previous_edge_alignment_count: i64,
-}
-
-impl LineContext {
-    pub fn directly_precedes(&self, other: &Self) -> bool {
-        if self.contig_index != other.contig_index {
-            if self.contig_index != other.contig_index - 1 {
-                return false;
-            }
-
-            self.edge_index == other.previous_contig_edge_count - 1
-                && self.alignment_index == other.previous_edge_alignment_count - 1
-        } else if self.edge_index != other.edge_index {
-            if self.edge_index != other.edge_index - 1 {
-                return false;
-            }
-
-            self.alignment_index == other.previous_edge_alignment_count - 1 || self.edge_index == -1
-        } else if self.alignment_index != other.alignment_index {
-            if self.alignment_index != other.alignment_index - 1 {
-                return false;
-            }
-
-            true
-        } else {
-            false
-        }
-    }
-}
-
-impl Default for LineContext {
-    fn default() -> Self {
-        Self {
-            contig_index: -1,
-            edge_index: -1,
-            alignment_index: -1,
-            previous_contig_edge_count: 0,
-            previous_edge_alignment_count: 0,
-        }
-    }
+    /// The position of the line among all lines forwarded by the parser.
+    pub sequence_number: u64,
+    pub contig_index: u64,
+    pub edge_index: u64,
+    pub alignment_index: u64,
+    /// The byte offset of the line in the input file.
+    pub input_offset: u64,
+    /// The length of the line in the input file, which stays charged to the memory budget
+    /// until the line leaves the pipeline.
+    pub input_length: usize,
 }